@@ -34,8 +34,10 @@
 #! gap> LoadPackage("vole", false);;
 #! gap> Set(RecNames(VoleRefiner));
 #! [ "DigraphStab", "DigraphTransporter", "FromConstraint", "InSymmetricGroup", 
-#!   "SetSetStab", "SetSetTransporter", "SetStab", "SetTransporter", 
-#!   "SetTupleStab", "SetTupleTransporter", "TupleStab", "TupleTransporter" ]
+#!   "MatrixStab", "MatrixTransporter", "MultisetStab", "MultisetTransporter", 
+#!   "SetMultisetStab", "SetMultisetTransporter", "SetSetStab", 
+#!   "SetSetTransporter", "SetStab", "SetTransporter", "SetTupleStab", 
+#!   "SetTupleTransporter", "TupleStab", "TupleTransporter" ]
 #! @EndExampleSession
 DeclareGlobalVariable("VoleRefiner");
 # TODO When we require GAP >= 4.12, use GlobalName rather than GlobalVariable
@@ -124,6 +126,114 @@ DeclareGlobalFunction("VoleRefiner.SetTupleStab");
 #! @EndExampleSession
 DeclareGlobalFunction("VoleRefiner.SetTupleTransporter");
 
+#! @BeginGroup MultisetActions
+#! @Arguments m, p
+#! @Returns A sorted list
+#! @Description
+#! <C>OnMultisets</C> returns the image of the multiset <A>m</A>, given as a
+#! list of positive integers in which each point occurs as many times as its
+#! multiplicity, under the permutation <A>p</A>. The image is a sorted list.
+#! <C>OnSetsMultisets</C> acts on a set of multisets, each of which is a
+#! sorted list, and returns the set of their images under <C>OnMultisets</C>.
+DeclareGlobalFunction("OnMultisets");
+#! @EndGroup
+#! @Arguments s, p
+#! @Group MultisetActions
+#! @BeginExampleSession
+#! gap> OnMultisets([3, 1, 3, 2], (1,2,3));
+#! [ 1, 1, 2, 3 ]
+#! gap> OnSetsMultisets([[1, 1, 2], [2, 3]], (1,2));
+#! [ [ 1, 2, 2 ], [ 1, 3 ] ]
+#! @EndExampleSession
+DeclareGlobalFunction("OnSetsMultisets");
+
+
+#! @BeginGroup Multiset
+#! @Arguments s
+#! @Returns A &Vole; refiner
+#! @Description
+#! A multiset is given as a list of positive integers, in which each point
+#! occurs as many times as its multiplicity. Permutations act on multisets,
+#! stored as sorted lists, by <Ref Func="OnMultisets"/>.
+DeclareGlobalFunction("VoleRefiner.MultisetStab");
+#! @EndGroup
+#! @Arguments s, t
+#! @Group Multiset
+#! @BeginExampleSession
+#! gap> VoleFind.Group(5, VoleRefiner.MultisetStab([1, 1, 2, 2, 3]))
+#! > = Group([(1,2), (4,5)]);
+#! true
+#! gap> x := VoleFind.Rep(VoleRefiner.MultisetTransporter([1, 1, 2], [2, 3, 3]));;
+#! gap> OnMultisets([1, 1, 2], x);
+#! [ 2, 3, 3 ]
+#! @EndExampleSession
+DeclareGlobalFunction("VoleRefiner.MultisetTransporter");
+
+
+#! @BeginGroup SetMultiset
+#! @Arguments s
+#! @Returns A &Vole; refiner
+#! @Description
+#! Refiners for a set of multisets, each given as in
+#! <Ref Func="VoleRefiner.MultisetStab"/>. Permutations act on sets of
+#! multisets by <Ref Func="OnSetsMultisets"/>.
+DeclareGlobalFunction("VoleRefiner.SetMultisetStab");
+#! @EndGroup
+#! @Arguments s, t
+#! @Group SetMultiset
+#! @BeginExampleSession
+#! gap> VoleFind.Group(3, VoleRefiner.SetMultisetStab([[1, 1, 2], [2, 2, 1]]))
+#! > = Group([(1,2)]);
+#! true
+#! @EndExampleSession
+DeclareGlobalFunction("VoleRefiner.SetMultisetTransporter");
+
+#! @Arguments m, p
+#! @Returns A matrix
+#! @Description
+#! The rows of an <M>r \times c</M> matrix <A>m</A>, given as a list of rows,
+#! are the points <C>[1 .. r]</C>, and its columns are the points
+#! <C>[r + 1 .. r + c]</C>. <C>OnMatrixRowsColumns</C> returns the image of
+#! <A>m</A> under the permutation <A>p</A>, which must map rows to rows and
+#! columns to columns: entry <C>[i, j]</C> of <A>m</A> is moved to
+#! <C>[i ^ p, (r + j) ^ p - r]</C>.
+#! @BeginExampleSession
+#! gap> OnMatrixRowsColumns([[1, 2, 3], [4, 5, 6]], (1,2)(3,4));
+#! [ [ 5, 4, 6 ], [ 2, 1, 3 ] ]
+#! @EndExampleSession
+DeclareGlobalFunction("OnMatrixRowsColumns");
+
+
+#! @BeginGroup Matrix
+#! @Arguments s
+#! @Returns A &Vole; refiner
+#! @Description
+#! Refiners for permuting the rows and columns of a matrix independently.
+#! Permutations act on matrices by <Ref Func="OnMatrixRowsColumns"/>.
+#! The entries of the matrices may be integers, booleans, strings, or lists
+#! of these. Matrices given to a transporter must have the same dimensions.
+#! <P/>
+#! When a search includes a matrix refiner, the record returned with the
+#! <C>raw</C> option has a component <C>matrix_sols</C>. This contains, for
+#! each matrix refiner, a record with components <C>constraint</C>, the
+#! position of the refiner, <C>sols</C>, a list containing the pair
+#! <C>[rows, columns]</C> of the row and column permutations of each
+#! solution, and, when searching for a canonical image, <C>canonical</C>.
+DeclareGlobalFunction("VoleRefiner.MatrixStab");
+#! @EndGroup
+#! @Arguments s, t
+#! @Group Matrix
+#! @BeginExampleSession
+#! gap> VoleFind.Group(VoleRefiner.MatrixStab([[1, 1], [2, 2]]))
+#! > = Group([(3,4)]);
+#! true
+#! gap> r := VoleFind.Rep(VoleRefiner.MatrixTransporter([[1, 2], [3, 4]],
+#! >                                                    [[4, 3], [2, 1]]) : raw);;
+#! gap> r.matrix_sols[1].sols;
+#! [ [ (1,2), (1,2) ] ]
+#! @EndExampleSession
+DeclareGlobalFunction("VoleRefiner.MatrixTransporter");
+
 #! @BeginGroup Digraph
 #! @Arguments s
 #! @Returns A &Vole; refiner
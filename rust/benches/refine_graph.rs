@@ -0,0 +1,55 @@
+//! Benchmark refining a partition by a large sparse graph, which is the hottest code in search
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde_json::json;
+use vole::vole::{
+    domain_state::DomainState,
+    parse_input::{self, Constraint},
+    refiners::{Refiner, Side},
+    trace::Tracer,
+};
+
+/// The refiner for the stabilizer of a random digraph on `n` vertices, where each vertex
+/// has between 0 and 5 out-neighbours
+fn random_digraph_refiner(n: usize) -> Box<dyn Refiner> {
+    let mut rng = ChaCha8Rng::seed_from_u64(1);
+    // Constraints are 1-indexed
+    let edges: Vec<Vec<usize>> = (0..n)
+        .map(|_| (0..rng.gen_range(0..6)).map(|_| rng.gen_range(1..=n)).collect())
+        .collect();
+    let constraint: Constraint = serde_json::from_value(json!({ "DigraphStab": { "edges": edges } })).unwrap();
+    parse_input::build_constraints(&[constraint]).pop().unwrap()
+}
+
+fn refine_graph(c: &mut Criterion) {
+    let n = 100_000;
+    let mut refiner = random_digraph_refiner(n);
+
+    c.bench_function("refine random digraph", |b| {
+        b.iter_batched(
+            || DomainState::new(n, Tracer::new()),
+            |mut domain| {
+                refiner.refine_begin(&mut domain, Side::Left).unwrap();
+                domain.refine_graphs().unwrap();
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    c.bench_function("refine random digraph with a fixed vertex", |b| {
+        b.iter_batched(
+            || DomainState::new(n, Tracer::new()),
+            |mut domain| {
+                refiner.refine_begin(&mut domain, Side::Left).unwrap();
+                domain.refine_partition_cell_by(0, |&x| x == 0).unwrap();
+                domain.refine_graphs().unwrap();
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, refine_graph);
+criterion_main!(benches);
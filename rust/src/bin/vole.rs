@@ -1,24 +1,183 @@
 use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use cpu_time::ProcessTime;
+use serde::Serialize;
+use vole::perm::Permutation;
+use vole::vole::canonical::{
+    canonical_digraph_labelling, canonical_labelled_edges, digraph_refiners, CanonicalSearcher, Classifier,
+};
+use vole::vole::parse_input::BatchInput;
+use vole::vole::refiners::Refiner;
+use vole::vole::search::SearchConfig;
+use vole::vole::search::{simple_coset_search, simple_group_search};
 use vole::vole::trace;
-use vole::vole::{domain_state::DomainState, trace::TracingType};
+use vole::vole::trace_dump::{diff_traces, TraceDivergence, TraceDump};
+use vole::vole::{
+    domain_state::DomainState,
+    trace::{TraceStorage, TracingType},
+};
 use vole::vole::{parse_input, state::State};
 use vole::vole::{
-    refiners::refiner_store::RefinerStore,
-    search::{simple_coset_search, simple_group_search},
+    search::certificate::{build_certificate, CertifiedProblem},
+    search::root_search,
+    solutions::Solutions,
 };
-use vole::vole::{search::root_search, solutions::Solutions};
 
 use tracing::Level;
 
 use tracing_subscriber::fmt::format::FmtSpan;
-use vole::gap_chat::{GapChatType, GAP_CHAT};
+use vole::gap_chat::{Command, DigraphFormat, GapChatType, GAP_CHAT};
 
 use std::panic;
 
+/// The result of `vole canon` for one digraph
+#[derive(Serialize)]
+struct CanonicalOutput {
+    /// The (1-indexed) position of each vertex in the canonical digraph
+    labelling: Vec<usize>,
+    /// The canonical digraph, in the same format as the input
+    digraph: serde_json::Value,
+}
+
+/// The result of `vole trace-diff` for one of the traces
+#[derive(Serialize)]
+struct TraceDiffOutput {
+    /// Which trace was compared
+    trace: &'static str,
+    /// The first difference, or `None` if the traces are identical
+    divergence: Option<TraceDivergence>,
+}
+
+/// Find the canonical labelling and canonical form of the digraph `input`, stored in `format`
+fn canonical_digraph_output(
+    searcher: &mut CanonicalSearcher,
+    input: &str,
+    format: DigraphFormat,
+) -> anyhow::Result<CanonicalOutput> {
+    let one_indexed = |labelling: &Permutation, n: usize| (0..n).map(|v| labelling.apply(v) + 1).collect();
+    if format == DigraphFormat::Json {
+        let edges = parse_input::read_json_digraph(input)?;
+        let (labelling, canonical) = canonical_labelled_edges(searcher, &edges)?;
+        return Ok(CanonicalOutput {
+            labelling: one_indexed(&labelling, edges.len()),
+            digraph: serde_json::to_value(parse_input::digraph_edges(&canonical))?,
+        });
+    }
+
+    let digraph = format.read(input)?;
+    let labelling = canonical_digraph_labelling(searcher, Arc::new(digraph.digraph.clone()), &digraph.colours)?;
+    Ok(CanonicalOutput {
+        labelling: one_indexed(&labelling, digraph.digraph.vertices()),
+        digraph: serde_json::Value::String(format.write(&digraph.permute(&labelling))?),
+    })
+}
+
+/// The result of `vole classify` for one structure
+#[derive(Serialize)]
+struct ClassifiedOutput {
+    class: usize,
+    representative: usize,
+    /// An isomorphism to the representative, as the (1-indexed) image of each point
+    isomorphism: Vec<usize>,
+}
+
+/// The number of points, and the refiners, for one structure to classify
+fn classify_refiners(input: &str, format: Option<DigraphFormat>) -> anyhow::Result<(usize, Vec<Box<dyn Refiner>>)> {
+    match format {
+        Some(format) => {
+            if !format.is_line_based() {
+                bail!("Only formats with one digraph per line can be classified");
+            }
+            let digraph = format.read(input)?;
+            let points = digraph.digraph.vertices();
+            Ok((points, digraph_refiners(Arc::new(digraph.digraph), &digraph.colours)))
+        }
+        None => {
+            let structure = serde_json::from_str::<BatchInput>(input)?.into_structure()?;
+            Ok((structure.points, structure.build_refiners()?))
+        }
+    }
+}
+
+/// Run a command which does not talk to GAP
+fn run_command(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::TraceDiff { left, right } => {
+            let read =
+                |path| -> anyhow::Result<TraceDump> { Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?) };
+            let (left, right) = (read(&left)?, read(&right)?);
+
+            let symmetry = diff_traces(&left, &right, |d| &d.symmetry);
+            let canonical = diff_traces(&left, &right, |d| &d.canonical);
+            for (trace, divergence) in [("symmetry", symmetry), ("canonical", canonical)] {
+                let output = TraceDiffOutput { trace, divergence };
+                println!("{}", serde_json::to_string(&output)?);
+            }
+        }
+        Command::Canon { input, format } => {
+            let mut input: Box<dyn BufRead> = match input {
+                Some(path) => Box::new(BufReader::new(File::open(path)?)),
+                None => Box::new(BufReader::new(std::io::stdin())),
+            };
+            let mut searcher = CanonicalSearcher::new(&SearchConfig::default());
+            if !format.is_line_based() {
+                let mut graph = String::new();
+                input.read_to_string(&mut graph)?;
+                let output = canonical_digraph_output(&mut searcher, &graph, format)?;
+                println!("{}", serde_json::to_string(&output)?);
+                return Ok(());
+            }
+            for line in input.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let output = canonical_digraph_output(&mut searcher, line.trim(), format)?;
+                println!("{}", serde_json::to_string(&output)?);
+            }
+        }
+        Command::Classify { input, format } => {
+            let mut input: Box<dyn BufRead> = match input {
+                Some(path) => Box::new(BufReader::new(File::open(path)?)),
+                None => Box::new(BufReader::new(std::io::stdin())),
+            };
+            let mut classifier = Classifier::new(&SearchConfig::default());
+            let mut line = String::new();
+            let mut line_number = 0;
+            loop {
+                line.clear();
+                if input.read_line(&mut line)? == 0 {
+                    break;
+                }
+                line_number += 1;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (points, refiners) = classify_refiners(line.trim(), format)
+                    .with_context(|| format!("Invalid structure on line {}", line_number))?;
+                let classified = classifier
+                    .add(points, refiners)
+                    .with_context(|| format!("Could not classify the structure on line {}", line_number))?;
+                let output = ClassifiedOutput {
+                    class: classified.class,
+                    representative: classified.representative,
+                    isomorphism: (0..points).map(|v| classified.isomorphism.apply(v) + 1).collect(),
+                };
+                println!("{}", serde_json::to_string(&output)?);
+            }
+        }
+    }
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
+    if let Some(command) = Command::from_command_line() {
+        return run_command(command);
+    }
+
     // Set up debugging output
 
     let (non_block, _guard) = tracing_appender::non_blocking(File::create("vole.trace")?);
@@ -35,21 +194,49 @@ fn main() -> anyhow::Result<()> {
             .init();
     }
 
+    // Check a certificate, without talking to GAP
+    if let Some(path) = &vole::gap_chat::OPTIONS.check_certificate {
+        let certified: CertifiedProblem = serde_json::from_reader(std::io::BufReader::new(File::open(path)?))?;
+        match certified.check() {
+            Ok(()) => {
+                println!("Certificate is valid");
+                return Ok(());
+            }
+            Err(e) => bail!("Certificate is invalid: {:?}", e),
+        }
+    }
+
     // Hide panic messages, if we are not tracing
     if vole::gap_chat::OPTIONS.quiet {
         panic::set_hook(Box::new(|_| {}));
     }
 
     let result = panic::catch_unwind(|| -> Result<(), anyhow::Error> {
-        let problem = parse_input::read_problem(&mut GAP_CHAT.lock().unwrap().in_file.as_mut().unwrap())?;
+        let mut problem = parse_input::read_problem(&mut GAP_CHAT.lock().unwrap().in_file.as_mut().unwrap())?;
 
-        let refiners = RefinerStore::new_from_refiners(parse_input::build_constraints(&problem.constraints));
+        if vole::gap_chat::OPTIONS.check_refiners {
+            problem.config.search_config.check_refiners = true;
+        }
+
+        let refiners = parse_input::build_refiner_store(&problem)?;
+
+        let storage = problem.config.search_config.trace_storage;
+        if storage == TraceStorage::Hashed
+            && (problem.config.find_canonical
+                || vole::gap_chat::OPTIONS.trace_dump.is_some()
+                || vole::gap_chat::OPTIONS.certificate.is_some())
+        {
+            bail!("Hashed traces cannot be used for canonical images, trace dumps or certificates");
+        }
 
-        let tracer = if problem.config.find_canonical {
+        let mut tracer = if problem.config.find_canonical {
             trace::Tracer::new()
         } else {
-            trace::Tracer::new_with_type(TracingType::SYMMETRY)
+            trace::Tracer::new_with_storage(TracingType::SYMMETRY, storage)
         };
+        if vole::gap_chat::OPTIONS.trace_dump.is_some() {
+            tracer.start_recording();
+        }
 
         let domain = DomainState::new(problem.config.points, tracer);
         let mut solutions = Solutions::new(problem.config.points);
@@ -72,6 +259,22 @@ fn main() -> anyhow::Result<()> {
             simple_group_search(&mut state, &mut solutions, &problem.config.search_config);
         }
 
+        if let Some(path) = &vole::gap_chat::OPTIONS.trace_dump {
+            let dump = state.domain.tracer().dump(state.refiners.names());
+            serde_json::to_writer(File::create(path)?, &dump)?;
+        }
+
+        let certificate = match &vole::gap_chat::OPTIONS.certificate {
+            Some(path) => {
+                let refiners = parse_input::build_refiner_store(&problem)?;
+                match build_certificate(refiners, problem.config.points, &solutions) {
+                    Ok(certificate) => Some((path, certificate)),
+                    Err(e) => bail!("Could not build certificate: {:?}", e),
+                }
+            }
+            None => None,
+        };
+
         if let Ok(time) = ProcessTime::try_now() {
             state.stats.vole_time = time.as_duration().as_millis();
         }
@@ -83,8 +286,13 @@ fn main() -> anyhow::Result<()> {
             },
             state.domain.rbase_branch_vals(),
             state.stats,
+            parse_input::matrix_solutions(&problem.constraints, &solutions),
         )?;
 
+        if let Some((path, certificate)) = certificate {
+            serde_json::to_writer(File::create(path)?, &CertifiedProblem { problem, certificate })?;
+        }
+
         Ok(())
     });
 
@@ -3,7 +3,7 @@
 //! This crate implements edge-coloured graphs.
 
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, num::Wrapping, slice, sync::Arc};
+use std::{num::Wrapping, slice, sync::Arc};
 
 use crate::{
     perm::Permutation,
@@ -14,26 +14,25 @@ use tracing::trace;
 
 use super::hash::{QHash, QuickHashable};
 
-/// The neighbours of a vertex in a directed graph.
-/// The keys are the neighbours, the image of the keys the "colour" of the edge
+/// The neighbours of a vertex in a directed graph, sorted by neighbour.
+/// Each entry is a neighbour, and the "colour" of the edge to that neighbour.
 /// Directed graphs have edges in both directions, but with different colours.
-type BTNeighbours = std::collections::BTreeMap<usize, Wrapping<QHash>>;
-type VNeighbours = Vec<(usize, Wrapping<QHash>)>;
-type Neighbours = BTNeighbours;
-// type Neighbours = indexmap::map::IndexMap<usize, Wrapping<QHash>>;
+pub type Neighbours = [(usize, Wrapping<QHash>)];
 
 /// A directed graph
 #[derive(Clone, Debug, Eq, Deserialize, Serialize)]
 pub struct Digraph {
-    /// The neighbours of each vertex, stored as a list
-    edges: Vec<Neighbours>,
+    /// The neighbours of vertex `i` are `neighbours[offsets[i]..offsets[i+1]]`
+    offsets: Vec<usize>,
+    /// The neighbours of every vertex, stored as one list
+    neighbours: Vec<(usize, Wrapping<QHash>)>,
 }
 
 impl std::hash::Hash for Digraph {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        for (i, row) in self.edges.iter().enumerate() {
+        for i in 0..self.vertices() {
             i.hash(state);
-            for r in row {
+            for r in self.neighbours(i) {
                 r.hash(state);
             }
         }
@@ -42,26 +41,18 @@ impl std::hash::Hash for Digraph {
 
 impl PartialEq<Self> for Digraph {
     fn eq(&self, other: &Self) -> bool {
-        // Check edges are sorted and unique
-        assert!(self
-            .edges
-            .iter()
-            .all(|e| e.iter().tuple_windows().all(|((a, _), (b, _))| a < b)));
-        self.edges == other.edges
+        debug_assert!(self.is_sorted());
+        self.offsets == other.offsets && self.neighbours == other.neighbours
     }
 }
 
 impl Ord for Digraph {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // Check edges are sorted and unique
-        assert!(self
-            .edges
-            .iter()
-            .all(|e| e.iter().tuple_windows().all(|((a, _), (b, _))| a < b)));
-        assert!(self.edges.len() == other.edges.len());
-
-        for (left, right) in self.edges.iter().zip(other.edges.iter()) {
-            let c = left.iter().cmp(right.iter());
+        debug_assert!(self.is_sorted());
+        assert!(self.vertices() == other.vertices());
+
+        for i in 0..self.vertices() {
+            let c = self.neighbours(i).cmp(other.neighbours(i));
             if c != std::cmp::Ordering::Equal {
                 return c;
             }
@@ -78,43 +69,158 @@ impl PartialOrd<Self> for Digraph {
 
 pub type RawDigraph = Vec<Vec<(usize, Wrapping<QHash>)>>;
 
+/// Builds a [Digraph] one edge at a time. Edges can be added in any order,
+/// and repeated edges between the same pair of vertices have their colours added together.
+#[derive(Clone, Debug, Default)]
+pub struct DigraphBuilder {
+    vertices: usize,
+    edges: Vec<(usize, usize, Wrapping<QHash>)>,
+}
+
+impl DigraphBuilder {
+    /// Start building a digraph on `n` vertices
+    pub fn new(n: usize) -> Self {
+        Self {
+            vertices: n,
+            edges: vec![],
+        }
+    }
+
+    /// Start building a digraph on `n` vertices, with space for `edges` coloured edges
+    pub fn with_capacity(n: usize, edges: usize) -> Self {
+        Self {
+            vertices: n,
+            edges: Vec::with_capacity(edges),
+        }
+    }
+
+    /// Number of vertices
+    pub fn vertices(&self) -> usize {
+        self.vertices
+    }
+
+    /// Increase the number of vertices to at least `n`
+    pub fn ensure_vertices(&mut self, n: usize) {
+        self.vertices = self.vertices.max(n);
+    }
+
+    /// Add `colour` to the edge from `source` to `target`, without adding the reverse edge
+    pub fn add_coloured_edge(&mut self, source: usize, target: usize, colour: Wrapping<QHash>) {
+        assert!(source < self.vertices && target < self.vertices);
+        self.edges.push((source, target, colour));
+    }
+
+    /// Add an edge from `source` to `target` with label `label`. This is the same as
+    /// an edge created by [Digraph::from_labelled_vec].
+    pub fn add_labelled_edge(&mut self, source: usize, target: usize, label: usize) {
+        let (out_edge, in_edge) = Digraph::edge_colours(label);
+        self.add_coloured_edge(source, target, out_edge);
+        self.add_coloured_edge(target, source, in_edge);
+    }
+
+    /// Add an unlabelled edge from `source` to `target`. This is the same as
+    /// an edge created by [Digraph::from_vec].
+    pub fn add_edge(&mut self, source: usize, target: usize) {
+        self.add_labelled_edge(source, target, 0);
+    }
+
+    /// Build the digraph
+    pub fn build(self) -> Digraph {
+        let n = self.vertices;
+
+        // Bucket the edges by source
+        let mut starts = vec![0; n + 1];
+        for &(source, _, _) in &self.edges {
+            starts[source + 1] += 1;
+        }
+        for i in 0..n {
+            starts[i + 1] += starts[i];
+        }
+        let mut next = starts.clone();
+        let mut buckets = vec![(0, Wrapping(0)); self.edges.len()];
+        for (source, target, colour) in self.edges {
+            buckets[next[source]] = (target, colour);
+            next[source] += 1;
+        }
+
+        // Sort each vertex's neighbours, merging repeated edges
+        let mut offsets = Vec::with_capacity(n + 1);
+        let mut neighbours: Vec<(usize, Wrapping<QHash>)> = Vec::with_capacity(buckets.len());
+        offsets.push(0);
+        for i in 0..n {
+            let row = &mut buckets[starts[i]..starts[i + 1]];
+            row.sort_unstable_by_key(|&(target, _)| target);
+            let row_start = neighbours.len();
+            for &(target, colour) in row.iter() {
+                match neighbours[row_start..].last_mut() {
+                    Some(last) if last.0 == target => last.1 += colour,
+                    _ => neighbours.push((target, colour)),
+                }
+            }
+            offsets.push(neighbours.len());
+        }
+        neighbours.shrink_to_fit();
+
+        Digraph { offsets, neighbours }
+    }
+}
+
 impl Digraph {
+    /// Edge labels must be less than this, so every label gives different edge colours
+    pub const LABEL_LIMIT: usize = usize::MAX / 2;
+
     /// The empty digraph on n vertices
     pub fn empty(n: usize) -> Self {
         Self {
-            edges: vec![Neighbours::new(); n],
+            offsets: vec![0; n + 1],
+            neighbours: vec![],
         }
     }
 
-    /// Make a digraph from a vector of vector of neighbours
-    pub fn from_vec(in_edges: Vec<Vec<usize>>) -> Self {
-        let mut edges: Vec<BTNeighbours> = vec![BTNeighbours::new(); in_edges.len()];
+    /// The colours given to the out and in directions of an edge with label `label`,
+    /// which must be less than [Digraph::LABEL_LIMIT]. Unlabelled edges are treated as
+    /// having label 0.
+    fn edge_colours(label: usize) -> (Wrapping<QHash>, Wrapping<QHash>) {
+        assert!(label < Self::LABEL_LIMIT, "Edge label {} is too large", label);
+        let base = label * 2;
+        ((base + 1).quick_hash(), (base + 2).quick_hash())
+    }
 
-        let out_edge = 1usize.quick_hash();
-        let in_edge = 2usize.quick_hash();
+    /// Check the neighbours of each vertex are sorted and unique
+    fn is_sorted(&self) -> bool {
+        (0..self.vertices()).all(|i| self.neighbours(i).iter().tuple_windows().all(|(a, b)| a.0 < b.0))
+    }
 
+    /// Make a digraph from a vector of vector of neighbours
+    pub fn from_vec(in_edges: Vec<Vec<usize>>) -> Self {
+        let mut builder =
+            DigraphBuilder::with_capacity(in_edges.len(), 2 * in_edges.iter().map(Vec::len).sum::<usize>());
         for (i, item) in in_edges.iter().enumerate() {
             for &edge in item {
-                *edges[i].entry(edge).or_insert(Wrapping(0)) += out_edge;
-                *edges[edge].entry(i).or_insert(Wrapping(0)) += in_edge;
+                builder.add_edge(i, edge);
             }
         }
+        builder.build()
+    }
 
-        //let ret_edges : Vec<VNeighbours> = edges.into_iter().map(|n| n.into_iter().collect::<VNeighbours>()).collect();
-
-        /*for e in &mut edges {
-            e.sort_keys();
-        }*/
-
-        Self { edges }
+    /// Make a digraph from a vector of vector of (neighbour, label) pairs.
+    /// An edge with label 0 is the same as an edge created by [Digraph::from_vec].
+    /// Repeated edges are allowed, and are distinguished from a single edge,
+    /// so this can also represent multigraphs.
+    pub fn from_labelled_vec(in_edges: Vec<Vec<(usize, usize)>>) -> Self {
+        let mut builder =
+            DigraphBuilder::with_capacity(in_edges.len(), 2 * in_edges.iter().map(Vec::len).sum::<usize>());
+        for (i, item) in in_edges.iter().enumerate() {
+            for &(edge, label) in item {
+                builder.add_labelled_edge(i, edge, label);
+            }
+        }
+        builder.build()
     }
 
-    /// Transforms a digraph into a Vec<HashMap<>>
+    /// Transforms a digraph into a Vec<Vec<>>
     pub fn to_raw_unordered(&self) -> RawDigraph {
-        self.edges
-            .iter()
-            .map(|n| n.iter().map(|(&k, &v)| (k, v)).collect())
-            .collect()
+        (0..self.vertices()).map(|i| self.neighbours(i).to_vec()).collect()
     }
 
     /// [Digraph::from_vec], where the vertices are 1-indexed.
@@ -129,12 +235,52 @@ impl Digraph {
 
     /// Number of vertices
     pub fn vertices(&self) -> usize {
-        self.edges.len()
+        self.offsets.len() - 1
     }
 
     /// Neighbours of vertex `i`
     pub fn neighbours(&self, i: usize) -> &Neighbours {
-        &self.edges[i]
+        &self.neighbours[self.offsets[i]..self.offsets[i + 1]]
+    }
+
+    /// The arcs of a digraph made only of unlabelled edges (as created by [Digraph::from_vec]),
+    /// as sorted (source, target) pairs. Returns `None` if the digraph has labelled or repeated edges.
+    pub fn unlabelled_arcs(&self) -> Option<Vec<(usize, usize)>> {
+        let (out_edge, in_edge) = Self::edge_colours(0);
+        let mut arcs = vec![];
+        for source in 0..self.vertices() {
+            for &(target, colour) in self.neighbours(source) {
+                // A loop, or a pair of arcs in opposite directions, gives both colours
+                if colour == out_edge || colour == out_edge + in_edge {
+                    arcs.push((source, target));
+                } else if colour != in_edge || source == target {
+                    return None;
+                }
+            }
+        }
+        Some(arcs)
+    }
+
+    /// Compare `self ^ perm` with `other`, without building `self ^ perm`.
+    /// This stops at the first vertex whose neighbours are different.
+    pub fn cmp_permuted(&self, perm: &Permutation, other: &Self) -> std::cmp::Ordering {
+        assert!(self.vertices() == other.vertices());
+        let inv = perm.inv();
+        let mut row = vec![];
+        for v in 0..self.vertices() {
+            row.clear();
+            row.extend(
+                self.neighbours(inv.apply(v))
+                    .iter()
+                    .map(|&(target, colour)| (perm.apply(target), colour)),
+            );
+            row.sort_unstable_by_key(|&(target, _)| target);
+            let c = row[..].cmp(other.neighbours(v));
+            if c != std::cmp::Ordering::Equal {
+                return c;
+            }
+        }
+        std::cmp::Ordering::Equal
     }
 
     /// Merge a list of digraphs into this graph. This new graph
@@ -149,46 +295,41 @@ impl Digraph {
     /// whereas merging \[a,b\] at depth 0, then \[c,d,e\] at depth 0 will
     /// produce a different graph
     fn merge(&mut self, digraphs: &[Self], in_depth: usize) {
-        let mut resort = HashSet::new();
-        for (size, d) in digraphs.iter().enumerate() {
-            let depth = in_depth + size;
-            if d.edges.len() > self.edges.len() {
-                self.edges.resize(d.edges.len(), Neighbours::new());
-                for i in self.edges.len()..d.edges.len() {
-                    resort.insert(i);
-                }
+        let vertices = digraphs.iter().map(|d| d.vertices()).fold(self.vertices(), usize::max);
+        let edges = digraphs.iter().map(|d| d.neighbours.len()).sum::<usize>() + self.neighbours.len();
+        let mut builder = DigraphBuilder::with_capacity(vertices, edges);
+        for i in 0..self.vertices() {
+            for &(neighbour, colour) in self.neighbours(i) {
+                builder.add_coloured_edge(i, neighbour, colour);
             }
+        }
 
-            for i in 0..d.edges.len() {
-                for (&neighbour, &colour) in &d.edges[i] {
-                    *self.edges[i].entry(neighbour).or_insert_with(|| {
-                        resort.insert(i);
-                        Wrapping(0)
-                    }) += (colour, depth).quick_hash();
+        for (size, d) in digraphs.iter().enumerate() {
+            let depth = in_depth + size;
+            for i in 0..d.vertices() {
+                for &(neighbour, colour) in d.neighbours(i) {
+                    builder.add_coloured_edge(i, neighbour, (colour, depth).quick_hash());
                 }
             }
         }
 
-        /* for i in resort.into_iter() {
-            self.edges[i].sort_keys();
-        }*/
+        *self = builder.build();
     }
 
     /// Relabel the vertices of a graph, possibly introducing some new unused vertices
-    /// in the process. `map` should be as long as `self.vertices()`.
+    /// in the process. `map` should be injective, and as long as `self.vertices()`.
     pub fn remap_vertices(&mut self, map: &[usize]) {
-        assert!(map.len() == self.edges.len());
+        assert!(map.len() == self.vertices());
         let max_val = *map.iter().max().unwrap_or(&0);
-        let mut new_edges: Vec<Neighbours> = vec![Neighbours::new(); max_val + 1];
+        let mut builder = DigraphBuilder::with_capacity(max_val + 1, self.neighbours.len());
 
-        for (loc, e) in self.edges.iter().enumerate() {
-            let image = map[loc];
-            for (&vert, &label) in e {
-                new_edges[image].insert(map[vert], label);
+        for (loc, &image) in map.iter().enumerate() {
+            for &(vert, label) in self.neighbours(loc) {
+                builder.add_coloured_edge(image, map[vert], label);
             }
         }
 
-        self.edges = new_edges;
+        *self = builder.build();
     }
 }
 
@@ -197,16 +338,28 @@ impl std::ops::BitXor<&Permutation> for &Digraph {
     type Output = Digraph;
 
     fn bitxor(self, perm: &Permutation) -> Self::Output {
-        let mut edges: Vec<Neighbours> = vec![Neighbours::new(); self.edges.len()];
-        for i in 0..self.edges.len() {
-            let i_img = perm.apply(i);
-            for (&target, &colour) in &self.edges[i] {
-                edges[i_img].insert(perm.apply(target), colour);
+        let n = self.vertices();
+
+        // The image of vertex i has as many neighbours as i
+        let mut offsets = vec![0; n + 1];
+        for i in 0..n {
+            offsets[perm.apply(i) + 1] = self.neighbours(i).len();
+        }
+        for i in 0..n {
+            offsets[i + 1] += offsets[i];
+        }
+
+        let mut neighbours = vec![(0, Wrapping(0)); self.neighbours.len()];
+        for i in 0..n {
+            let start = offsets[perm.apply(i)];
+            let row = &mut neighbours[start..start + self.neighbours(i).len()];
+            for (slot, &(target, colour)) in row.iter_mut().zip(self.neighbours(i)) {
+                *slot = (perm.apply(target), colour);
             }
-            // edges[i_img].sort_keys();
+            row.sort_unstable_by_key(|&(target, _)| target);
         }
 
-        Digraph { edges }
+        Digraph { offsets, neighbours }
     }
 }
 
@@ -278,19 +431,17 @@ impl Backtrack for DigraphStack {
     }
 }
 
-#[allow(clippy::eq_op, clippy::neg_cmp_op_on_partial_ord)]
+#[allow(clippy::eq_op, clippy::neg_cmp_op_on_partial_ord, clippy::nonminimal_bool)]
 #[cfg(test)]
 mod tests {
-    use super::{Digraph, Neighbours};
+    use super::{Digraph, DigraphBuilder};
     use crate::perm::Permutation;
     #[test]
     fn id_perm() {
         let d = Digraph::empty(3);
         assert_eq!(d.vertices(), 3);
         for i in 0..d.vertices() {
-            let empty = Neighbours::new();
-            assert_eq!(*d.neighbours(i), empty);
-            assert_eq!(*d.neighbours(i), empty);
+            assert!(d.neighbours(i).is_empty());
         }
         assert_eq!(d, d);
         assert!(!(d < d));
@@ -337,6 +488,74 @@ mod tests {
         assert_eq!(d, dg);
     }
 
+    #[test]
+    fn labelled_graph() {
+        let d = Digraph::from_vec(vec![vec![1], vec![2], vec![0]]);
+        let l = Digraph::from_labelled_vec(vec![vec![(1, 0)], vec![(2, 0)], vec![(0, 0)]]);
+        assert_eq!(d, l);
+
+        let l = Digraph::from_labelled_vec(vec![vec![(1, 1)], vec![(2, 1)], vec![(0, 2)]]);
+        assert!(d != l);
+        let p = Permutation::from_vec(vec![1, 2, 0]);
+        let lp = (&l) ^ (&p);
+        assert!(l != lp);
+        let lpp = (&lp) ^ (&p);
+        assert!(l != lpp);
+        let lppp = (&lpp) ^ (&p);
+        assert_eq!(l, lppp);
+
+        let multi = Digraph::from_labelled_vec(vec![vec![(1, 0), (1, 0)], vec![]]);
+        let single = Digraph::from_labelled_vec(vec![vec![(1, 0)], vec![]]);
+        assert!(multi != single);
+
+        // The largest labels still give different colours
+        let max = Digraph::LABEL_LIMIT - 1;
+        let top = Digraph::from_labelled_vec(vec![vec![(1, max)], vec![]]);
+        let below = Digraph::from_labelled_vec(vec![vec![(1, max - 1)], vec![]]);
+        assert!(top != below);
+    }
+
+    #[test]
+    #[should_panic(expected = "too large")]
+    fn label_too_large() {
+        Digraph::from_labelled_vec(vec![vec![(1, Digraph::LABEL_LIMIT)], vec![]]);
+    }
+
+    #[test]
+    fn builder_graph() {
+        let mut b = DigraphBuilder::new(3);
+        b.add_labelled_edge(2, 0, 1);
+        b.add_edge(0, 1);
+        b.add_edge(1, 2);
+        b.add_edge(1, 2);
+        let d = b.build();
+        let l = Digraph::from_labelled_vec(vec![vec![(1, 0)], vec![(2, 0), (2, 0)], vec![(0, 1)]]);
+        assert_eq!(d, l);
+        assert_eq!(d.vertices(), 3);
+        assert_eq!(d.neighbours(1).iter().map(|&(v, _)| v).collect::<Vec<_>>(), vec![0, 2]);
+
+        let mut b = DigraphBuilder::new(2);
+        b.ensure_vertices(4);
+        b.add_edge(3, 0);
+        let d = b.build();
+        assert_eq!(d, Digraph::from_vec(vec![vec![], vec![], vec![], vec![0]]));
+    }
+
+    #[test]
+    fn cmp_permuted_graph() {
+        let d = Digraph::from_labelled_vec(vec![vec![(1, 0), (2, 1)], vec![(2, 0)], vec![], vec![(0, 2)]]);
+        let e = Digraph::from_vec(vec![vec![1], vec![3], vec![], vec![0, 2]]);
+        for p in [
+            Permutation::id(),
+            Permutation::from_vec(vec![1, 2, 0]),
+            Permutation::from_vec(vec![3, 2, 1, 0]),
+        ] {
+            for other in [&d, &e, &(&d ^ &p)] {
+                assert_eq!(d.cmp_permuted(&p, other), (&d ^ &p).cmp(other));
+            }
+        }
+    }
+
     #[test]
     fn more_graph() {
         let d = Digraph::from_vec(vec![vec![1, 2], vec![], vec![], vec![]]);
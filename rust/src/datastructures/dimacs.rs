@@ -0,0 +1,205 @@
+//! Reading and writing graphs in the DIMACS and edge list formats used by graph
+//! isomorphism benchmarks, such as those for bliss and saucy.
+//!
+//! DIMACS files have a problem line `p edge <vertices> <edges>`, then a line `e <u> <v>`
+//! for each edge, where vertices are 1-indexed. bliss extends this with lines
+//! `n <v> <colour>`, which give vertex `v` a colour (other vertices have colour 0).
+//! Lines starting with `c` are comments. Edge lists have a line `<u> <v>` for each edge,
+//! where vertices are 0-indexed, and lines starting with `#` or `%` are comments.
+//!
+//! Both formats store undirected graphs, which are read as digraphs with an arc in each
+//! direction for every edge (a loop is a single arc). Repeated edges are only added once.
+
+use std::fmt::Write;
+use std::io::BufRead;
+
+use anyhow::{bail, Context, Result};
+
+use super::digraph::{Digraph, DigraphBuilder};
+use super::graph6::undirected_arcs;
+use crate::perm::Permutation;
+
+/// A digraph, with a colour for each vertex
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColouredDigraph {
+    pub digraph: Digraph,
+    /// The colour of each vertex
+    pub colours: Vec<usize>,
+}
+
+impl ColouredDigraph {
+    /// A digraph where every vertex has colour 0
+    pub fn uncoloured(digraph: Digraph) -> Self {
+        let colours = vec![0; digraph.vertices()];
+        Self { digraph, colours }
+    }
+
+    /// Check if every vertex has the same colour
+    pub fn is_uncoloured(&self) -> bool {
+        self.colours.iter().all(|&c| c == self.colours[0])
+    }
+
+    /// Apply `p` to the digraph and the colours
+    pub fn permute(&self, p: &Permutation) -> Self {
+        let mut colours = vec![0; self.colours.len()];
+        for (v, &c) in self.colours.iter().enumerate() {
+            colours[p.apply(v)] = c;
+        }
+        Self {
+            digraph: &self.digraph ^ p,
+            colours,
+        }
+    }
+
+    /// Read a graph in (bliss's coloured) DIMACS format
+    pub fn from_dimacs<R: BufRead>(input: R) -> Result<Self> {
+        let mut vertices = None;
+        let mut edges = vec![];
+        let mut colours = vec![];
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+            let context = || format!("Invalid DIMACS line {}: '{}'", i + 1, line);
+            let vertex = |v: &str| -> Result<usize> {
+                let n = vertices.context("DIMACS problem line must come before vertices")?;
+                match v.parse::<usize>()? {
+                    v if (1..=n).contains(&v) => Ok(v - 1),
+                    v => bail!("Vertex {} is not in a graph with {} vertices", v, n),
+                }
+            };
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [] | ["c", ..] => {}
+                ["p", "edge" | "col", n, _] if vertices.is_none() => {
+                    let n: usize = n.parse().with_context(context)?;
+                    vertices = Some(n);
+                    colours = vec![0; n];
+                }
+                ["e", u, v] => edges.push((vertex(u).with_context(context)?, vertex(v).with_context(context)?)),
+                ["n", v, c] => colours[vertex(v).with_context(context)?] = c.parse().with_context(context)?,
+                _ => bail!(context()),
+            }
+        }
+        let vertices = vertices.context("DIMACS graph has no problem line")?;
+        Ok(Self {
+            digraph: build_undirected(vertices, edges),
+            colours,
+        })
+    }
+
+    /// Write an undirected graph in DIMACS format, with bliss's lines for vertex colours
+    pub fn to_dimacs(&self) -> Result<String> {
+        let edges = undirected_edges(&self.digraph, "DIMACS")?;
+        let mut out = format!("p edge {} {}\n", self.digraph.vertices(), edges.len());
+        for (v, &c) in self.colours.iter().enumerate().filter(|&(_, &c)| c != 0) {
+            writeln!(out, "n {} {}", v + 1, c)?;
+        }
+        for (u, v) in edges {
+            writeln!(out, "e {} {}", u + 1, v + 1)?;
+        }
+        Ok(out)
+    }
+
+    /// Read a graph as a list of edges. The number of vertices is one more
+    /// than the largest vertex, and every vertex has colour 0.
+    pub fn from_edge_list<R: BufRead>(input: R) -> Result<Self> {
+        let mut edges: Vec<(usize, usize)> = vec![];
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+            let context = || format!("Invalid edge list line {}: '{}'", i + 1, line);
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [] => {}
+                [first, ..] if first.starts_with('#') || first.starts_with('%') => {}
+                [u, v] => edges.push((u.parse().with_context(context)?, v.parse().with_context(context)?)),
+                _ => bail!(context()),
+            }
+        }
+        let vertices = edges.iter().map(|&(u, v)| u.max(v) + 1).max().unwrap_or(0);
+        Ok(Self::uncoloured(build_undirected(vertices, edges)))
+    }
+
+    /// Write an uncoloured, undirected graph as a list of edges
+    pub fn to_edge_list(&self) -> Result<String> {
+        if !self.is_uncoloured() {
+            bail!("Edge lists can not store vertex colours");
+        }
+        let mut out = String::new();
+        for (u, v) in undirected_edges(&self.digraph, "Edge lists")? {
+            writeln!(out, "{} {}", u, v)?;
+        }
+        Ok(out)
+    }
+}
+
+/// Build an undirected graph on `vertices` vertices, ignoring repeated edges
+fn build_undirected(vertices: usize, edges: Vec<(usize, usize)>) -> Digraph {
+    let mut edges: Vec<(usize, usize)> = edges.into_iter().map(|(u, v)| (u.min(v), u.max(v))).collect();
+    edges.sort_unstable();
+    edges.dedup();
+
+    let mut builder = DigraphBuilder::with_capacity(vertices, 4 * edges.len());
+    for (u, v) in edges {
+        builder.add_edge(u, v);
+        if u != v {
+            builder.add_edge(v, u);
+        }
+    }
+    builder.build()
+}
+
+/// The edges (u, v) with u <= v of the undirected graph `d`
+fn undirected_edges(d: &Digraph, format: &str) -> Result<Vec<(usize, usize)>> {
+    let arcs = undirected_arcs(d, format, true)?;
+    Ok(arcs.into_iter().filter(|&(u, v)| u <= v).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ColouredDigraph;
+    use crate::datastructures::digraph::Digraph;
+    use crate::perm::Permutation;
+
+    fn path() -> Digraph {
+        Digraph::from_vec(vec![vec![1], vec![0, 2], vec![1], vec![]])
+    }
+
+    #[test]
+    fn dimacs() {
+        let input = "c A path, with a repeated edge\np edge 4 3\nn 4 2\ne 1 2\ne 3 2\ne 2 1\n";
+        let d = ColouredDigraph::from_dimacs(input.as_bytes()).unwrap();
+        assert_eq!(d.digraph, path());
+        assert_eq!(d.colours, vec![0, 0, 0, 2]);
+
+        let out = d.to_dimacs().unwrap();
+        assert_eq!(out, "p edge 4 2\nn 4 2\ne 1 2\ne 2 3\n");
+        assert_eq!(ColouredDigraph::from_dimacs(out.as_bytes()).unwrap(), d);
+
+        assert!(ColouredDigraph::from_dimacs("e 1 2\n".as_bytes()).is_err());
+        assert!(ColouredDigraph::from_dimacs("p edge 2 1\ne 1 3\n".as_bytes()).is_err());
+        assert!(ColouredDigraph::from_dimacs("p edge 2 1\nx\n".as_bytes()).is_err());
+        assert!(ColouredDigraph::from_dimacs("c empty\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn edge_list() {
+        let d = ColouredDigraph::from_edge_list("# A path\n0 1\n\n2 1\n".as_bytes()).unwrap();
+        assert_eq!(d.digraph.vertices(), 3);
+        assert_eq!(d.to_edge_list().unwrap(), "0 1\n1 2\n");
+        assert!(ColouredDigraph::from_edge_list("0 1 2\n".as_bytes()).is_err());
+
+        let mut coloured = ColouredDigraph::uncoloured(path());
+        coloured.colours[0] = 1;
+        assert!(coloured.to_edge_list().is_err());
+    }
+
+    #[test]
+    fn permute() {
+        let mut d = ColouredDigraph::uncoloured(path());
+        d.colours[3] = 1;
+        let p = Permutation::from_vec(vec![3, 2, 1, 0]);
+        let image = d.permute(&p);
+        assert_eq!(image.colours, vec![1, 0, 0, 0]);
+        assert_eq!(
+            image.digraph,
+            Digraph::from_vec(vec![vec![], vec![2], vec![1, 3], vec![2]])
+        );
+    }
+}
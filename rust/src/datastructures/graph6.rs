@@ -0,0 +1,380 @@
+//! Reading and writing [Digraph]s in the graph6, sparse6 and digraph6 formats
+//! used by nauty, SageMath and House of Graphs (see `formats.txt` in nauty).
+//!
+//! graph6 and sparse6 store undirected graphs, which are read as digraphs with an
+//! arc in each direction for every edge (a loop is a single arc). Only digraphs made
+//! of unlabelled, unrepeated edges can be written.
+
+use anyhow::{bail, Context, Result};
+
+use super::digraph::{Digraph, DigraphBuilder};
+
+/// Optional header at the start of a graph6 file
+pub const GRAPH6_HEADER: &str = ">>graph6<<";
+/// Optional header at the start of a sparse6 file
+pub const SPARSE6_HEADER: &str = ">>sparse6<<";
+/// Optional header at the start of a digraph6 file
+pub const DIGRAPH6_HEADER: &str = ">>digraph6<<";
+
+/// Largest number of vertices which can be stored
+pub const MAX_VERTICES: usize = (1 << 36) - 1;
+
+/// Largest number of vertices which can be read from sparse6. The length of graph6 and
+/// digraph6 data depends on the number of vertices, but sparse6 can store a graph with
+/// many vertices and no edges in a few bytes, so the number of vertices must be limited.
+pub const MAX_SPARSE6_VERTICES: usize = 1 << 24;
+
+/// Check every byte of `s` stores 6 bits
+fn data_bytes(s: &str) -> Result<&[u8]> {
+    let data = s.as_bytes();
+    if let Some(b) = data.iter().find(|&&b| !(63..=126).contains(&b)) {
+        bail!("Invalid character {:?} in graph", *b as char);
+    }
+    Ok(data)
+}
+
+/// The bits stored in `data`, most significant first
+fn bits(data: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    data.iter()
+        .flat_map(|&b| (0..6).rev().map(move |i| ((b - 63) >> i) & 1 == 1))
+}
+
+/// Read the number of vertices at the start of `data`, returning it and the rest of `data`
+fn decode_size(data: &[u8]) -> Result<(usize, &[u8])> {
+    let value = |bytes: &[u8]| bytes.iter().fold(0, |acc, &b| (acc << 6) | usize::from(b - 63));
+    match data {
+        [126, 126, rest @ ..] if rest.len() >= 6 => Ok((value(&rest[..6]), &rest[6..])),
+        [126, rest @ ..] if rest.len() >= 3 && rest[0] != 126 => Ok((value(&rest[..3]), &rest[3..])),
+        [b, rest @ ..] if *b != 126 => Ok((usize::from(b - 63), rest)),
+        _ => bail!("Invalid number of vertices"),
+    }
+}
+
+/// Packs bits into bytes, 6 at a time
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, bit: bool) {
+        if self.bits.is_multiple_of(6) {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 1 << (5 - self.bits % 6);
+        }
+        self.bits += 1;
+    }
+
+    /// Push the lowest `width` bits of `value`, most significant first
+    fn push_value(&mut self, value: usize, width: usize) {
+        for i in (0..width).rev() {
+            self.push((value >> i) & 1 == 1);
+        }
+    }
+
+    /// Number of bits needed to fill the last byte
+    fn padding(&self) -> usize {
+        (6 - self.bits % 6) % 6
+    }
+
+    /// Append the bytes to `out`, padding the last byte with 0s
+    fn write_to(self, out: &mut String) {
+        out.extend(self.bytes.into_iter().map(|b| char::from(b + 63)));
+    }
+}
+
+/// Append the number of vertices `n` to `out`
+fn encode_size(n: usize, out: &mut String) {
+    assert!(n <= MAX_VERTICES, "Too many vertices to store in graph6");
+    let mut writer = BitWriter::default();
+    if n < 63 {
+        writer.push_value(n, 6);
+    } else if n < 258_048 {
+        writer.push_value(63, 6);
+        writer.push_value(n, 18);
+    } else {
+        writer.push_value(63, 6);
+        writer.push_value(63, 6);
+        writer.push_value(n, 36);
+    }
+    writer.write_to(out);
+}
+
+/// The arcs of `d`, checking it is undirected. Loops are only allowed if `loops`.
+pub(super) fn undirected_arcs(d: &Digraph, format: &str, loops: bool) -> Result<Vec<(usize, usize)>> {
+    let arcs = d
+        .unlabelled_arcs()
+        .with_context(|| format!("{} can not store labelled or repeated edges", format))?;
+    for &(source, target) in &arcs {
+        if source == target && !loops {
+            bail!("{} can not store loops", format);
+        }
+        if arcs.binary_search(&(target, source)).is_err() {
+            bail!("{} can only store undirected graphs", format);
+        }
+    }
+    Ok(arcs)
+}
+
+impl Digraph {
+    /// Read a graph in graph6 format
+    pub fn from_graph6(s: &str) -> Result<Self> {
+        let data = data_bytes(s.trim_end().strip_prefix(GRAPH6_HEADER).unwrap_or(s.trim_end()))?;
+        let (n, data) = decode_size(data)?;
+        let pairs = n.checked_mul(n.saturating_sub(1)).map(|x| x / 2);
+        if pairs.map(|p| p.div_ceil(6)) != Some(data.len()) {
+            bail!("Wrong length of graph6 data for {} vertices", n);
+        }
+
+        let mut builder = DigraphBuilder::new(n);
+        let mut bits = bits(data);
+        for j in 1..n {
+            for i in 0..j {
+                if bits.next().unwrap() {
+                    builder.add_edge(i, j);
+                    builder.add_edge(j, i);
+                }
+            }
+        }
+        Ok(builder.build())
+    }
+
+    /// Write an undirected graph without loops in graph6 format
+    pub fn to_graph6(&self) -> Result<String> {
+        let arcs = undirected_arcs(self, "graph6", false)?;
+        let n = self.vertices();
+        let mut out = String::new();
+        encode_size(n, &mut out);
+        let mut writer = BitWriter::default();
+        for j in 1..n {
+            for i in 0..j {
+                writer.push(arcs.binary_search(&(i, j)).is_ok());
+            }
+        }
+        writer.write_to(&mut out);
+        Ok(out)
+    }
+
+    /// Read a digraph in digraph6 format
+    pub fn from_digraph6(s: &str) -> Result<Self> {
+        let s = s.trim_end().strip_prefix(DIGRAPH6_HEADER).unwrap_or(s.trim_end());
+        let data = data_bytes(s.strip_prefix('&').context("digraph6 graphs must start with '&'")?)?;
+        let (n, data) = decode_size(data)?;
+        if n.checked_mul(n).map(|x| x.div_ceil(6)) != Some(data.len()) {
+            bail!("Wrong length of digraph6 data for {} vertices", n);
+        }
+
+        let mut builder = DigraphBuilder::new(n);
+        let mut bits = bits(data);
+        for i in 0..n {
+            for j in 0..n {
+                if bits.next().unwrap() {
+                    builder.add_edge(i, j);
+                }
+            }
+        }
+        Ok(builder.build())
+    }
+
+    /// Write a digraph in digraph6 format
+    pub fn to_digraph6(&self) -> Result<String> {
+        let arcs = self
+            .unlabelled_arcs()
+            .context("digraph6 can not store labelled or repeated edges")?;
+        let n = self.vertices();
+        let mut out = "&".to_string();
+        encode_size(n, &mut out);
+        let mut writer = BitWriter::default();
+        let mut arcs = arcs.into_iter().peekable();
+        for i in 0..n {
+            for j in 0..n {
+                writer.push(arcs.next_if_eq(&(i, j)).is_some());
+            }
+        }
+        writer.write_to(&mut out);
+        Ok(out)
+    }
+
+    /// Read a graph in sparse6 format. Repeated edges are allowed.
+    pub fn from_sparse6(s: &str) -> Result<Self> {
+        let s = s.trim_end().strip_prefix(SPARSE6_HEADER).unwrap_or(s.trim_end());
+        let data = data_bytes(s.strip_prefix(':').context("sparse6 graphs must start with ':'")?)?;
+        let (n, data) = decode_size(data)?;
+        if n > MAX_SPARSE6_VERTICES {
+            bail!(
+                "sparse6 graph has {} vertices, but at most {} can be read",
+                n,
+                MAX_SPARSE6_VERTICES
+            );
+        }
+        let k = sparse6_width(n);
+
+        let mut builder = DigraphBuilder::new(n);
+        let mut bits = bits(data);
+        let mut v = 0;
+        // Each step is one bit, then a k bit vertex. Padding is either too short
+        // to be a step, or moves v past the last vertex.
+        while let Some(b) = bits.next() {
+            let mut x = 0;
+            for _ in 0..k {
+                match bits.next() {
+                    Some(bit) => x = (x << 1) | usize::from(bit),
+                    None => return Ok(builder.build()),
+                }
+            }
+            if b {
+                v += 1;
+            }
+            if x >= n || v >= n {
+                break;
+            }
+            if x > v {
+                v = x;
+            } else {
+                builder.add_edge(x, v);
+                if x != v {
+                    builder.add_edge(v, x);
+                }
+            }
+        }
+        Ok(builder.build())
+    }
+
+    /// Write an undirected graph in sparse6 format
+    pub fn to_sparse6(&self) -> Result<String> {
+        let arcs = undirected_arcs(self, "sparse6", true)?;
+        let n = self.vertices();
+        let k = sparse6_width(n);
+        let mut out = ":".to_string();
+        encode_size(n, &mut out);
+
+        // Edges (u, v) with u <= v, ordered by v
+        let mut edges: Vec<(usize, usize)> = arcs.into_iter().filter(|&(u, v)| u >= v).collect();
+        edges.sort_unstable();
+
+        let mut writer = BitWriter::default();
+        let mut current = 0;
+        for (v, u) in edges {
+            if v == current {
+                writer.push(false);
+                writer.push_value(u, k);
+            } else if v == current + 1 {
+                current = v;
+                writer.push(true);
+                writer.push_value(u, k);
+            } else {
+                current = v;
+                writer.push(true);
+                writer.push_value(v, k);
+                writer.push(false);
+                writer.push_value(u, k);
+            }
+        }
+
+        // Padding with 1s could be read as an edge to vertex n - 1, so in that case
+        // start the padding with a 0, which moves v past the last vertex instead.
+        if k < 6 && n == 1 << k && writer.padding() >= k && current + 1 < n {
+            writer.push(false);
+        }
+        for _ in 0..writer.padding() {
+            writer.push(true);
+        }
+        writer.write_to(&mut out);
+        Ok(out)
+    }
+}
+
+/// The number of bits used to store a vertex in sparse6
+fn sparse6_width(n: usize) -> usize {
+    let mut k = 1;
+    while (1 << k) < n {
+        k += 1;
+    }
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::digraph::Digraph;
+
+    fn undirected(n: usize, edges: &[(usize, usize)]) -> Digraph {
+        let mut adj = vec![vec![]; n];
+        for &(i, j) in edges {
+            adj[i].push(j);
+            if i != j {
+                adj[j].push(i);
+            }
+        }
+        Digraph::from_vec(adj)
+    }
+
+    #[test]
+    fn graph6() {
+        // Examples from nauty's formats.txt
+        let d = undirected(5, &[(0, 2), (0, 4), (1, 3), (3, 4)]);
+        assert_eq!(d.to_graph6().unwrap(), "DQc");
+        assert_eq!(Digraph::from_graph6("DQc").unwrap(), d);
+        assert_eq!(Digraph::from_graph6(">>graph6<<DQc\n").unwrap(), d);
+
+        let empty = Digraph::empty(0);
+        assert_eq!(empty.to_graph6().unwrap(), "?");
+        assert_eq!(Digraph::from_graph6("?").unwrap(), empty);
+
+        assert!(Digraph::from_graph6("DQ").is_err());
+        assert!(Digraph::from_graph6("D Qc").is_err());
+        assert!(Digraph::from_vec(vec![vec![1], vec![]]).to_graph6().is_err());
+        assert!(undirected(2, &[(0, 0)]).to_graph6().is_err());
+    }
+
+    #[test]
+    fn digraph6() {
+        let d = Digraph::from_vec(vec![vec![1, 2], vec![2], vec![], vec![]]);
+        let d6 = d.to_digraph6().unwrap();
+        assert_eq!(d6, "&CW_?");
+        assert_eq!(Digraph::from_digraph6(&d6).unwrap(), d);
+
+        let loops = Digraph::from_vec(vec![vec![0, 1], vec![0]]);
+        assert_eq!(Digraph::from_digraph6(&loops.to_digraph6().unwrap()).unwrap(), loops);
+        assert!(Digraph::from_digraph6("CW_?").is_err());
+        assert!(Digraph::from_labelled_vec(vec![vec![(1, 2)], vec![]])
+            .to_digraph6()
+            .is_err());
+    }
+
+    #[test]
+    fn sparse6() {
+        // Example from nauty's formats.txt
+        let d = undirected(7, &[(0, 1), (0, 2), (1, 2), (5, 6)]);
+        assert_eq!(d.to_sparse6().unwrap(), ":Fa@x^");
+        assert_eq!(Digraph::from_sparse6(":Fa@x^").unwrap(), d);
+
+        for g in [
+            undirected(4, &[(0, 1), (1, 2)]),
+            undirected(4, &[(0, 3), (2, 2)]),
+            undirected(2, &[(0, 1)]),
+            undirected(1, &[]),
+            undirected(70, &[(0, 69), (3, 40), (40, 41)]),
+        ] {
+            assert_eq!(Digraph::from_sparse6(&g.to_sparse6().unwrap()).unwrap(), g);
+        }
+        assert!(Digraph::from_sparse6("Fa@x^").is_err());
+    }
+
+    #[test]
+    fn large_sizes() {
+        for n in [62, 63, 258_047, 258_048] {
+            let d = undirected(n, &[(0, n - 1)]);
+            assert_eq!(Digraph::from_sparse6(&d.to_sparse6().unwrap()).unwrap(), d);
+        }
+        let d = undirected(100, &[(0, 99), (50, 51)]);
+        assert_eq!(Digraph::from_graph6(&d.to_graph6().unwrap()).unwrap(), d);
+
+        // The largest size, with too little data (or none, which is valid sparse6)
+        assert!(Digraph::from_sparse6(":~~~~~~~~").is_err());
+        assert!(Digraph::from_graph6("~~~~~~~~").is_err());
+        assert!(Digraph::from_digraph6("&~~~~~~~~").is_err());
+    }
+}
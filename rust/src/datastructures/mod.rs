@@ -1,6 +1,8 @@
 //! A collection of data structures which are used in vole
 
 pub mod digraph;
+pub mod dimacs;
+pub mod graph6;
 pub mod hash;
 pub mod small_int_set;
 pub mod sortedvec;
@@ -30,7 +30,7 @@ impl SmallIntSet {
     }
 
     /// Return an iterator to iterate through the set (in sorted order)
-    pub fn sorted_iter(&mut self) -> ::std::slice::Iter<usize> {
+    pub fn sorted_iter(&mut self) -> ::std::slice::Iter<'_, usize> {
         // Sort lazily
         self.values.sort();
         self.values.iter()
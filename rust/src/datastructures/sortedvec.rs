@@ -31,7 +31,7 @@ impl<T: Ord> SortedVec<T> {
     }
 
     /// Returns an iterator over the vector
-    pub fn iter(&self) -> std::slice::Iter<T> {
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
         self.into_iter()
     }
 }
@@ -80,7 +80,7 @@ mod tests {
         assert!(!s.contains(&3));
         assert!(!s.contains(&6));
 
-        let t: SortedVec<i32> = vec![4, 3, 1].iter().map(|&x| x + 1).collect();
+        let t: SortedVec<i32> = [4, 3, 1].iter().map(|&x| x + 1).collect();
         assert_eq!(t, s);
 
         let w: SortedVec<i32> = t.into_iter().map(|x| x * 2).collect();
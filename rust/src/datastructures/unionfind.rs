@@ -13,15 +13,23 @@ pub struct UnionFind {
 impl UnionFind {
     pub fn new(size: usize) -> Self {
         Self {
-            orbit_mins: vec![usize::max_value(); size],
-            depth_explored: vec![usize::max_value(); size],
+            orbit_mins: vec![usize::MAX; size],
+            depth_explored: vec![usize::MAX; size],
         }
     }
 
+    /// Reset to `size` points, each in its own orbit, reusing the memory already allocated
+    pub fn reset(&mut self, size: usize) {
+        self.orbit_mins.clear();
+        self.orbit_mins.resize(size, usize::MAX);
+        self.depth_explored.clear();
+        self.depth_explored.resize(size, usize::MAX);
+    }
+
     fn expand_to(&mut self, size: usize) {
         while self.orbit_mins.len() < size {
-            self.orbit_mins.push(usize::max_value());
-            self.depth_explored.push(usize::max_value());
+            self.orbit_mins.push(usize::MAX);
+            self.depth_explored.push(usize::MAX);
         }
     }
 
@@ -34,7 +42,7 @@ impl UnionFind {
     }
 
     fn find(&self, mut p: usize) -> usize {
-        while self.orbit_mins[p] != usize::max_value() {
+        while self.orbit_mins[p] != usize::MAX {
             p = self.orbit_mins[p];
         }
         p
@@ -69,7 +77,7 @@ impl UnionFind {
         self.orbit_mins[bf] = base;
         self.orbit_mins[a] = base;
         self.orbit_mins[b] = base;
-        self.orbit_mins[base] = usize::max_value();
+        self.orbit_mins[base] = usize::MAX;
         self.depth_explored[base] = min_depth_explored;
         true
     }
@@ -84,7 +92,7 @@ impl UnionFind {
 
     /// Should we branch on this value at this depth
     pub fn orbit_needs_searching(&mut self, c: usize, depth: usize) -> bool {
-        if self.orbit_mins[c] != usize::max_value() {
+        if self.orbit_mins[c] != usize::MAX {
             return false;
         }
 
@@ -108,7 +116,7 @@ impl UnionFind {
             elms.push(i);
         }
 
-        let mut orbs: Vec<Vec<usize>> = h.into_iter().map(|(_, v)| v).collect();
+        let mut orbs: Vec<Vec<usize>> = h.into_values().collect();
 
         for o in &mut orbs {
             o.sort();
@@ -18,7 +18,8 @@ use std::io::Write;
 
 use structopt::StructOpt;
 
-use crate::vole::{solutions::Solutions, stats::Stats};
+use crate::datastructures::{digraph::Digraph, dimacs::ColouredDigraph};
+use crate::vole::{parse_input, parse_input::MatrixSolutions, solutions::Solutions, stats::Stats};
 
 /// Store command line arguments
 #[derive(StructOpt, Debug)]
@@ -51,6 +52,141 @@ pub struct Opt {
     /// Be quiet (hide rust backtraces on crash)
     #[structopt(short, long)]
     pub quiet: bool,
+
+    /// Write a certificate for the result of search to this file
+    #[structopt(long, parse(from_os_str))]
+    pub certificate: Option<PathBuf>,
+
+    /// Check the certificate in this file, instead of talking to GAP
+    #[structopt(long, parse(from_os_str))]
+    pub check_certificate: Option<PathBuf>,
+
+    /// Check every refiner is self-consistent during search, reporting any problems in the stats
+    #[structopt(long)]
+    pub check_refiners: bool,
+
+    /// Record the traces of search, and write them to this file (to be compared with `vole trace-diff`)
+    #[structopt(long, parse(from_os_str))]
+    pub trace_dump: Option<PathBuf>,
+}
+
+/// Commands which are run on their own, instead of talking to GAP
+#[derive(StructOpt, Debug)]
+#[structopt(name = "vole")]
+pub enum Command {
+    /// Compare two trace dumps (written by `--trace-dump`), and report the first difference in
+    /// the symmetry and canonical traces, each as a line of JSON
+    TraceDiff {
+        #[structopt(parse(from_os_str))]
+        left: PathBuf,
+        #[structopt(parse(from_os_str))]
+        right: PathBuf,
+    },
+    /// Find the canonical labelling and canonical form of digraphs, without GAP.
+    /// Each line of the input is one digraph, except for the dimacs and edge-list
+    /// formats, where the whole input is one graph.
+    Canon {
+        /// Read digraphs from this file, instead of standard input
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+        /// The format of each digraph: json, graph6, sparse6, digraph6, dimacs or edge-list
+        #[structopt(long, default_value = "json")]
+        format: DigraphFormat,
+    },
+    /// Sort structures into isomorphism classes, without GAP. Each line of the input
+    /// is one structure, and the output gives its class, the first structure in its class
+    /// (numbered from 0) and an isomorphism to that structure.
+    Classify {
+        /// Read structures from this file, instead of standard input
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+        /// Read digraphs in this format (json, graph6, sparse6 or digraph6). Otherwise
+        /// each line is either a problem, or has the number of points and a list of constraints.
+        #[structopt(long)]
+        format: Option<DigraphFormat>,
+    },
+}
+
+/// The formats digraphs can be read in from the command line
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DigraphFormat {
+    /// The (1-indexed) out-neighbours of each vertex, where a neighbour is
+    /// either a vertex or a [vertex, label] pair
+    Json,
+    Graph6,
+    Sparse6,
+    Digraph6,
+    /// DIMACS, including bliss's lines for vertex colours
+    Dimacs,
+    EdgeList,
+}
+
+impl std::str::FromStr for DigraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "graph6" => Ok(Self::Graph6),
+            "sparse6" => Ok(Self::Sparse6),
+            "digraph6" => Ok(Self::Digraph6),
+            "dimacs" => Ok(Self::Dimacs),
+            "edge-list" => Ok(Self::EdgeList),
+            _ => Err(format!("Unknown digraph format '{}'", s)),
+        }
+    }
+}
+
+impl DigraphFormat {
+    /// Check if each line of the input is a separate digraph
+    pub fn is_line_based(self) -> bool {
+        !matches!(self, Self::Dimacs | Self::EdgeList)
+    }
+
+    /// Read a digraph stored in this format. Json digraphs read this way can not
+    /// be written again, as the labels of their edges are lost.
+    pub fn read(self, s: &str) -> Result<ColouredDigraph> {
+        match self {
+            Self::Json => Ok(ColouredDigraph::uncoloured(Digraph::from_labelled_vec(
+                parse_input::read_json_digraph(s)?,
+            ))),
+            Self::Graph6 => Digraph::from_graph6(s).map(ColouredDigraph::uncoloured),
+            Self::Sparse6 => Digraph::from_sparse6(s).map(ColouredDigraph::uncoloured),
+            Self::Digraph6 => Digraph::from_digraph6(s).map(ColouredDigraph::uncoloured),
+            Self::Dimacs => ColouredDigraph::from_dimacs(s.as_bytes()),
+            Self::EdgeList => ColouredDigraph::from_edge_list(s.as_bytes()),
+        }
+    }
+
+    /// Write a digraph in this format, which must not be Json
+    pub fn write(self, d: &ColouredDigraph) -> Result<String> {
+        if !d.is_uncoloured() && !matches!(self, Self::Dimacs) {
+            return Err(anyhow!("Only DIMACS can store vertex colours"));
+        }
+        match self {
+            Self::Json => Err(anyhow!("Json digraphs must be written as a list of edges")),
+            Self::Graph6 => d.digraph.to_graph6(),
+            Self::Sparse6 => d.digraph.to_sparse6(),
+            Self::Digraph6 => d.digraph.to_digraph6(),
+            Self::Dimacs => d.to_dimacs(),
+            Self::EdgeList => d.to_edge_list(),
+        }
+    }
+}
+
+impl Command {
+    /// The names of all commands
+    const NAMES: &'static [&'static str] = &["trace-diff", "canon", "classify"];
+
+    /// Parse the command line as a command, if the first argument is the name of a command
+    pub fn from_command_line() -> Option<Self> {
+        let first = std::env::args().nth(1)?;
+        if Self::NAMES.contains(&first.as_str()) {
+            Some(Self::from_args())
+        } else {
+            None
+        }
+    }
 }
 
 /// Store communication channels with GAP
@@ -196,6 +332,9 @@ struct Results {
     search_fix_order: Vec<usize>,
     stats: Stats,
     rbase_branches: Vec<usize>,
+    /// The solutions as row and column permutations, for each matrix constraint
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    matrix_sols: Vec<MatrixSolutions>,
 }
 
 impl GapChatType {
@@ -207,6 +346,7 @@ impl GapChatType {
         fixed: &[usize],
         rbase_base: &[usize],
         stats: Stats,
+        matrix_sols: Vec<MatrixSolutions>,
     ) -> anyhow::Result<()> {
         let sols: Vec<Vec<usize>> = solutions
             .get()
@@ -231,6 +371,7 @@ impl GapChatType {
                     search_fix_order,
                     stats,
                     rbase_branches,
+                    matrix_sols,
                 },
             ),
         )?;
@@ -179,6 +179,7 @@ impl From<Vec<usize>> for Permutation {
     }
 }
 
+#[allow(clippy::nonminimal_bool)]
 #[cfg(test)]
 mod tests {
     use super::Permutation;
@@ -46,6 +46,7 @@ mod tests {
     use crate::perm::Permutation;
 
     #[test]
+    #[allow(clippy::mutable_key_type)]
     fn make_trivial() {
         let p = Permutation::id();
         let mut r = RandomPerm::new(vec![p]);
@@ -58,6 +59,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::mutable_key_type)]
     fn make_lots() {
         let p = Permutation::from_vec(vec![6, 0, 1, 2, 3, 4, 5]);
         let q = Permutation::from_vec(vec![1, 0]);
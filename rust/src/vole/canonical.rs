@@ -0,0 +1,375 @@
+//! Canonical labelling of digraphs and other structures
+//!
+//! This runs canonical search over the symmetric group on the points, and finds
+//! the smallest image of each leaf without GAP (see [CanonicalMinimiser::Symmetric]),
+//! so it can be used from the command line.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::datastructures::{digraph::Digraph, hash::QHash};
+use crate::perm::Permutation;
+use crate::vole::{
+    domain_state::DomainState,
+    refiners::{
+        digraph::DigraphTransporter, labelled::LabelledPointsTransporter, refiner_store::RefinerStore,
+        symmetricgrp::InSymmetricGrp, Refiner,
+    },
+    search::{simple_group_search, CanonicalMinimiser, SearchConfig},
+    solutions::Solutions,
+    state::State,
+    trace::{Tracer, TracingType},
+};
+
+/// The result of canonical search for a structure
+#[derive(Debug)]
+pub struct CanonicalForm {
+    /// Maps the structure to its canonical image
+    pub labelling: Permutation,
+    /// The images of the structure under `labelling`, one for each refiner (see
+    /// [RefinerStore::get_canonical_images]). Two structures on the same number of
+    /// points, with the same refiners, are isomorphic exactly when their images are equal.
+    pub images: Vec<Box<dyn Any>>,
+}
+
+/// Runs canonical search on many structures, one after another. The search state is
+/// kept between structures, so its memory is only allocated once.
+pub struct CanonicalSearcher {
+    config: SearchConfig,
+    /// The state and solutions of the last search, which are reset before each search
+    search: Option<(State, Solutions)>,
+}
+
+impl CanonicalSearcher {
+    pub fn new(search_config: &SearchConfig) -> Self {
+        let mut config = search_config.clone();
+        config.canonical_minimiser = CanonicalMinimiser::Symmetric;
+        Self { config, search: None }
+    }
+
+    /// Find the canonical form of the structure on the points `0..points` which is stabilized
+    /// by `refiners`, searching in the symmetric group. Every refiner must be for a group.
+    pub fn canonical_form(&mut self, points: usize, refiners: Vec<Box<dyn Refiner>>) -> Result<CanonicalForm> {
+        let mut all_refiners: Vec<Box<dyn Refiner>> =
+            vec![Box::new(InSymmetricGrp::new_symmetric_group((0..points).collect()))];
+        all_refiners.extend(refiners);
+
+        // Search needs at least two points, so smaller structures are given a state for two
+        // points, but are not searched, as their only labelling is the identity
+        let search_points = points.max(2);
+        let (state, sols) = match &mut self.search {
+            Some((state, sols)) => {
+                state.domain.reset(search_points, TracingType::BOTH);
+                state.refiners.reset(all_refiners);
+                state.stats = Default::default();
+                sols.reset(search_points);
+                (state, sols)
+            }
+            None => {
+                let state = State {
+                    domain: DomainState::new(search_points, Tracer::new()),
+                    refiners: RefinerStore::new_from_refiners(all_refiners).with_group(0),
+                    stats: Default::default(),
+                };
+                let (state, sols) = self.search.insert((state, Solutions::new(search_points)));
+                (state, sols)
+            }
+        };
+
+        if points < 2 {
+            let labelling = Permutation::id();
+            let images = state.refiners.get_canonical_images(&labelling);
+            return Ok(CanonicalForm { labelling, images });
+        }
+
+        simple_group_search(state, sols, &self.config);
+        match sols.take_canonical() {
+            Some(canonical) => Ok(CanonicalForm {
+                labelling: canonical.perm,
+                images: canonical.images,
+            }),
+            None => bail!("Canonical search did not find a canonical image"),
+        }
+    }
+
+    /// The refiners used by the last search, whose images are in its [CanonicalForm]
+    fn refiners(&self) -> &RefinerStore {
+        &self.search.as_ref().expect("No search has been run").0.refiners
+    }
+}
+
+/// Find the canonical form of the structure on the points `0..points` which is stabilized
+/// by `refiners`, searching in the symmetric group. Every refiner must be for a group.
+/// To find the canonical forms of many structures, use a [CanonicalSearcher].
+pub fn canonical_form(
+    points: usize,
+    refiners: Vec<Box<dyn Refiner>>,
+    search_config: &SearchConfig,
+) -> Result<CanonicalForm> {
+    CanonicalSearcher::new(search_config).canonical_form(points, refiners)
+}
+
+/// The refiners for the stabilizer of `digraph`, and of `colours` if it is not empty
+pub fn digraph_refiners(digraph: Arc<Digraph>, colours: &[usize]) -> Vec<Box<dyn Refiner>> {
+    let mut refiners: Vec<Box<dyn Refiner>> = vec![Box::new(DigraphTransporter::new_stabilizer(digraph))];
+    if !colours.is_empty() {
+        refiners.push(Box::new(LabelledPointsTransporter::new_stabilizer(colours.to_vec())));
+    }
+    refiners
+}
+
+/// Find a canonical labelling of `digraph`, which maps each vertex to its position in
+/// the canonical digraph. Two digraphs are isomorphic exactly when applying their
+/// canonical labellings gives the same digraph. If `colours` is not empty, it gives
+/// each vertex a colour, which isomorphisms must preserve.
+pub fn canonical_digraph_labelling(
+    searcher: &mut CanonicalSearcher,
+    digraph: Arc<Digraph>,
+    colours: &[usize],
+) -> Result<Permutation> {
+    let points = digraph.vertices();
+    Ok(searcher
+        .canonical_form(points, digraph_refiners(digraph, colours))?
+        .labelling)
+}
+
+/// A digraph given as the (neighbour, label) pairs of each vertex, as used by
+/// [Digraph::from_labelled_vec]
+pub type LabelledEdges = Vec<Vec<(usize, usize)>>;
+
+/// Apply `p` to a digraph given as the (neighbour, label) pairs of each vertex,
+/// as used by [Digraph::from_labelled_vec]. The neighbours of each vertex are sorted.
+pub fn permute_labelled_edges(edges: &[Vec<(usize, usize)>], p: &Permutation) -> LabelledEdges {
+    let mut image = vec![vec![]; edges.len()];
+    for (v, neighbours) in edges.iter().enumerate() {
+        image[p.apply(v)] = neighbours.iter().map(|&(w, label)| (p.apply(w), label)).collect();
+    }
+    for neighbours in &mut image {
+        neighbours.sort_unstable();
+    }
+    image
+}
+
+/// Find a canonical labelling of the digraph with the (neighbour, label) pairs `edges`, as
+/// used by [Digraph::from_labelled_vec], and the canonical form of `edges`
+pub fn canonical_labelled_edges(
+    searcher: &mut CanonicalSearcher,
+    edges: &[Vec<(usize, usize)>],
+) -> Result<(Permutation, LabelledEdges)> {
+    let digraph = Arc::new(Digraph::from_labelled_vec(edges.to_vec()));
+    let labelling = canonical_digraph_labelling(searcher, digraph, &[])?;
+    let canonical = permute_labelled_edges(edges, &labelling);
+    Ok((labelling, canonical))
+}
+
+/// Which isomorphism class a structure is in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Classified {
+    /// The isomorphism class, numbered from 0 in the order classes are first seen
+    pub class: usize,
+    /// The first structure in the class, numbered from 0 in the order structures were added
+    pub representative: usize,
+    /// An isomorphism from the structure to the representative
+    pub isomorphism: Permutation,
+}
+
+/// An isomorphism class found by a [Classifier]
+struct Class {
+    /// The number of the class
+    class: usize,
+    /// The first structure in the class, and its canonical form
+    representative: usize,
+    form: CanonicalForm,
+}
+
+/// Sorts structures into isomorphism classes, using their canonical forms
+pub struct Classifier {
+    searcher: CanonicalSearcher,
+    /// The classes, by number of points and hash of their canonical images. Classes
+    /// whose hashes collide are told apart by comparing their images.
+    classes: HashMap<(usize, QHash), Vec<Class>>,
+    /// The number of classes
+    class_count: usize,
+    /// The number of structures added
+    count: usize,
+}
+
+impl Classifier {
+    pub fn new(search_config: &SearchConfig) -> Self {
+        Self {
+            searcher: CanonicalSearcher::new(search_config),
+            classes: HashMap::new(),
+            class_count: 0,
+            count: 0,
+        }
+    }
+
+    /// Add the structure on the points `0..points` which is stabilized by `refiners`
+    /// (as for [CanonicalSearcher::canonical_form])
+    pub fn add(&mut self, points: usize, refiners: Vec<Box<dyn Refiner>>) -> Result<Classified> {
+        let form = self.searcher.canonical_form(points, refiners)?;
+        let index = self.count;
+        self.count += 1;
+
+        let refiners = self.searcher.refiners();
+        let hash = refiners.hash_canonical_images(&form.images);
+        let bucket = self.classes.entry((points, hash)).or_default();
+        if let Some(c) = bucket
+            .iter()
+            .find(|c| refiners.canonical_images_equal(&form.images, &c.form.images))
+        {
+            return Ok(Classified {
+                class: c.class,
+                representative: c.representative,
+                isomorphism: c.form.labelling.inv().multiply(&form.labelling),
+            });
+        }
+
+        let class = self.class_count;
+        self.class_count += 1;
+        bucket.push(Class {
+            class,
+            representative: index,
+            form,
+        });
+        Ok(Classified {
+            class,
+            representative: index,
+            isomorphism: Permutation::id(),
+        })
+    }
+
+    /// The number of isomorphism classes
+    pub fn classes(&self) -> usize {
+        self.class_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{
+        canonical_digraph_labelling, canonical_form, canonical_labelled_edges, digraph_refiners,
+        permute_labelled_edges, CanonicalSearcher, Classifier,
+    };
+    use crate::datastructures::{digraph::Digraph, dimacs::ColouredDigraph};
+    use crate::perm::Permutation;
+    use crate::vole::refiners::{labelled::LabelledPointsTransporter, Refiner};
+    use crate::vole::search::SearchConfig;
+
+    fn canonical_edges(searcher: &mut CanonicalSearcher, edges: &[Vec<(usize, usize)>]) -> Vec<Vec<(usize, usize)>> {
+        canonical_labelled_edges(searcher, edges).unwrap().1
+    }
+
+    #[test]
+    fn canonical_digraphs() {
+        // A directed 4-cycle with one labelled edge, and a pendant vertex
+        let graph = vec![vec![(1, 0)], vec![(2, 3)], vec![(3, 0)], vec![(0, 0), (4, 0)], vec![]];
+        let perms = [
+            Permutation::from_vec(vec![4, 3, 2, 1, 0]),
+            Permutation::from_vec(vec![1, 2, 3, 4, 0]),
+            Permutation::from_vec(vec![2, 0, 4, 1, 3]),
+        ];
+        // Make the labelled edge unlabelled
+        let mut other = graph.clone();
+        other[1][0].1 = 0;
+
+        let mut simple_config = SearchConfig::default();
+        simple_config.full_graph_refine = false;
+        for search_config in [SearchConfig::default(), simple_config] {
+            let searcher = &mut CanonicalSearcher::new(&search_config);
+            let canonical = canonical_edges(searcher, &graph);
+            for p in &perms {
+                assert_eq!(canonical_edges(searcher, &permute_labelled_edges(&graph, p)), canonical);
+            }
+            assert_ne!(canonical_edges(searcher, &other), canonical);
+        }
+    }
+
+    #[test]
+    fn canonical_coloured() {
+        // A path, with one end coloured
+        let path = ColouredDigraph::from_edge_list("0 1\n1 2\n2 3\n".as_bytes()).unwrap();
+        let mut searcher = CanonicalSearcher::new(&Default::default());
+        let mut canonical = |d: &ColouredDigraph| {
+            let labelling =
+                canonical_digraph_labelling(&mut searcher, Arc::new(d.digraph.clone()), &d.colours).unwrap();
+            d.permute(&labelling)
+        };
+
+        let mut left = path.clone();
+        left.colours[0] = 1;
+        let mut right = path;
+        right.colours[3] = 1;
+        assert_eq!(canonical(&left), canonical(&right));
+
+        let mut middle = left.clone();
+        middle.colours.swap(0, 1);
+        assert_ne!(canonical(&left), canonical(&middle));
+    }
+
+    #[test]
+    fn classify() {
+        let cycle = Digraph::from_vec(vec![vec![1], vec![2], vec![3], vec![0]]);
+        let path = Digraph::from_vec(vec![vec![1], vec![2], vec![3], vec![]]);
+        let p = Permutation::from_vec(vec![2, 0, 3, 1]);
+        let inputs = [cycle.clone(), path.clone(), &cycle ^ &p, &path ^ &p, Digraph::empty(1)];
+
+        let mut classifier = Classifier::new(&Default::default());
+        let classified: Vec<_> = inputs
+            .iter()
+            .map(|d| {
+                classifier
+                    .add(d.vertices(), digraph_refiners(Arc::new(d.clone()), &[]))
+                    .unwrap()
+            })
+            .collect();
+
+        assert_eq!(classifier.classes(), 3);
+        let classes: Vec<_> = classified.iter().map(|c| (c.class, c.representative)).collect();
+        assert_eq!(classes, vec![(0, 0), (1, 1), (0, 0), (1, 1), (2, 4)]);
+        for (d, c) in inputs.iter().zip(&classified) {
+            assert_eq!(&(d ^ &c.isomorphism), &inputs[c.representative]);
+        }
+
+        // Structures with different types of refiners are in different classes
+        let labels: Vec<Box<dyn Refiner>> = vec![Box::new(LabelledPointsTransporter::new_stabilizer(vec![0]))];
+        assert_eq!(classifier.add(1, labels).unwrap().class, 3);
+    }
+
+    #[test]
+    fn searcher_reuse() {
+        // Searching digraphs of different sizes one after another gives the same
+        // labellings as searching each one with a new searcher
+        let digraphs = [
+            Digraph::from_vec(vec![vec![1], vec![2], vec![3], vec![0, 4], vec![]]),
+            Digraph::from_vec(vec![vec![0]]),
+            Digraph::from_vec(vec![vec![1, 2], vec![], vec![1]]),
+            Digraph::from_vec(vec![vec![4], vec![], vec![3], vec![1], vec![2], vec![0]]),
+        ];
+        let mut searcher = CanonicalSearcher::new(&Default::default());
+        for d in digraphs.iter().chain(&digraphs) {
+            let reused = canonical_digraph_labelling(&mut searcher, Arc::new(d.clone()), &[]).unwrap();
+            let form = canonical_form(
+                d.vertices(),
+                digraph_refiners(Arc::new(d.clone()), &[]),
+                &Default::default(),
+            );
+            assert_eq!(reused, form.unwrap().labelling);
+        }
+    }
+
+    #[test]
+    fn canonical_small() {
+        let d = Arc::new(Digraph::from_vec(vec![vec![0]]));
+        let mut searcher = CanonicalSearcher::new(&SearchConfig::default());
+        assert_eq!(
+            canonical_digraph_labelling(&mut searcher, d, &[]).unwrap(),
+            Permutation::id()
+        );
+    }
+}
@@ -6,7 +6,10 @@ use crate::{
     datastructures::digraph::{Digraph, DigraphStack},
     vole::partition_stack,
 };
-use crate::{datastructures::hash::QuickHashable, vole::trace};
+use crate::{
+    datastructures::hash::QuickHashable,
+    vole::{trace, trace_dump::TraceSource},
+};
 
 use std::fmt::Debug;
 
@@ -22,6 +25,7 @@ pub struct DomainState {
     rbase_digraph_stack: Option<DigraphStack>,
     digraph_stack_cells_refined: Backtracking<usize>,
     rbase_branch_vals: Vec<usize>,
+    rbase_trace_len: usize,
 }
 
 impl DomainState {
@@ -34,8 +38,22 @@ impl DomainState {
             rbase_digraph_stack: Option::None,
             digraph_stack_cells_refined: Backtracking::new(0),
             rbase_branch_vals: vec![],
+            rbase_trace_len: 0,
         }
     }
+
+    /// Reset to the state [DomainState::new] makes for `n` points, with the tracer reset to
+    /// trace type `tt`, reusing the memory already allocated
+    pub fn reset(&mut self, n: usize, tt: trace::TracingType) {
+        self.stack.reset(n);
+        self.rbase_stack = None;
+        self.tracer.reset(tt);
+        self.digraph_stack = DigraphStack::empty(n);
+        self.rbase_digraph_stack = None;
+        self.digraph_stack_cells_refined = Backtracking::new(0);
+        self.rbase_branch_vals.clear();
+        self.rbase_trace_len = 0;
+    }
 }
 
 impl DomainState {
@@ -51,6 +69,16 @@ impl DomainState {
         self.tracer.add(e)
     }
 
+    /// Set what is adding events to the trace (this is reset by `restore_state`)
+    pub fn set_trace_source(&mut self, source: TraceSource) {
+        self.tracer.set_source(source)
+    }
+
+    /// Record that search has branched on `i` (this is reset by `restore_state`)
+    pub fn push_branch_val(&mut self, i: usize) {
+        self.tracer.push_branch(i)
+    }
+
     pub fn has_rbase(&self) -> bool {
         self.rbase_stack.is_some()
     }
@@ -62,6 +90,11 @@ impl DomainState {
     pub fn rbase_branch_vals(&self) -> &[usize] {
         &self.rbase_branch_vals
     }
+
+    /// The values search has branched on to reach the current node
+    pub fn branch_path(&self) -> &[usize] {
+        self.tracer.branch_path()
+    }
     fn inject_known_solutions(&mut self) {
         //GapChatType::send_request(&("known_solutions"), )
     }
@@ -70,6 +103,7 @@ impl DomainState {
         assert!(self.rbase_stack.is_none());
         self.rbase_stack = Some(self.stack.clone());
         self.rbase_digraph_stack = Some(self.digraph_stack.clone());
+        self.rbase_trace_len = self.tracer.symmetry_trace_len();
         self.inject_known_solutions();
         refiners.snapshot_rbase(self);
     }
@@ -78,6 +112,11 @@ impl DomainState {
         &self.rbase_stack
     }
 
+    /// The length of the symmetry trace when the rbase was completed
+    pub fn rbase_trace_len(&self) -> usize {
+        self.rbase_trace_len
+    }
+
     pub fn rbase_digraph_stack(&self) -> &DigraphStack {
         self.rbase_digraph_stack.as_ref().unwrap()
     }
@@ -92,33 +131,27 @@ impl DomainState {
         })
     }
 
-    pub fn refine_partition_cell_by<F: Copy, T: Ord + Hash + Debug + QuickHashable>(
+    pub fn refine_partition_cell_by<F, T: Ord + Hash + Debug + QuickHashable>(
         &mut self,
         i: usize,
         f: F,
     ) -> trace::Result<()>
     where
-        F: Fn(&usize) -> T,
+        F: Copy + Fn(&usize) -> T,
     {
         self.stack.refine_partition_cell_by(&mut self.tracer, i, f)
     }
 
-    pub fn base_refine_partition_by<F: Copy, T: Ord + Hash + Debug + QuickHashable>(
-        &mut self,
-        f: F,
-    ) -> trace::Result<()>
+    pub fn base_refine_partition_by<F, T: Ord + Hash + Debug + QuickHashable>(&mut self, f: F) -> trace::Result<()>
     where
-        F: Fn(&usize) -> T,
+        F: Copy + Fn(&usize) -> T,
     {
         self.stack.base_refine_partition_by(&mut self.tracer, f)
     }
 
-    pub fn extended_refine_partition_by<F: Copy, T: Ord + Hash + Debug + QuickHashable>(
-        &mut self,
-        f: F,
-    ) -> trace::Result<()>
+    pub fn extended_refine_partition_by<F, T: Ord + Hash + Debug + QuickHashable>(&mut self, f: F) -> trace::Result<()>
     where
-        F: Fn(&usize) -> T,
+        F: Copy + Fn(&usize) -> T,
     {
         self.stack.extended_refine_partition_by(&mut self.tracer, f)
     }
@@ -1,4 +1,5 @@
 pub mod backtracking;
+pub mod canonical;
 pub mod domain_state;
 pub mod parse_input;
 mod partition_stack;
@@ -10,3 +11,4 @@ pub mod state;
 pub mod stats;
 mod subsearch;
 pub mod trace;
+pub mod trace_dump;
@@ -1,70 +1,214 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
-use crate::datastructures::digraph::Digraph;
+use crate::datastructures::{digraph::Digraph, sortedvec::SortedVec};
 
+use super::refiners::code::CodeTransporter;
+use super::refiners::labelled::LabelledPointsTransporter;
+use super::refiners::matrix::{split_row_column_permutation, MatrixTransporter};
+use super::refiners::necklace::{min_rotation, NecklaceTransporter};
+use super::refiners::partition::{OrderedPartitionTransporter, UnorderedPartitionTransporter};
+use super::refiners::refiner_store::RefinerStore;
 use super::refiners::simple::SetTransporter;
 use super::refiners::simple::TupleTransporter;
+use super::refiners::simple::{MultisetTransporter, RelationalStructureTransporter, SetMultisetTransporter};
+use super::refiners::structure::StructureTransporter;
 use super::refiners::symmetricgrp::InSymmetricGrp;
 use super::refiners::Refiner;
 use super::refiners::{
-    digraph::DigraphTransporter,
+    digraph::{DigraphSetTransporter, DigraphTransporter},
     simple::{SetSetTransporter, SetTupleTransporter},
 };
 use super::search::SearchConfig;
+use super::solutions::Solutions;
+use crate::perm::Permutation;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
-use std::{io::BufRead, sync::Arc};
+use std::{collections::HashSet, convert::TryFrom, io::BufRead, sync::Arc};
 
 /// Translate a GAP description of a refiner to a [Refiner] object. This mainly
 /// involves moving from GAP's 1-indexed structures to a 0-indexed structure.
 trait RefinerDescription {
     /// Build a [Box<dyn Refiner>]
     fn build_refiner(&self) -> Box<dyn Refiner>;
+
+    /// Check the constraint only uses the (1-indexed) points [1..`points`]. This must
+    /// be checked before [RefinerDescription::build_refiner] is called, for constraints
+    /// whose refiners require it.
+    fn check_points(&self, _points: usize) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Check every (1-indexed) point in `values` is in [1..`points`]
+fn check_point_range<'a>(values: impl IntoIterator<Item = &'a usize>, points: usize) -> Result<()> {
+    match values.into_iter().find(|&&x| x == 0 || x > points) {
+        Some(x) => bail!("Point {} is not in [1..{}]", x, points),
+        None => Ok(()),
+    }
+}
+
+/// An edge of a digraph sent from GAP, either as a plain target vertex,
+/// or as a `[target, label]` pair. A plain edge has label 0, and labels must
+/// be less than [Digraph::LABEL_LIMIT].
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged, try_from = "DigraphEdgeFields")]
+pub enum DigraphEdge {
+    Plain(usize),
+    Labelled(usize, usize),
+}
+
+/// A [DigraphEdge], before checking its label
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DigraphEdgeFields {
+    Plain(usize),
+    Labelled(usize, usize),
+}
+
+impl TryFrom<DigraphEdgeFields> for DigraphEdge {
+    type Error = String;
+
+    fn try_from(fields: DigraphEdgeFields) -> std::result::Result<Self, String> {
+        match fields {
+            DigraphEdgeFields::Plain(x) => Ok(Self::Plain(x)),
+            DigraphEdgeFields::Labelled(_, label) if label >= Digraph::LABEL_LIMIT => {
+                Err(format!("Edge label {} is too large", label))
+            }
+            DigraphEdgeFields::Labelled(x, label) => Ok(Self::Labelled(x, label)),
+        }
+    }
+}
+
+/// Convert the (1-indexed) out-neighbours of each vertex into the (0-indexed)
+/// (neighbour, label) pairs used by [Digraph::from_labelled_vec]
+pub fn labelled_edges(edges: &[Vec<DigraphEdge>]) -> Vec<Vec<(usize, usize)>> {
+    edges
+        .iter()
+        .map(|v| {
+            v.iter()
+                .map(|e| match *e {
+                    DigraphEdge::Plain(x) => (x - 1, 0),
+                    DigraphEdge::Labelled(x, label) => (x - 1, label),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The inverse of [labelled_edges], where edges with label 0 are unlabelled
+pub fn digraph_edges(edges: &[Vec<(usize, usize)>]) -> Vec<Vec<DigraphEdge>> {
+    edges
+        .iter()
+        .map(|v| {
+            v.iter()
+                .map(|&(x, label)| match label {
+                    0 => DigraphEdge::Plain(x + 1),
+                    _ => DigraphEdge::Labelled(x + 1, label),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Build a [Digraph] from the (1-indexed) out-neighbours of each vertex
+fn build_digraph(edges: &[Vec<DigraphEdge>]) -> Digraph {
+    Digraph::from_labelled_vec(labelled_edges(edges))
+}
+
+/// Convert the (1-indexed) out-neighbours of each vertex into a sorted list of
+/// (source, target, label) triples
+fn build_edge_list(edges: &[Vec<DigraphEdge>]) -> SortedVec<(usize, usize, usize)> {
+    edges
+        .iter()
+        .enumerate()
+        .flat_map(|(i, v)| {
+            v.iter().map(move |e| match *e {
+                DigraphEdge::Plain(x) => (i, x - 1, 0),
+                DigraphEdge::Labelled(x, label) => (i, x - 1, label),
+            })
+        })
+        .collect()
 }
 
 /// Store a Digraph Stabilizer constraint sent from GAP
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DigraphStab {
-    edges: Vec<Vec<usize>>,
+    edges: Vec<Vec<DigraphEdge>>,
 }
 
 impl RefinerDescription for DigraphStab {
     fn build_refiner(&self) -> Box<dyn Refiner> {
-        let edges = self.edges.iter().map(|v| v.iter().map(|x| *x - 1).collect()).collect();
-
-        Box::new(DigraphTransporter::new_stabilizer(Arc::new(Digraph::from_vec(edges))))
+        Box::new(DigraphTransporter::new_stabilizer(Arc::new(build_digraph(&self.edges))))
     }
 }
 
 /// Store a Digraph Transporter constraint sent from GAP
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DigraphTransport {
-    left_edges: Vec<Vec<usize>>,
-    right_edges: Vec<Vec<usize>>,
+    left_edges: Vec<Vec<DigraphEdge>>,
+    right_edges: Vec<Vec<DigraphEdge>>,
 }
 
 impl RefinerDescription for DigraphTransport {
     fn build_refiner(&self) -> Box<dyn Refiner> {
-        let left_edges = self
-            .left_edges
-            .iter()
-            .map(|v| v.iter().map(|x| *x - 1).collect())
-            .collect();
-
-        let right_edges = self
-            .right_edges
-            .iter()
-            .map(|v| v.iter().map(|x| *x - 1).collect())
-            .collect();
-
         Box::new(DigraphTransporter::new_transporter(
-            Arc::new(Digraph::from_vec(left_edges)),
-            Arc::new(Digraph::from_vec(right_edges)),
+            Arc::new(build_digraph(&self.left_edges)),
+            Arc::new(build_digraph(&self.right_edges)),
         ))
     }
 }
 
+/// Check each digraph in `digraphs`, given as (1-indexed) out-neighbours, has at most
+/// `points` vertices, and its edges only join points in [1..`points`]
+fn check_digraph_set_points(digraphs: &[Vec<Vec<DigraphEdge>>], points: usize) -> Result<()> {
+    if let Some(d) = digraphs.iter().find(|d| d.len() > points) {
+        bail!("A digraph with {} vertices does not fit in [1..{}]", d.len(), points);
+    }
+    let targets = digraphs.iter().flatten().flatten().map(|e| match e {
+        DigraphEdge::Plain(x) | DigraphEdge::Labelled(x, _) => x,
+    });
+    check_point_range(targets, points)
+}
+
+/// Store a Digraph Set Stabilizer constraint sent from GAP. The digraphs
+/// can be permuted amongst themselves.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DigraphSetStab {
+    digraphs: Vec<Vec<Vec<DigraphEdge>>>,
+}
+
+impl RefinerDescription for DigraphSetStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let digraphs = self.digraphs.iter().map(|d| build_edge_list(d)).collect();
+        Box::new(DigraphSetTransporter::new_stabilizer(digraphs))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_digraph_set_points(&self.digraphs, points)
+    }
+}
+
+/// Store a Digraph Set Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DigraphSetTransport {
+    left_digraphs: Vec<Vec<Vec<DigraphEdge>>>,
+    right_digraphs: Vec<Vec<Vec<DigraphEdge>>>,
+}
+
+impl RefinerDescription for DigraphSetTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let left_digraphs = self.left_digraphs.iter().map(|d| build_edge_list(d)).collect();
+        let right_digraphs = self.right_digraphs.iter().map(|d| build_edge_list(d)).collect();
+        Box::new(DigraphSetTransporter::new_transporter(left_digraphs, right_digraphs))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_digraph_set_points(&self.left_digraphs, points)?;
+        check_digraph_set_points(&self.right_digraphs, points)
+    }
+}
+
 /// Store a Set Stabilizer constraint sent from GAP
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SetStab {
@@ -132,6 +276,10 @@ impl RefinerDescription for SetSetStab {
         let points = self.points.iter().map(|x| x.iter().map(|&y| y - 1).collect()).collect();
         Box::new(SetSetTransporter::new_stabilizer(points))
     }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(self.points.iter().flatten(), points)
+    }
 }
 
 /// Store a Set Set Transporter constraint sent from GAP
@@ -155,6 +303,10 @@ impl RefinerDescription for SetSetTransport {
             .collect();
         Box::new(SetSetTransporter::new_transporter(left_points, right_points))
     }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(self.left_points.iter().chain(&self.right_points).flatten(), points)
+    }
 }
 
 /// Store a Set Tuple Stabilizer constraint sent from GAP
@@ -168,6 +320,10 @@ impl RefinerDescription for SetTupleStab {
         let points = self.points.iter().map(|x| x.iter().map(|&y| y - 1).collect()).collect();
         Box::new(SetTupleTransporter::new_stabilizer(points))
     }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(self.points.iter().flatten(), points)
+    }
 }
 
 /// Store a Set Set Transporter constraint sent from GAP
@@ -191,6 +347,810 @@ impl RefinerDescription for SetTupleTransport {
             .collect();
         Box::new(SetTupleTransporter::new_transporter(left_points, right_points))
     }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(self.left_points.iter().chain(&self.right_points).flatten(), points)
+    }
+}
+
+/// Store a Multiset Stabilizer constraint sent from GAP. Each point
+/// occurs in `points` as many times as its multiplicity.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MultisetStab {
+    points: Vec<usize>,
+}
+
+impl RefinerDescription for MultisetStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let points = self.points.iter().map(|&x| x - 1).collect();
+        Box::new(MultisetTransporter::new_stabilizer(points))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(&self.points, points)
+    }
+}
+
+/// Store a Multiset Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MultisetTransport {
+    left_points: Vec<usize>,
+    right_points: Vec<usize>,
+}
+
+impl RefinerDescription for MultisetTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let left_points = self.left_points.iter().map(|&x| x - 1).collect();
+        let right_points = self.right_points.iter().map(|&x| x - 1).collect();
+        Box::new(MultisetTransporter::new_transporter(left_points, right_points))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(self.left_points.iter().chain(&self.right_points), points)
+    }
+}
+
+/// Store a Set Multiset Stabilizer constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SetMultisetStab {
+    points: Vec<Vec<usize>>,
+}
+
+impl RefinerDescription for SetMultisetStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let points = self.points.iter().map(|x| x.iter().map(|&y| y - 1).collect()).collect();
+        Box::new(SetMultisetTransporter::new_stabilizer(points))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(self.points.iter().flatten(), points)
+    }
+}
+
+/// Store a Set Multiset Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SetMultisetTransport {
+    left_points: Vec<Vec<usize>>,
+    right_points: Vec<Vec<usize>>,
+}
+
+impl RefinerDescription for SetMultisetTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let left_points = self
+            .left_points
+            .iter()
+            .map(|x| x.iter().map(|&y| y - 1).collect())
+            .collect();
+        let right_points = self
+            .right_points
+            .iter()
+            .map(|x| x.iter().map(|&y| y - 1).collect())
+            .collect();
+        Box::new(SetMultisetTransporter::new_transporter(left_points, right_points))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(self.left_points.iter().chain(&self.right_points).flatten(), points)
+    }
+}
+
+/// A combinatorial structure built by nesting sets, tuples, multisets and
+/// cycles (sequences considered up to rotation), with points at the leaves.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Hash, Deserialize, Serialize)]
+pub enum Structure {
+    Point(usize),
+    Set(Vec<Self>),
+    Tuple(Vec<Self>),
+    Multiset(Vec<Self>),
+    Cycle(Vec<Self>),
+}
+
+impl Structure {
+    /// Apply `f` to every point in the structure
+    pub fn map_points<F: Copy + Fn(usize) -> usize>(&self, f: F) -> Self {
+        let map = |v: &[Self]| v.iter().map(|x| x.map_points(f)).collect();
+        match self {
+            Self::Point(x) => Self::Point(f(*x)),
+            Self::Set(v) => Self::Set(map(v)),
+            Self::Tuple(v) => Self::Tuple(map(v)),
+            Self::Multiset(v) => Self::Multiset(map(v)),
+            Self::Cycle(v) => Self::Cycle(map(v)),
+        }
+    }
+
+    /// Produce the unique representative of this structure, where sets
+    /// and multisets are sorted (and sets have no repeated members), and cycles
+    /// are rotated to start from their smallest rotation.
+    pub fn canonicalise(&self) -> Self {
+        let canon = |v: &[Self]| v.iter().map(|x| x.canonicalise()).collect::<Vec<_>>();
+        match self {
+            Self::Point(x) => Self::Point(*x),
+            Self::Set(v) => {
+                let mut v = canon(v);
+                v.sort();
+                v.dedup();
+                Self::Set(v)
+            }
+            Self::Tuple(v) => Self::Tuple(canon(v)),
+            Self::Multiset(v) => {
+                let mut v = canon(v);
+                v.sort();
+                Self::Multiset(v)
+            }
+            Self::Cycle(v) => Self::Cycle(min_rotation(&canon(v))),
+        }
+    }
+
+    /// The number of sets, tuples, multisets and cycles in the structure
+    pub fn inner_nodes(&self) -> usize {
+        match self {
+            Self::Point(_) => 0,
+            Self::Set(v) | Self::Tuple(v) | Self::Multiset(v) | Self::Cycle(v) => {
+                1 + v.iter().map(|x| x.inner_nodes()).sum::<usize>()
+            }
+        }
+    }
+}
+
+/// Store a Structure Stabilizer constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StructureStab {
+    structure: Structure,
+}
+
+impl RefinerDescription for StructureStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let structure = self.structure.map_points(|x| x - 1);
+        Box::new(StructureTransporter::new_stabilizer(structure))
+    }
+}
+
+/// Store a Structure Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StructureTransport {
+    left_structure: Structure,
+    right_structure: Structure,
+}
+
+impl RefinerDescription for StructureTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let left_structure = self.left_structure.map_points(|x| x - 1);
+        let right_structure = self.right_structure.map_points(|x| x - 1);
+        Box::new(StructureTransporter::new_transporter(left_structure, right_structure))
+    }
+}
+
+/// Deserialize the cells of a partition, checking they are disjoint
+fn partition_cells<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<Vec<usize>>, D::Error> {
+    let cells = Vec::<Vec<usize>>::deserialize(deserializer)?;
+    let mut seen = HashSet::new();
+    match cells.iter().flatten().find(|&&x| !seen.insert(x)) {
+        Some(x) => Err(D::Error::custom(format!(
+            "Point {} is in more than one cell of a partition",
+            x
+        ))),
+        None => Ok(cells),
+    }
+}
+
+/// Store an Ordered Partition Stabilizer constraint sent from GAP. The cells of the
+/// partition must be disjoint. Any point in no cell is treated as being in an extra cell.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OrderedPartitionStab {
+    #[serde(deserialize_with = "partition_cells")]
+    cells: Vec<Vec<usize>>,
+}
+
+impl RefinerDescription for OrderedPartitionStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let cells = self.cells.iter().map(|x| x.iter().map(|&y| y - 1).collect()).collect();
+        Box::new(OrderedPartitionTransporter::new_stabilizer(cells))
+    }
+}
+
+/// Store an Ordered Partition Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OrderedPartitionTransport {
+    #[serde(deserialize_with = "partition_cells")]
+    left_cells: Vec<Vec<usize>>,
+    #[serde(deserialize_with = "partition_cells")]
+    right_cells: Vec<Vec<usize>>,
+}
+
+impl RefinerDescription for OrderedPartitionTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let left_cells = self
+            .left_cells
+            .iter()
+            .map(|x| x.iter().map(|&y| y - 1).collect())
+            .collect();
+        let right_cells = self
+            .right_cells
+            .iter()
+            .map(|x| x.iter().map(|&y| y - 1).collect())
+            .collect();
+        Box::new(OrderedPartitionTransporter::new_transporter(left_cells, right_cells))
+    }
+}
+
+/// Store an Unordered Partition Stabilizer constraint sent from GAP. The cells of the
+/// partition must be disjoint. Any point in no cell is treated as being in an extra cell.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UnorderedPartitionStab {
+    #[serde(deserialize_with = "partition_cells")]
+    cells: Vec<Vec<usize>>,
+}
+
+impl RefinerDescription for UnorderedPartitionStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let cells = self.cells.iter().map(|x| x.iter().map(|&y| y - 1).collect()).collect();
+        Box::new(UnorderedPartitionTransporter::new_stabilizer(cells))
+    }
+}
+
+/// Store an Unordered Partition Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UnorderedPartitionTransport {
+    #[serde(deserialize_with = "partition_cells")]
+    left_cells: Vec<Vec<usize>>,
+    #[serde(deserialize_with = "partition_cells")]
+    right_cells: Vec<Vec<usize>>,
+}
+
+impl RefinerDescription for UnorderedPartitionTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let left_cells = self
+            .left_cells
+            .iter()
+            .map(|x| x.iter().map(|&y| y - 1).collect())
+            .collect();
+        let right_cells = self
+            .right_cells
+            .iter()
+            .map(|x| x.iter().map(|&y| y - 1).collect())
+            .collect();
+        Box::new(UnorderedPartitionTransporter::new_transporter(left_cells, right_cells))
+    }
+}
+
+/// A relation in a relational structure, given as a set of tuples.
+/// `label` distinguishes the relations in a structure.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Relation {
+    label: usize,
+    tuples: Vec<Vec<usize>>,
+}
+
+/// Convert a list of (1-indexed) relations into (label, tuple) pairs
+fn relation_tuples(relations: &[Relation]) -> SortedVec<(usize, Vec<usize>)> {
+    relations
+        .iter()
+        .flat_map(|r| {
+            r.tuples
+                .iter()
+                .map(move |t| (r.label, t.iter().map(|&y| y - 1).collect()))
+        })
+        .collect()
+}
+
+/// Check every point in the tuples of `relations` is in [1..`points`]
+fn check_relation_points(relations: &[Relation], points: usize) -> Result<()> {
+    check_point_range(relations.iter().flat_map(|r| &r.tuples).flatten(), points)
+}
+
+/// Store a Relational Structure Stabilizer constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RelationalStructureStab {
+    relations: Vec<Relation>,
+}
+
+impl RefinerDescription for RelationalStructureStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        Box::new(RelationalStructureTransporter::new_stabilizer(relation_tuples(
+            &self.relations,
+        )))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_relation_points(&self.relations, points)
+    }
+}
+
+/// Store a Relational Structure Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RelationalStructureTransport {
+    left_relations: Vec<Relation>,
+    right_relations: Vec<Relation>,
+}
+
+impl RefinerDescription for RelationalStructureTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        Box::new(RelationalStructureTransporter::new_transporter(
+            relation_tuples(&self.left_relations),
+            relation_tuples(&self.right_relations),
+        ))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_relation_points(&self.left_relations, points)?;
+        check_relation_points(&self.right_relations, points)
+    }
+}
+
+/// A value sent from GAP, used to label objects. Labels are totally ordered,
+/// first by type (in the order below), and then by value.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Hash, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Label {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<Self>),
+}
+
+/// Store a Labelled Points Stabilizer constraint sent from GAP. Point `i`
+/// has label `labels[i-1]`, and points larger than `labels.len()` are unlabelled.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LabelledPointsStab {
+    labels: Vec<Label>,
+}
+
+impl RefinerDescription for LabelledPointsStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        Box::new(LabelledPointsTransporter::new_stabilizer(self.labels.clone()))
+    }
+}
+
+/// Store a Labelled Points Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LabelledPointsTransport {
+    left_labels: Vec<Label>,
+    right_labels: Vec<Label>,
+}
+
+impl RefinerDescription for LabelledPointsTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        Box::new(LabelledPointsTransporter::new_transporter(
+            self.left_labels.clone(),
+            self.right_labels.clone(),
+        ))
+    }
+}
+
+/// The number of rows and columns of `matrix`, checking all its rows have the same length
+fn matrix_dimensions<T>(matrix: &[Vec<T>]) -> std::result::Result<(usize, usize), String> {
+    let cols = matrix.first().map_or(0, |r| r.len());
+    match matrix.iter().position(|r| r.len() != cols) {
+        Some(i) => Err(format!(
+            "Row {} of a matrix has length {}, but row 1 has length {}",
+            i + 1,
+            matrix[i].len(),
+            cols
+        )),
+        None => Ok((matrix.len(), cols)),
+    }
+}
+
+/// Deserialize a matrix, checking all its rows have the same length
+fn matrix<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<Vec<Label>>, D::Error> {
+    let matrix = Vec::<Vec<Label>>::deserialize(deserializer)?;
+    matrix_dimensions(&matrix).map_err(D::Error::custom)?;
+    Ok(matrix)
+}
+
+/// Store a Matrix Stabilizer constraint sent from GAP. For an r×c matrix,
+/// the rows are the points [1..r] and the columns are the points [r+1..r+c].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MatrixStab {
+    #[serde(deserialize_with = "matrix")]
+    matrix: Vec<Vec<Label>>,
+}
+
+impl RefinerDescription for MatrixStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        Box::new(MatrixTransporter::new_stabilizer(self.matrix.clone()))
+    }
+}
+
+/// Store a Matrix Transporter constraint sent from GAP. The matrices must have
+/// the same dimensions.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(try_from = "MatrixTransportFields")]
+pub struct MatrixTransport {
+    left_matrix: Vec<Vec<Label>>,
+    right_matrix: Vec<Vec<Label>>,
+}
+
+/// A [MatrixTransport], before checking the matrices have the same dimensions
+#[derive(Deserialize)]
+struct MatrixTransportFields {
+    #[serde(deserialize_with = "matrix")]
+    left_matrix: Vec<Vec<Label>>,
+    #[serde(deserialize_with = "matrix")]
+    right_matrix: Vec<Vec<Label>>,
+}
+
+impl TryFrom<MatrixTransportFields> for MatrixTransport {
+    type Error = String;
+
+    fn try_from(fields: MatrixTransportFields) -> std::result::Result<Self, String> {
+        let left = matrix_dimensions(&fields.left_matrix)?;
+        let right = matrix_dimensions(&fields.right_matrix)?;
+        if left != right {
+            return Err(format!(
+                "Can not transport a {}x{} matrix to a {}x{} matrix",
+                left.0, left.1, right.0, right.1
+            ));
+        }
+        Ok(Self {
+            left_matrix: fields.left_matrix,
+            right_matrix: fields.right_matrix,
+        })
+    }
+}
+
+impl RefinerDescription for MatrixTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        Box::new(MatrixTransporter::new_transporter(
+            self.left_matrix.clone(),
+            self.right_matrix.clone(),
+        ))
+    }
+}
+
+/// A permutation of the rows, and a permutation of the columns, of a matrix (1-indexed)
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RowColumnPermutation {
+    pub rows: Vec<usize>,
+    pub columns: Vec<usize>,
+}
+
+impl RowColumnPermutation {
+    /// Split `p`, which permutes the rows `0..rows` and columns `rows..rows+cols`
+    /// of a matrix, or return `None` if `p` does not map rows to rows and columns to columns
+    pub fn split(p: &Permutation, rows: usize, cols: usize) -> Option<Self> {
+        let (row_perm, col_perm) = split_row_column_permutation(p, rows, cols)?;
+        Some(Self {
+            rows: (0..rows).map(|i| row_perm.apply(i) + 1).collect(),
+            columns: (0..cols).map(|i| col_perm.apply(i) + 1).collect(),
+        })
+    }
+}
+
+/// The solutions of a problem, split into row and column permutations of
+/// the matrices in one of its matrix constraints
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MatrixSolutions {
+    /// The position of the matrix constraint in the problem (1-indexed)
+    pub constraint: usize,
+    /// The row and column permutations of each solution
+    pub sols: Vec<RowColumnPermutation>,
+    /// The row and column permutations of the canonical permutation, if it
+    /// maps rows to rows and columns to columns
+    pub canonical: Option<RowColumnPermutation>,
+}
+
+/// Split `solutions` into row and column permutations, for each matrix constraint in `constraints`
+pub fn matrix_solutions(constraints: &[Constraint], solutions: &Solutions) -> Vec<MatrixSolutions> {
+    constraints
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| {
+            let (rows, cols) = c.matrix_dimensions()?;
+            Some(MatrixSolutions {
+                constraint: i + 1,
+                // Every solution satisfies the constraint, so can be split
+                sols: solutions
+                    .get()
+                    .iter()
+                    .filter_map(|p| RowColumnPermutation::split(p, rows, cols))
+                    .collect(),
+                canonical: solutions
+                    .get_canonical()
+                    .as_ref()
+                    .and_then(|c| RowColumnPermutation::split(&c.perm, rows, cols)),
+            })
+        })
+        .collect()
+}
+
+/// Deserialize the size of the field of a linear code, checking it is prime
+fn field_size<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<usize, D::Error> {
+    let q = usize::deserialize(deserializer)?;
+    if q < 2 || (2..).take_while(|d| d * d <= q).any(|d| q.is_multiple_of(d)) {
+        return Err(D::Error::custom(format!(
+            "Codes are only supported over fields of prime size, not {}",
+            q
+        )));
+    }
+    Ok(q)
+}
+
+/// Deserialize a list of codewords, checking they all have the same length
+fn codewords<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<Vec<usize>>, D::Error> {
+    let codewords = Vec::<Vec<usize>>::deserialize(deserializer)?;
+    let length = code_length(&codewords);
+    match codewords.iter().position(|w| w.len() != length) {
+        Some(i) => Err(D::Error::custom(format!(
+            "Codeword {} has length {}, but codeword 1 has length {}",
+            i + 1,
+            codewords[i].len(),
+            length
+        ))),
+        None => Ok(codewords),
+    }
+}
+
+/// Check every entry of `codewords` is in the field of size `q`
+fn check_codeword_entries(codewords: &[Vec<usize>], q: usize) -> std::result::Result<(), String> {
+    match codewords.iter().flatten().find(|&&x| x >= q) {
+        Some(x) => Err(format!("Codeword entry {} is not in the field of size {}", x, q)),
+        None => Ok(()),
+    }
+}
+
+/// Check a code of length `length` fits in the points [1..`points`]
+fn check_code_length(length: usize, points: usize) -> Result<()> {
+    if length > points {
+        bail!("A code of length {} does not fit in [1..{}]", length, points);
+    }
+    Ok(())
+}
+
+/// Store a Linear Code Stabilizer constraint sent from GAP. The code is spanned by
+/// `codewords` (which may be a generator matrix, or any list of codewords) over the
+/// field of size `q`, and the coordinates are the points [1..length].
+///
+/// Only prime `q` is supported, and every entry of the codewords must be less than `q`.
+/// Refinement uses the minimum weight codewords, which are only found when the code has
+/// at most 2^20 codewords. Larger codes are still searched correctly, but with much
+/// weaker refinement.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(try_from = "LinearCodeStabFields")]
+pub struct LinearCodeStab {
+    q: usize,
+    codewords: Vec<Vec<usize>>,
+}
+
+/// A [LinearCodeStab], before checking the entries of the codewords are in the field
+#[derive(Deserialize)]
+struct LinearCodeStabFields {
+    #[serde(deserialize_with = "field_size")]
+    q: usize,
+    #[serde(deserialize_with = "codewords")]
+    codewords: Vec<Vec<usize>>,
+}
+
+impl TryFrom<LinearCodeStabFields> for LinearCodeStab {
+    type Error = String;
+
+    fn try_from(fields: LinearCodeStabFields) -> std::result::Result<Self, String> {
+        check_codeword_entries(&fields.codewords, fields.q)?;
+        Ok(Self {
+            q: fields.q,
+            codewords: fields.codewords,
+        })
+    }
+}
+
+impl RefinerDescription for LinearCodeStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let length = code_length(&self.codewords);
+        Box::new(CodeTransporter::new_stabilizer(
+            length,
+            self.q,
+            true,
+            self.codewords.clone(),
+        ))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_code_length(code_length(&self.codewords), points)
+    }
+}
+
+/// Store a Linear Code Transporter constraint sent from GAP, with the same
+/// restrictions as [LinearCodeStab]. The codes must have the same length.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(try_from = "LinearCodeTransportFields")]
+pub struct LinearCodeTransport {
+    q: usize,
+    left_codewords: Vec<Vec<usize>>,
+    right_codewords: Vec<Vec<usize>>,
+}
+
+/// A [LinearCodeTransport], before checking the codes have the same length
+#[derive(Deserialize)]
+struct LinearCodeTransportFields {
+    #[serde(deserialize_with = "field_size")]
+    q: usize,
+    #[serde(deserialize_with = "codewords")]
+    left_codewords: Vec<Vec<usize>>,
+    #[serde(deserialize_with = "codewords")]
+    right_codewords: Vec<Vec<usize>>,
+}
+
+impl TryFrom<LinearCodeTransportFields> for LinearCodeTransport {
+    type Error = String;
+
+    fn try_from(fields: LinearCodeTransportFields) -> std::result::Result<Self, String> {
+        let left = code_length(&fields.left_codewords);
+        let right = code_length(&fields.right_codewords);
+        if !fields.left_codewords.is_empty() && !fields.right_codewords.is_empty() && left != right {
+            return Err(format!(
+                "Can not transport a code of length {} to a code of length {}",
+                left, right
+            ));
+        }
+        check_codeword_entries(&fields.left_codewords, fields.q)?;
+        check_codeword_entries(&fields.right_codewords, fields.q)?;
+        Ok(Self {
+            q: fields.q,
+            left_codewords: fields.left_codewords,
+            right_codewords: fields.right_codewords,
+        })
+    }
+}
+
+impl RefinerDescription for LinearCodeTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let length = code_length(&self.left_codewords).max(code_length(&self.right_codewords));
+        Box::new(CodeTransporter::new_transporter(
+            length,
+            self.q,
+            true,
+            self.left_codewords.clone(),
+            self.right_codewords.clone(),
+        ))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_code_length(
+            code_length(&self.left_codewords).max(code_length(&self.right_codewords)),
+            points,
+        )
+    }
+}
+
+fn code_length(codewords: &[Vec<usize>]) -> usize {
+    codewords.first().map_or(0, |w| w.len())
+}
+
+/// Deserialize the truth table of a Boolean function, checking its length is a power of two
+fn truth_table<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<bool>, D::Error> {
+    let truth_table = Vec::<bool>::deserialize(deserializer)?;
+    if !truth_table.len().is_power_of_two() {
+        return Err(D::Error::custom(format!(
+            "Truth table has length {}, which is not a power of two",
+            truth_table.len()
+        )));
+    }
+    Ok(truth_table)
+}
+
+/// The support of a Boolean function on `n` variables, given by its truth table
+/// of length `2^n`. Bit `j` of each index into the truth table is the value of variable `j`.
+fn boolean_function_support(truth_table: &[bool]) -> (usize, Vec<Vec<usize>>) {
+    let n = truth_table.len().trailing_zeros() as usize;
+    let support = truth_table
+        .iter()
+        .enumerate()
+        .filter(|(_, &b)| b)
+        .map(|(i, _)| (0..n).map(|j| (i >> j) & 1).collect())
+        .collect();
+    (n, support)
+}
+
+/// Store a Boolean Function Stabilizer constraint sent from GAP, for permutations
+/// of the variables. The variables are the points [1..n].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BooleanFunctionStab {
+    #[serde(deserialize_with = "truth_table")]
+    truth_table: Vec<bool>,
+}
+
+impl RefinerDescription for BooleanFunctionStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let (n, support) = boolean_function_support(&self.truth_table);
+        Box::new(CodeTransporter::new_stabilizer(n, 2, false, support))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_code_length(boolean_function_support(&self.truth_table).0, points)
+    }
+}
+
+/// Store a Boolean Function Transporter constraint sent from GAP. The functions
+/// must have the same number of variables.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(try_from = "BooleanFunctionTransportFields")]
+pub struct BooleanFunctionTransport {
+    left_truth_table: Vec<bool>,
+    right_truth_table: Vec<bool>,
+}
+
+/// A [BooleanFunctionTransport], before checking the functions have the same number of variables
+#[derive(Deserialize)]
+struct BooleanFunctionTransportFields {
+    #[serde(deserialize_with = "truth_table")]
+    left_truth_table: Vec<bool>,
+    #[serde(deserialize_with = "truth_table")]
+    right_truth_table: Vec<bool>,
+}
+
+impl TryFrom<BooleanFunctionTransportFields> for BooleanFunctionTransport {
+    type Error = String;
+
+    fn try_from(fields: BooleanFunctionTransportFields) -> std::result::Result<Self, String> {
+        if fields.left_truth_table.len() != fields.right_truth_table.len() {
+            return Err("Boolean functions must have the same number of variables".to_string());
+        }
+        Ok(Self {
+            left_truth_table: fields.left_truth_table,
+            right_truth_table: fields.right_truth_table,
+        })
+    }
+}
+
+impl RefinerDescription for BooleanFunctionTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let (n, left_support) = boolean_function_support(&self.left_truth_table);
+        let (_, right_support) = boolean_function_support(&self.right_truth_table);
+        Box::new(CodeTransporter::new_transporter(
+            n,
+            2,
+            false,
+            left_support,
+            right_support,
+        ))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_code_length(boolean_function_support(&self.left_truth_table).0, points)
+    }
+}
+
+/// Store a Necklace Stabilizer constraint sent from GAP. This is a sequence of
+/// points, which can be rotated and (if `directed` is false) reflected.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NecklaceStab {
+    points: Vec<usize>,
+    directed: bool,
+}
+
+impl RefinerDescription for NecklaceStab {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let points = self.points.iter().map(|&x| x - 1).collect();
+        Box::new(NecklaceTransporter::new_stabilizer(points, self.directed))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(&self.points, points)
+    }
+}
+
+/// Store a Necklace Transporter constraint sent from GAP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NecklaceTransport {
+    left_points: Vec<usize>,
+    right_points: Vec<usize>,
+    directed: bool,
+}
+
+impl RefinerDescription for NecklaceTransport {
+    fn build_refiner(&self) -> Box<dyn Refiner> {
+        let left_points = self.left_points.iter().map(|&x| x - 1).collect();
+        let right_points = self.right_points.iter().map(|&x| x - 1).collect();
+        Box::new(NecklaceTransporter::new_transporter(
+            left_points,
+            right_points,
+            self.directed,
+        ))
+    }
+
+    fn check_points(&self, points: usize) -> Result<()> {
+        check_point_range(self.left_points.iter().chain(&self.right_points), points)
+    }
 }
 
 /// Store a Symmetric Group constraint sent from GAP
@@ -224,6 +1184,8 @@ impl RefinerDescription for GapRefiner {
 pub enum Constraint {
     DigraphStab(DigraphStab),
     DigraphTransport(DigraphTransport),
+    DigraphSetStab(DigraphSetStab),
+    DigraphSetTransport(DigraphSetTransport),
     SetStab(SetStab),
     SetTransport(SetTransport),
     TupleStab(TupleStab),
@@ -232,11 +1194,69 @@ pub enum Constraint {
     SetSetTransport(SetSetTransport),
     SetTupleStab(SetTupleStab),
     SetTupleTransport(SetTupleTransport),
+    MultisetStab(MultisetStab),
+    MultisetTransport(MultisetTransport),
+    SetMultisetStab(SetMultisetStab),
+    SetMultisetTransport(SetMultisetTransport),
+    StructureStab(StructureStab),
+    StructureTransport(StructureTransport),
+    OrderedPartitionStab(OrderedPartitionStab),
+    OrderedPartitionTransport(OrderedPartitionTransport),
+    UnorderedPartitionStab(UnorderedPartitionStab),
+    UnorderedPartitionTransport(UnorderedPartitionTransport),
+    RelationalStructureStab(RelationalStructureStab),
+    RelationalStructureTransport(RelationalStructureTransport),
+    LabelledPointsStab(LabelledPointsStab),
+    LabelledPointsTransport(LabelledPointsTransport),
+    MatrixStab(MatrixStab),
+    MatrixTransport(MatrixTransport),
+    LinearCodeStab(LinearCodeStab),
+    LinearCodeTransport(LinearCodeTransport),
+    BooleanFunctionStab(BooleanFunctionStab),
+    BooleanFunctionTransport(BooleanFunctionTransport),
+    NecklaceStab(NecklaceStab),
+    NecklaceTransport(NecklaceTransport),
     InSymmetricGroup(InSymmetricGroup),
     GapRefiner(GapRefiner),
 }
 
+impl Constraint {
+    /// The number of rows and columns of the matrices in a matrix constraint
+    pub fn matrix_dimensions(&self) -> Option<(usize, usize)> {
+        let matrix = match self {
+            Self::MatrixStab(c) => &c.matrix,
+            Self::MatrixTransport(c) => &c.left_matrix,
+            _ => return None,
+        };
+        matrix_dimensions(matrix).ok()
+    }
+}
+
 impl RefinerDescription for Constraint {
+    fn check_points(&self, points: usize) -> Result<()> {
+        match self {
+            Self::LinearCodeStab(c) => c.check_points(points),
+            Self::LinearCodeTransport(c) => c.check_points(points),
+            Self::BooleanFunctionStab(c) => c.check_points(points),
+            Self::BooleanFunctionTransport(c) => c.check_points(points),
+            Self::DigraphSetStab(c) => c.check_points(points),
+            Self::DigraphSetTransport(c) => c.check_points(points),
+            Self::SetSetStab(c) => c.check_points(points),
+            Self::SetSetTransport(c) => c.check_points(points),
+            Self::SetTupleStab(c) => c.check_points(points),
+            Self::SetTupleTransport(c) => c.check_points(points),
+            Self::MultisetStab(c) => c.check_points(points),
+            Self::MultisetTransport(c) => c.check_points(points),
+            Self::SetMultisetStab(c) => c.check_points(points),
+            Self::SetMultisetTransport(c) => c.check_points(points),
+            Self::RelationalStructureStab(c) => c.check_points(points),
+            Self::RelationalStructureTransport(c) => c.check_points(points),
+            Self::NecklaceStab(c) => c.check_points(points),
+            Self::NecklaceTransport(c) => c.check_points(points),
+            _ => Ok(()),
+        }
+    }
+
     fn build_refiner(&self) -> Box<dyn Refiner> {
         match self {
             Self::DigraphStab(c) => c.build_refiner(),
@@ -250,6 +1270,30 @@ impl RefinerDescription for Constraint {
             Self::TupleTransport(c) => c.build_refiner(),
             Self::SetSetTransport(c) => c.build_refiner(),
             Self::SetTupleTransport(c) => c.build_refiner(),
+            Self::MultisetStab(c) => c.build_refiner(),
+            Self::MultisetTransport(c) => c.build_refiner(),
+            Self::SetMultisetStab(c) => c.build_refiner(),
+            Self::SetMultisetTransport(c) => c.build_refiner(),
+            Self::StructureStab(c) => c.build_refiner(),
+            Self::StructureTransport(c) => c.build_refiner(),
+            Self::OrderedPartitionStab(c) => c.build_refiner(),
+            Self::OrderedPartitionTransport(c) => c.build_refiner(),
+            Self::UnorderedPartitionStab(c) => c.build_refiner(),
+            Self::UnorderedPartitionTransport(c) => c.build_refiner(),
+            Self::RelationalStructureStab(c) => c.build_refiner(),
+            Self::RelationalStructureTransport(c) => c.build_refiner(),
+            Self::LabelledPointsStab(c) => c.build_refiner(),
+            Self::LabelledPointsTransport(c) => c.build_refiner(),
+            Self::MatrixStab(c) => c.build_refiner(),
+            Self::MatrixTransport(c) => c.build_refiner(),
+            Self::DigraphSetStab(c) => c.build_refiner(),
+            Self::DigraphSetTransport(c) => c.build_refiner(),
+            Self::LinearCodeStab(c) => c.build_refiner(),
+            Self::LinearCodeTransport(c) => c.build_refiner(),
+            Self::BooleanFunctionStab(c) => c.build_refiner(),
+            Self::BooleanFunctionTransport(c) => c.build_refiner(),
+            Self::NecklaceStab(c) => c.build_refiner(),
+            Self::NecklaceTransport(c) => c.build_refiner(),
             Self::InSymmetricGroup(c) => c.build_refiner(),
         }
     }
@@ -264,6 +1308,10 @@ pub struct ProblemConfig {
     pub find_coset: bool,
     /// Find canonical image
     pub find_canonical: bool,
+    /// The index in `constraints` of the constraint for the group we are searching in.
+    /// This must be given when `find_canonical` is set.
+    #[serde(default)]
+    pub group: Option<usize>,
     /// Only perform root search
     pub root_search: bool,
     /// Search configuration
@@ -284,6 +1332,102 @@ pub fn build_constraints(constraints: &[Constraint]) -> Vec<Box<dyn Refiner>> {
     constraints.iter().map(|x| x.build_refiner()).collect()
 }
 
+/// Check every constraint only uses the (1-indexed) points [1..`points`]
+pub fn check_constraint_points(constraints: &[Constraint], points: usize) -> Result<()> {
+    for (i, c) in constraints.iter().enumerate() {
+        c.check_points(points)
+            .with_context(|| format!("Constraint {} is invalid", i))?;
+    }
+    Ok(())
+}
+
+/// Build the refiners for `problem`, checking the constraints and group constraint are valid
+pub fn build_refiner_store(problem: &Problem) -> Result<RefinerStore> {
+    check_constraint_points(&problem.constraints, problem.config.points)?;
+    let refiners = RefinerStore::new_from_refiners(build_constraints(&problem.constraints));
+    match problem.config.group {
+        Some(group) => {
+            if group >= problem.constraints.len() {
+                bail!("Group constraint {} does not exist", group);
+            }
+            if !refiners.is_group(group) {
+                bail!("Group constraint {} does not define a group", group);
+            }
+            Ok(refiners.with_group(group))
+        }
+        None if problem.config.find_canonical => {
+            bail!("Finding a canonical image requires a group constraint")
+        }
+        None => Ok(refiners),
+    }
+}
+
+/// A structure on the points `0..points`, whose automorphisms are the permutations
+/// which satisfy every constraint. Every constraint must be a stabilizer.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchStructure {
+    pub points: usize,
+    pub constraints: Vec<Constraint>,
+}
+
+impl BatchStructure {
+    /// Build the refiners for the constraints, checking they are valid and are all for groups
+    pub fn build_refiners(&self) -> Result<Vec<Box<dyn Refiner>>> {
+        if self.constraints.iter().any(|c| matches!(c, Constraint::GapRefiner(_))) {
+            bail!("GAP refiners can only be used from GAP");
+        }
+        check_constraint_points(&self.constraints, self.points)?;
+        let refiners = build_constraints(&self.constraints);
+        if let Some(i) = refiners.iter().position(|r| !r.is_group()) {
+            bail!("Constraint {} is not a stabilizer", i);
+        }
+        Ok(refiners)
+    }
+}
+
+/// One structure in a batch to be classified up to isomorphism, given either as
+/// a [BatchStructure] or as a [Problem] (whose constraints are used in the same way)
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum BatchInput {
+    Problem(Problem),
+    Structure(BatchStructure),
+}
+
+impl BatchInput {
+    /// Convert the input to a [BatchStructure]
+    pub fn into_structure(self) -> Result<BatchStructure> {
+        match self {
+            Self::Structure(s) => Ok(s),
+            Self::Problem(p) if p.config.group.is_some() => {
+                bail!("Problems can only be classified in the symmetric group")
+            }
+            Self::Problem(p) => Ok(BatchStructure {
+                points: p.config.points,
+                constraints: p.constraints,
+            }),
+        }
+    }
+}
+
+/// Read a digraph given as the (1-indexed) out-neighbours of each vertex, where a
+/// neighbour is either a vertex or a [vertex, label] pair, as [labelled_edges]
+pub fn read_json_digraph(s: &str) -> Result<Vec<Vec<(usize, usize)>>> {
+    let edges: Vec<Vec<DigraphEdge>> = serde_json::from_str(s)?;
+    if edges
+        .iter()
+        .flatten()
+        .any(|e| matches!(e, DigraphEdge::Plain(0) | DigraphEdge::Labelled(0, _)))
+    {
+        bail!("Vertices are numbered from 1");
+    }
+    let edges = labelled_edges(&edges);
+    if let Some(&(x, _)) = edges.iter().flatten().find(|&&(x, _)| x >= edges.len()) {
+        bail!("Vertex {} is not in a digraph with {} vertices", x + 1, edges.len());
+    }
+    Ok(edges)
+}
+
 /// Read a `Problem` from an input stream (Problem should be in JSON)
 pub fn read_problem<R: BufRead>(prob: &mut R) -> Result<Problem> {
     let mut line = String::new();
@@ -293,3 +1437,134 @@ pub fn read_problem<R: BufRead>(prob: &mut R) -> Result<Problem> {
     assert!(parsed.config.points > 1, "Problems must have at least two points");
     Ok(parsed)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{check_constraint_points, matrix_solutions, Constraint, RowColumnPermutation};
+    use crate::perm::Permutation;
+    use crate::vole::solutions::Solutions;
+
+    fn parse(json: &str) -> Result<Constraint, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn invalid_partitions() {
+        assert!(parse(r#"{"OrderedPartitionStab": {"cells": [[1, 2], [3]]}}"#).is_ok());
+        assert!(parse(r#"{"OrderedPartitionStab": {"cells": [[1, 2], [2]]}}"#).is_err());
+        assert!(parse(r#"{"UnorderedPartitionTransport": {"left_cells": [[1]], "right_cells": [[1, 1]]}}"#).is_err());
+    }
+
+    #[test]
+    fn invalid_edge_labels() {
+        assert!(parse(r#"{"DigraphStab": {"edges": [[[2, 5]], [1]]}}"#).is_ok());
+        let too_large = format!(r#"{{"DigraphStab": {{"edges": [[[2, {}]], [1]]}}}}"#, usize::MAX / 2);
+        assert!(parse(&too_large).is_err());
+        assert!(super::read_json_digraph(&format!("[[[2, {}]], []]", usize::MAX)).is_err());
+    }
+
+    /// Check the constraint `json` can only be used on at least `min_points` points
+    fn assert_min_points(json: &str, min_points: usize) {
+        let constraints = [parse(json).unwrap()];
+        assert!(check_constraint_points(&constraints, min_points).is_ok());
+        assert!(check_constraint_points(&constraints, min_points - 1).is_err());
+    }
+
+    #[test]
+    fn invalid_points() {
+        assert_min_points(r#"{"SetSetStab": {"points": [[1, 2], [4]]}}"#, 4);
+        assert_min_points(
+            r#"{"SetTupleTransport": {"left_points": [[1]], "right_points": [[3]]}}"#,
+            3,
+        );
+        assert_min_points(r#"{"MultisetStab": {"points": [2, 2, 5]}}"#, 5);
+        assert_min_points(r#"{"MultisetTransport": {"left_points": [6], "right_points": [1]}}"#, 6);
+        assert_min_points(r#"{"SetMultisetStab": {"points": [[1, 1], [3]]}}"#, 3);
+        assert_min_points(
+            r#"{"RelationalStructureStab": {"relations": [{"label": 1, "tuples": [[1, 7]]}]}}"#,
+            7,
+        );
+
+        assert_min_points(r#"{"NecklaceStab": {"points": [1, 3, 2], "directed": true}}"#, 3);
+        assert_min_points(
+            r#"{"NecklaceTransport": {"left_points": [1, 2], "right_points": [4, 2], "directed": false}}"#,
+            4,
+        );
+
+        assert_min_points(r#"{"DigraphSetStab": {"digraphs": [[[2], [3], []]]}}"#, 3);
+        assert_min_points(
+            r#"{"DigraphSetTransport": {"left_digraphs": [[[2]]], "right_digraphs": [[[], [[4, 1]]]]}}"#,
+            4,
+        );
+
+        assert_min_points(r#"{"LinearCodeStab": {"q": 2, "codewords": [[1, 0, 1]]}}"#, 3);
+        assert_min_points(
+            r#"{"LinearCodeTransport": {"q": 3, "left_codewords": [[1, 2]], "right_codewords": [[2, 1]]}}"#,
+            2,
+        );
+        assert_min_points(
+            r#"{"BooleanFunctionStab": {"truth_table": [false, true, true, true]}}"#,
+            2,
+        );
+        assert_min_points(
+            r#"{"BooleanFunctionTransport": {"left_truth_table": [false, true], "right_truth_table": [true, false]}}"#,
+            1,
+        );
+
+        let zero = [parse(r#"{"MultisetStab": {"points": [0, 1]}}"#).unwrap()];
+        assert!(check_constraint_points(&zero, 5).is_err());
+    }
+
+    #[test]
+    fn invalid_matrices() {
+        assert!(parse(r#"{"MatrixStab": {"matrix": [[1, 2], [3, 4]]}}"#).is_ok());
+        assert!(parse(r#"{"MatrixStab": {"matrix": [[1, 2], [3]]}}"#).is_err());
+        assert!(parse(r#"{"MatrixTransport": {"left_matrix": [[1, 2]], "right_matrix": [[1], [2]]}}"#).is_err());
+    }
+
+    #[test]
+    fn split_matrix_solutions() {
+        let constraints = vec![
+            parse(r#"{"SetStab": {"points": [1]}}"#).unwrap(),
+            parse(r#"{"MatrixStab": {"matrix": [[1, 2, 3], [1, 2, 3]]}}"#).unwrap(),
+        ];
+        assert_eq!(constraints[1].matrix_dimensions(), Some((2, 3)));
+
+        // Swap the rows, and the last two columns
+        let mut sols = Solutions::new(5);
+        sols.add_solution(&Permutation::from_vec(vec![1, 0, 2, 4, 3]));
+        let split = matrix_solutions(&constraints, &sols);
+        assert_eq!(split.len(), 1);
+        assert_eq!(split[0].constraint, 2);
+        assert_eq!(
+            split[0].sols,
+            vec![RowColumnPermutation {
+                rows: vec![2, 1],
+                columns: vec![1, 3, 2],
+            }]
+        );
+        assert!(split[0].canonical.is_none());
+
+        let mixed = Permutation::from_vec(vec![2, 1, 0]);
+        assert!(RowColumnPermutation::split(&mixed, 2, 3).is_none());
+    }
+
+    #[test]
+    fn invalid_codes() {
+        assert!(parse(r#"{"LinearCodeStab": {"q": 3, "codewords": [[1, 2, 0]]}}"#).is_ok());
+        assert!(parse(r#"{"LinearCodeStab": {"q": 4, "codewords": [[1, 2, 0]]}}"#).is_err());
+        assert!(parse(r#"{"LinearCodeStab": {"q": 2, "codewords": [[1, 0], [1]]}}"#).is_err());
+        assert!(parse(r#"{"LinearCodeStab": {"q": 3, "codewords": [[1, 3, 0]]}}"#).is_err());
+        assert!(
+            parse(r#"{"LinearCodeTransport": {"q": 2, "left_codewords": [[1]], "right_codewords": [[2]]}}"#).is_err()
+        );
+        assert!(
+            parse(r#"{"LinearCodeTransport": {"q": 2, "left_codewords": [[1]], "right_codewords": [[1, 0]]}}"#)
+                .is_err()
+        );
+
+        assert!(parse(r#"{"BooleanFunctionStab": {"truth_table": [false, true, true]}}"#).is_err());
+        let transport = r#"{"BooleanFunctionTransport": {"left_truth_table": [false, true], "right_truth_table": [false, true, true, true]}}"#;
+        assert!(parse(transport).is_err());
+    }
+}
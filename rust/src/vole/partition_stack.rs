@@ -1,8 +1,11 @@
 #![allow(dead_code)]
 
-use crate::{datastructures::small_int_set::SmallIntSet, vole::trace};
+use crate::vole::trace;
 use crate::{
-    datastructures::{digraph::Digraph, hash::QuickHashable},
+    datastructures::{
+        digraph::Digraph,
+        hash::{QHash, QuickHashable},
+    },
     perm::Permutation,
 };
 
@@ -71,6 +74,34 @@ struct CellData {
     extended_cells: Vec<usize>,
 }
 
+/// Buffers used by [PartitionStack::refine_partition_cells_by_graph], which are kept between
+/// calls so they are only allocated once. Between calls every label is zero, and nothing is
+/// touched.
+#[derive(Clone, Debug, Default)]
+struct GraphScratch {
+    /// The label of each point, from the neighbours it has in the current splitters
+    points: Vec<Wrapping<QHash>>,
+    /// The points with a label, and which points are in `touched`
+    touched: Vec<usize>,
+    is_touched: Vec<bool>,
+}
+
+impl GraphScratch {
+    /// Make the buffers large enough for a partition of `n` values
+    fn resize(&mut self, n: usize) {
+        self.points.resize(n, Wrapping(0));
+        self.is_touched.resize(n, false);
+    }
+
+    fn clear(&mut self) {
+        for &x in &self.touched {
+            self.points[x] = Wrapping(0);
+            self.is_touched[x] = false;
+        }
+        self.touched.clear();
+    }
+}
+
 /// An ordered partition of a set [1..n], which support many operations, including:
 /// * Split cells
 /// * Which which cell a value is contained in
@@ -95,6 +126,8 @@ pub struct PartitionStack {
     splits: Vec<usize>,
 
     saved_depths: Vec<usize>,
+
+    graph_scratch: GraphScratch,
 }
 
 impl PartitionStack {
@@ -118,9 +151,37 @@ impl PartitionStack {
             marks: MarkStore::new(n),
             splits: vec![],
             saved_depths: vec![],
+            graph_scratch: Default::default(),
         }
     }
 
+    /// Reset to a single cell containing [1..`n`], as [PartitionStack::new] does, but reusing
+    /// the memory already allocated
+    pub fn reset(&mut self, n: usize) {
+        assert!(n > 1);
+        self.base_size = n;
+        self.extended_size = n;
+        let cells = &mut self.cells;
+        cells.values.clear();
+        cells.values.extend(0..n);
+        cells.inv_values.clear();
+        cells.inv_values.extend(0..n);
+        cells.base_fixed.clear();
+        cells.base_fixed_values.clear();
+        cells.starts.clear();
+        cells.starts.push(0);
+        cells.lengths.clear();
+        cells.lengths.push(n);
+        cells.base_cells.clear();
+        cells.base_cells.push(0);
+        cells.extended_cells.clear();
+        cells.extended_cells.push(0);
+        self.marks.marks.clear();
+        self.marks.marks.resize(n, 0);
+        self.splits.clear();
+        self.saved_depths.clear();
+    }
+
     /// Add a new cell which contains `extra` new numbers, returns id of new partition
     pub fn extend(&mut self, extra: usize) -> usize {
         assert!(extra > 0);
@@ -137,8 +198,8 @@ impl PartitionStack {
         self.marks.extend_marks(extra, new_cell);
         debug_assert!(self.sanity_check());
 
-        // usize::max_value() denotes the extra cell was created by adding to the partition
-        self.splits.push(usize::max_value());
+        // usize::MAX denotes the extra cell was created by adding to the partition
+        self.splits.push(usize::MAX);
         new_cell
     }
 
@@ -362,7 +423,7 @@ impl PartitionStack {
     fn unsplit_cell(&mut self) {
         let unsplit = self.splits.pop().unwrap();
 
-        if unsplit == usize::max_value() {
+        if unsplit == usize::MAX {
             // This was a newly created cell
             self.revert_extend();
             return;
@@ -428,14 +489,14 @@ impl PartitionStack {
         }
     }
 
-    pub fn refine_partition_cell_by<F: Copy, O: Ord + Hash + Debug + QuickHashable>(
+    pub fn refine_partition_cell_by<F, O: Ord + Hash + Debug + QuickHashable>(
         &mut self,
         tracer: &mut trace::Tracer,
         i: usize,
         f: F,
     ) -> trace::Result<()>
     where
-        F: Fn(&usize) -> O,
+        F: Copy + Fn(&usize) -> O,
     {
         let cell_slice = self.mut_cell(i);
         if cell_slice.len() == 1 {
@@ -483,13 +544,13 @@ impl PartitionStack {
         Ok(())
     }
 
-    pub fn base_refine_partition_by<F: Copy, O: Ord + Hash + Debug + QuickHashable>(
+    pub fn base_refine_partition_by<F, O: Ord + Hash + Debug + QuickHashable>(
         &mut self,
         tracer: &mut trace::Tracer,
         f: F,
     ) -> trace::Result<()>
     where
-        F: Fn(&usize) -> O,
+        F: Copy + Fn(&usize) -> O,
     {
         let mut pos = 0;
         while pos < self.base_cells().len() {
@@ -500,13 +561,13 @@ impl PartitionStack {
         Ok(())
     }
 
-    pub fn extended_refine_partition_by<F: Copy, O: Ord + Hash + Debug + QuickHashable>(
+    pub fn extended_refine_partition_by<F, O: Ord + Hash + Debug + QuickHashable>(
         &mut self,
         tracer: &mut trace::Tracer,
         f: F,
     ) -> trace::Result<()>
     where
-        F: Fn(&usize) -> O,
+        F: Copy + Fn(&usize) -> O,
     {
         let mut pos = 0;
         while pos < self.extended_cells().len() {
@@ -516,38 +577,139 @@ impl PartitionStack {
         }
         Ok(())
     }
+    /// Refine cell `i` by `values`, where `touched` contains every point of the cell whose value
+    /// may be non-zero. This produces the same trace as [Self::refine_partition_cell_by], but takes
+    /// time proportional to the number of touched points rather than the size of the cell
+    /// (the order of points within the resulting cells may differ).
+    fn refine_partition_cell_by_touched(
+        &mut self,
+        tracer: &mut trace::Tracer,
+        i: usize,
+        touched: &mut [usize],
+        values: &[Wrapping<QHash>],
+    ) -> trace::Result<()> {
+        let len = self.cells.lengths[i];
+        if len == 1 {
+            return Ok(());
+        }
+
+        touched.sort_by_key(|&x| values[x]);
+        let touched = &touched[touched.partition_point(|&x| values[x] == Wrapping(0))..];
+        let zeros = len - touched.len();
+        let (first, last) = match (touched.first(), touched.last()) {
+            (Some(&first), Some(&last)) => (values[first], values[last]),
+            _ => (Wrapping(0), Wrapping(0)),
+        };
+        if touched.is_empty() || (zeros == 0 && first == last) {
+            tracer.add(trace::TraceEvent::NoSplit {
+                cell: i,
+                reason: first.quick_hash().0,
+            })?;
+            return Ok(());
+        }
+
+        // Move the touched points to the end of the cell, in sorted order
+        let cell_start = self.cells.starts[i];
+        for (k, &x) in touched.iter().enumerate() {
+            let target = cell_start + zeros + k;
+            let pos = self.cells.inv_values[x];
+            let y = self.cells.values[target];
+            self.cells.values.swap(pos, target);
+            self.cells.inv_values[x] = target;
+            self.cells.inv_values[y] = pos;
+        }
+
+        // First cell is never split
+        let smallest = if zeros > 0 { Wrapping(0) } else { first };
+        tracer.add(trace::TraceEvent::NoSplit {
+            cell: i,
+            reason: smallest.quick_hash().0,
+        })?;
+        for k in (0..touched.len()).rev() {
+            let p = zeros + k;
+            if p == 0 {
+                break;
+            }
+            let prev = if k == 0 { Wrapping(0) } else { values[touched[k - 1]] };
+            if values[touched[k]] != prev {
+                self.split_cell(i, p);
+                tracer.add(trace::TraceEvent::Split {
+                    cell: i,
+                    size: p,
+                    reason: prev.quick_hash().0,
+                })?
+            }
+        }
+        tracer.add(trace::TraceEvent::End())?;
+        Ok(())
+    }
+
+    /// Refine the partition using the graph `d`, using each cell from `first_cell` onwards
+    /// (and every cell created while refining) as a splitter. Splitters are used in rounds:
+    /// each point is labelled by the neighbours it has in every cell created since the previous
+    /// round, and then each cell containing a labelled point is refined, in order. Only labelled
+    /// points are looked at, so a round takes time proportional to the edges out of its splitters.
     pub fn refine_partition_cells_by_graph(
         &mut self,
         tracer: &mut trace::Tracer,
         d: &Digraph,
         first_cell: usize,
+    ) -> trace::Result<()> {
+        let mut scratch = std::mem::take(&mut self.graph_scratch);
+        scratch.resize(self.extended_domain_size());
+        let result = self.refine_by_graph_rounds(tracer, d, first_cell, &mut scratch);
+        scratch.clear();
+        self.graph_scratch = scratch;
+        result
+    }
+
+    fn refine_by_graph_rounds(
+        &mut self,
+        tracer: &mut trace::Tracer,
+        d: &Digraph,
+        first_cell: usize,
+        scratch: &mut GraphScratch,
     ) -> trace::Result<()> {
         let mut cells_done = first_cell;
         while cells_done < self.extended_cells().len() {
-            let mut seen_cells = SmallIntSet::new(self.extended_domain_size());
-
-            let mut points = vec![Wrapping(0_u64); self.extended_domain_size()];
-
-            while cells_done < self.extended_cells().len() {
-                let c = self.extended_cells()[cells_done];
+            let cells_end = self.extended_cells().len();
+            for &c in &self.extended_cells()[cells_done..cells_end] {
                 // Use a slightly less good hashing strategy, as this bit of code is the hotest piece of code
                 let c_hash = c.quick_hash();
                 for &p in self.cell(c) {
-                    for (&neighbour, &colour) in d.neighbours(p) {
-                        points[neighbour] += c_hash * colour; // TODO: Benchmark against (c, colour).quick_hash();
-                        seen_cells.insert(self.cell_of(neighbour));
+                    for &(neighbour, colour) in d.neighbours(p) {
+                        scratch.points[neighbour] += c_hash * colour; // TODO: Benchmark against (c, colour).quick_hash();
+                        if !scratch.is_touched[neighbour] {
+                            scratch.is_touched[neighbour] = true;
+                            scratch.touched.push(neighbour);
+                        }
                     }
                 }
-                cells_done += 1;
             }
-
-            //dbg!(format!("{:?}",&points));
-            // This may increment self.extended_cells().len(), which is why we look around
-            //dbg!(format!("{:?}",self.extended_as_list_set()));
-            for &s in seen_cells.sorted_iter() {
-                self.refine_partition_cell_by(tracer, s, |x| points[*x])?;
+            cells_done = cells_end;
+
+            // Group the touched points by their cell. Refining a cell only changes the cell of
+            // points it contains, so these groups stay correct while we refine.
+            let mut touched = std::mem::take(&mut scratch.touched);
+            touched.sort_unstable_by_key(|&x| self.cell_of(x));
+            let mut result = Ok(());
+            let mut start = 0;
+            while start < touched.len() {
+                let cell = self.cell_of(touched[start]);
+                let end = start
+                    + touched[start..]
+                        .iter()
+                        .take_while(|&&x| self.cell_of(x) == cell)
+                        .count();
+                result = self.refine_partition_cell_by_touched(tracer, cell, &mut touched[start..end], &scratch.points);
+                if result.is_err() {
+                    break;
+                }
+                start = end;
             }
-            //dbg!(format!("{:?}",self.extended_as_list_set()));
+            scratch.touched = touched;
+            scratch.clear();
+            result?;
         }
         Ok(())
     }
@@ -577,13 +739,18 @@ pub fn perm_between(lhs: &PartitionStack, rhs: &PartitionStack) -> Permutation {
 
 #[cfg(test)]
 mod tests {
+    use std::{collections::BTreeSet, num::Wrapping};
+
+    use itertools::Itertools;
+    use rand::{seq::SliceRandom, Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
     use test_log::test;
 
     use super::perm_between;
     use super::PartitionStack;
     use super::Permutation;
     use crate::{
-        datastructures::digraph::Digraph,
+        datastructures::{digraph::Digraph, hash::QuickHashable},
         vole::{backtracking::Backtrack, trace},
     };
 
@@ -775,6 +942,131 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_refine_graph_labelled() -> trace::Result<()> {
+        // Vertex 0 splits the other vertices into three cells, which then split 0 apart from 4 and 5
+        let edges = vec![vec![(1, 1), (2, 2), (3, 2)], vec![], vec![], vec![], vec![], vec![]];
+        let mut tracer = trace::Tracer::new();
+        let mut p = PartitionStack::new(6);
+        p.refine_partition_by_graph(&mut tracer, &Digraph::from_labelled_vec(edges))?;
+        assert_eq!(p.base_as_list_set(), vec![vec![4, 5], vec![0], vec![2, 3], vec![1]]);
+        assert!(p.sanity_check());
+
+        // Relabelling the graph must give the same trace
+        let relabel = [5, 3, 0, 4, 1, 2];
+        let mut edges = vec![vec![]; 6];
+        edges[relabel[0]] = vec![(relabel[1], 1), (relabel[2], 2), (relabel[3], 2)];
+        let mut tracer2 = trace::Tracer::new();
+        let mut q = PartitionStack::new(6);
+        q.refine_partition_by_graph(&mut tracer2, &Digraph::from_labelled_vec(edges))?;
+        assert!(q.sanity_check());
+        assert_eq!(tracer.symmetry_trace_len(), tracer2.symmetry_trace_len());
+        let len = tracer.symmetry_trace_len();
+        assert_eq!(tracer.symmetry_trace_hash(0, len), tracer2.symmetry_trace_hash(0, len));
+        let relabelled: Vec<Vec<usize>> = p
+            .base_as_list_set()
+            .iter()
+            .map(|c| c.iter().map(|&x| relabel[x]).sorted().collect())
+            .collect();
+        assert_eq!(q.base_as_list_set(), relabelled);
+        Ok(())
+    }
+
+    /// Refine by `d` in the way [PartitionStack::refine_partition_cells_by_graph] did before it used a
+    /// queue of splitters: every new cell is a splitter, and they are processed in batches
+    fn refine_by_graph_batched(p: &mut PartitionStack, tracer: &mut trace::Tracer, d: &Digraph) -> trace::Result<()> {
+        let mut cells_done = 0;
+        while cells_done < p.extended_cells().len() {
+            let mut seen_cells = BTreeSet::new();
+            let mut points = vec![Wrapping(0_u64); p.extended_domain_size()];
+            while cells_done < p.extended_cells().len() {
+                let c = p.extended_cells()[cells_done];
+                let c_hash = c.quick_hash();
+                for &x in p.cell(c) {
+                    for &(neighbour, colour) in d.neighbours(x) {
+                        points[neighbour] += c_hash * colour;
+                        seen_cells.insert(p.cell_of(neighbour));
+                    }
+                }
+                cells_done += 1;
+            }
+            for &s in &seen_cells {
+                p.refine_partition_cell_by(tracer, s, |x| points[*x])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fix `fixed`, and then refine by the graph with the labelled edges `edges`
+    fn refine_fixed(
+        tracer: &mut trace::Tracer,
+        edges: &[Vec<(usize, usize)>],
+        fixed: usize,
+        batched: bool,
+    ) -> trace::Result<PartitionStack> {
+        let d = Digraph::from_labelled_vec(edges.to_vec());
+        let mut p = PartitionStack::new(edges.len());
+        p.refine_partition_cell_by(tracer, 0, |&x| x == fixed)?;
+        if batched {
+            refine_by_graph_batched(&mut p, tracer, &d)?;
+        } else {
+            p.refine_partition_by_graph(tracer, &d)?;
+        }
+        Ok(p)
+    }
+
+    #[test]
+    fn test_refine_graph_matches_batched() -> trace::Result<()> {
+        let cells = |p: &PartitionStack| -> Vec<Vec<usize>> {
+            p.base_as_list_set()
+                .into_iter()
+                .map(|c| c.into_iter().sorted().collect())
+                .collect()
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for _ in 0..200 {
+            let n = rng.gen_range(2..40);
+            let edges: Vec<Vec<(usize, usize)>> = (0..n)
+                .map(|_| {
+                    let arcs = rng.gen_range(0..4);
+                    (0..arcs).map(|_| (rng.gen_range(0..n), rng.gen_range(0..2))).collect()
+                })
+                .collect();
+            let fixed = rng.gen_range(0..n);
+
+            // Record the trace of the batched version, and then check every event against it
+            let mut tracer = trace::Tracer::new();
+            tracer.save_state();
+            let batched = refine_fixed(&mut tracer, &edges, fixed, true)?;
+            let len = tracer.position();
+            tracer.restore_state();
+            let p = refine_fixed(&mut tracer, &edges, fixed, false)?;
+            assert_eq!(tracer.position(), len);
+            assert_eq!(tracer.symmetry_trace_len(), len);
+            assert!(p.sanity_check());
+            assert_eq!(cells(&p), cells(&batched));
+
+            // Relabelling the graph gives the same trace, and the relabelled partition
+            let mut relabel: Vec<usize> = (0..n).collect();
+            relabel.shuffle(&mut rng);
+            let mut relabelled_edges = vec![vec![]; n];
+            for (v, neighbours) in edges.iter().enumerate() {
+                relabelled_edges[relabel[v]] = neighbours.iter().map(|&(w, label)| (relabel[w], label)).collect();
+            }
+            let mut tracer2 = trace::Tracer::new();
+            let q = refine_fixed(&mut tracer2, &relabelled_edges, relabel[fixed], false)?;
+            assert_eq!(len, tracer2.symmetry_trace_len());
+            assert_eq!(tracer.symmetry_trace_hash(0, len), tracer2.symmetry_trace_hash(0, len));
+            let relabelled: Vec<Vec<usize>> = p
+                .base_as_list_set()
+                .iter()
+                .map(|c| c.iter().map(|&x| relabel[x]).sorted().collect())
+                .collect();
+            assert_eq!(q.base_as_list_set(), relabelled);
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_perm() -> trace::Result<()> {
         let mut tracer = trace::Tracer::new();
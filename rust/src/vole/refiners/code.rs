@@ -0,0 +1,316 @@
+use super::Refiner;
+
+use super::{super::domain_state::DomainState, Side};
+use crate::{
+    datastructures::{digraph::Digraph, sortedvec::SortedVec},
+    vole::trace,
+};
+use crate::{perm::Permutation, vole::backtracking::Backtrack};
+use std::{cmp::Ordering, rc::Rc};
+
+/// Largest number of codewords we will enumerate, when searching for the
+/// minimum weight codewords of a linear code
+const MAX_ENUMERATED_CODEWORDS: usize = 1 << 20;
+
+fn inverse_mod(x: usize, q: usize) -> usize {
+    // q is prime, so x^(q-2) is the inverse of x
+    let mut result = 1;
+    for _ in 0..q - 2 {
+        result = (result * x) % q;
+    }
+    result
+}
+
+/// Put `rows` into reduced row echelon form over the field of size `q` (which must be prime),
+/// removing zero rows. Every entry of `rows` must be less than `q`.
+pub fn row_reduce(rows: &[Vec<usize>], q: usize) -> Vec<Vec<usize>> {
+    assert!(
+        rows.iter().flatten().all(|&x| x < q),
+        "Codeword entries must be less than {}",
+        q
+    );
+    let mut rows = rows.to_vec();
+    let length = rows.first().map_or(0, |r| r.len());
+    let mut rank = 0;
+    for col in 0..length {
+        let pivot = match (rank..rows.len()).find(|&i| rows[i][col] != 0) {
+            Some(pivot) => pivot,
+            None => continue,
+        };
+        rows.swap(rank, pivot);
+        let inv = inverse_mod(rows[rank][col], q);
+        for x in &mut rows[rank] {
+            *x = (*x * inv) % q;
+        }
+        let pivot_row = rows[rank].clone();
+        for (i, row) in rows.iter_mut().enumerate() {
+            let scale = row[col];
+            if i != rank && scale != 0 {
+                for (x, &r) in row.iter_mut().zip(&pivot_row) {
+                    *x = (*x + (q - scale) * r) % q;
+                }
+            }
+        }
+        rank += 1;
+    }
+    rows.truncate(rank);
+    rows
+}
+
+/// Check if `v` is in the row space of `basis`, which must be in reduced row echelon form
+fn in_row_space(v: &[usize], basis: &[Vec<usize>], q: usize) -> bool {
+    let mut v = v.to_vec();
+    for row in basis {
+        let pivot = row.iter().position(|&x| x != 0).unwrap();
+        let scale = v[pivot];
+        if scale != 0 {
+            for (x, &r) in v.iter_mut().zip(row) {
+                *x = (*x + (q - scale) * r) % q;
+            }
+        }
+    }
+    v.iter().all(|&x| x == 0)
+}
+
+fn weight(v: &[usize]) -> usize {
+    v.iter().filter(|&&x| x != 0).count()
+}
+
+/// All non-zero codewords of minimum weight in the code spanned by `basis`, or
+/// an empty list if the code is too large to enumerate
+fn min_weight_codewords(basis: &[Vec<usize>], q: usize) -> SortedVec<Vec<usize>> {
+    let length = basis.first().map_or(0, |r| r.len());
+    let size = q.checked_pow(basis.len() as u32);
+    if basis.is_empty() || size.is_none_or(|s| s > MAX_ENUMERATED_CODEWORDS) {
+        return SortedVec::from_unsorted(vec![]);
+    }
+
+    let mut min_weight = usize::MAX;
+    let mut words = vec![];
+    let mut coeffs = vec![0; basis.len()];
+    // Step through all coefficient vectors, skipping 0
+    loop {
+        let pos = coeffs.iter().position(|&c| c != q - 1);
+        match pos {
+            None => break,
+            Some(pos) => {
+                coeffs[pos] += 1;
+                coeffs[..pos].iter_mut().for_each(|c| *c = 0);
+            }
+        }
+
+        let mut word = vec![0; length];
+        for (&c, row) in coeffs.iter().zip(basis) {
+            for (x, &r) in word.iter_mut().zip(row) {
+                *x = (*x + c * r) % q;
+            }
+        }
+
+        let w = weight(&word);
+        match w.cmp(&min_weight) {
+            Ordering::Less => {
+                min_weight = w;
+                words = vec![word];
+            }
+            Ordering::Equal => words.push(word),
+            Ordering::Greater => {}
+        }
+    }
+    words.into_iter().collect()
+}
+
+/// A code of words of a fixed length, whose coordinates are the points `0..length`.
+/// A linear code is stored as a basis in reduced row echelon form, while for a
+/// non-linear code the basis is just the sorted list of codewords.
+struct CodeData {
+    length: usize,
+    q: usize,
+    linear: bool,
+    basis: Vec<Vec<usize>>,
+    /// The codewords used to build the graph invariant. For linear codes these are
+    /// the minimum weight codewords, for non-linear codes these are all the codewords.
+    words: SortedVec<Vec<usize>>,
+}
+
+impl CodeData {
+    fn new(length: usize, q: usize, linear: bool, codewords: Vec<Vec<usize>>) -> Self {
+        assert!(
+            q >= 2 && (2..q).all(|d| !q.is_multiple_of(d)),
+            "Field size must be prime"
+        );
+        assert!(
+            codewords.iter().all(|w| w.len() == length),
+            "All codewords must have the same length"
+        );
+        if linear {
+            let basis = row_reduce(&codewords, q);
+            let words = min_weight_codewords(&basis, q);
+            Self {
+                length,
+                q,
+                linear,
+                basis,
+                words,
+            }
+        } else {
+            let words: SortedVec<Vec<usize>> = codewords.into_iter().collect();
+            Self {
+                length,
+                q,
+                linear,
+                basis: words.iter().cloned().collect(),
+                words,
+            }
+        }
+    }
+
+    /// Permute the coordinates of `word`, or return `None` if `p` does not map coordinates to coordinates
+    fn permute_word(&self, word: &[usize], p: &Permutation) -> Option<Vec<usize>> {
+        let mut image = vec![0; self.length];
+        for (i, &x) in word.iter().enumerate() {
+            *image.get_mut(p.apply(i))? = x;
+        }
+        Some(image)
+    }
+
+    fn image(&self, p: &Permutation) -> Option<Vec<Vec<usize>>> {
+        let permuted: Vec<Vec<usize>> = self
+            .basis
+            .iter()
+            .map(|w| self.permute_word(w, p))
+            .collect::<Option<_>>()?;
+        if self.linear {
+            Some(row_reduce(&permuted, self.q))
+        } else {
+            let sorted: SortedVec<Vec<usize>> = permuted.into_iter().collect();
+            Some(sorted.iter().cloned().collect())
+        }
+    }
+}
+
+/// Refiner for the permutations of coordinates which map one code to another. The
+/// coordinates of codes of length n are the points `0..n`. The invariant is a graph,
+/// with one new vertex for each codeword in `words`, joined to each coordinate where the
+/// codeword is non-zero, labelled by the entry of the codeword.
+pub struct CodeTransporter {
+    code_left: Rc<CodeData>,
+    code_right: Rc<CodeData>,
+}
+
+impl CodeTransporter {
+    /// Create a transporter between the codes spanned by (if `linear`), or made of (if not `linear`)
+    /// `codewords_left` and `codewords_right`, over the field of prime size `q`
+    pub fn new_transporter(
+        length: usize,
+        q: usize,
+        linear: bool,
+        codewords_left: Vec<Vec<usize>>,
+        codewords_right: Vec<Vec<usize>>,
+    ) -> Self {
+        Self {
+            code_left: Rc::new(CodeData::new(length, q, linear, codewords_left)),
+            code_right: Rc::new(CodeData::new(length, q, linear, codewords_right)),
+        }
+    }
+
+    pub fn new_stabilizer(length: usize, q: usize, linear: bool, codewords: Vec<Vec<usize>>) -> Self {
+        let r = Rc::new(CodeData::new(length, q, linear, codewords));
+        Self {
+            code_left: r.clone(),
+            code_right: r,
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> Option<Vec<Vec<usize>>> {
+        match side {
+            Side::Left => self.code_left.image(p),
+            Side::Right => self.code_right.image(p),
+        }
+    }
+
+    fn compare(&self, lhs: &Option<Vec<Vec<usize>>>, rhs: &Option<Vec<Vec<usize>>>) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl Refiner for CodeTransporter {
+    gen_any_image_compare!(Option<Vec<Vec<usize>>>);
+
+    fn name(&self) -> String {
+        let kind = if self.code_left.linear { "Linear" } else { "NonLinear" };
+        if self.is_group() {
+            format!("{}CodeStabilizer of {:?}", kind, self.code_left.basis)
+        } else {
+            format!(
+                "{}CodeTransporter of {:?} -> {:?}",
+                kind, self.code_left.basis, self.code_right.basis
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        let (left, right) = (&self.code_left, &self.code_right);
+        if left.basis.len() != right.basis.len() {
+            return false;
+        }
+
+        if left.linear {
+            left.basis.iter().all(|w| match left.permute_word(w, p) {
+                Some(image) => in_row_space(&image, &right.basis, right.q),
+                None => false,
+            })
+        } else {
+            left.image(p).as_ref() == Some(&right.basis)
+        }
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let code = match side {
+            Side::Left => &self.code_left,
+            Side::Right => &self.code_right,
+        };
+
+        // Record: dimension (or number of codewords), number and minimum weight of codewords in the invariant
+        state.add_invariant_fact(code.basis.len())?;
+        state.add_invariant_fact(code.words.len())?;
+        state.add_invariant_fact(code.words.iter().map(|w| weight(w)).min().unwrap_or(0))?;
+
+        let length = code.length;
+        debug_assert!(length <= state.partition().base_domain_size());
+
+        // Colour coordinates apart from any other points
+        state.base_refine_partition_by(|&x| x < length)?;
+
+        if code.words.is_empty() {
+            return Ok(());
+        }
+
+        let extended = state.partition().extended_domain_size();
+        let extra_points = code.words.len();
+        let _ = state.extend_partition(extra_points);
+
+        let mut graph: Vec<Vec<(usize, usize)>> = vec![vec![]; extended + extra_points];
+        for (i, w) in code.words.iter().enumerate() {
+            for (coord, &x) in w.iter().enumerate() {
+                if x != 0 {
+                    graph[coord].push((extended + i, x));
+                }
+            }
+        }
+
+        state.add_graph(&Digraph::from_labelled_vec(graph));
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        Rc::ptr_eq(&self.code_left, &self.code_right) || self.code_left.basis == self.code_right.basis
+    }
+}
+
+impl Backtrack for CodeTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use super::Refiner;
 use super::{super::domain_state::DomainState, Side};
-use crate::datastructures::digraph::RawDigraph;
+use crate::datastructures::sortedvec::SortedVec;
 use crate::perm::Permutation;
 use crate::vole::trace;
 use crate::{datastructures::digraph::Digraph, vole::backtracking::Backtrack};
@@ -10,29 +10,20 @@ use crate::{datastructures::digraph::Digraph, vole::backtracking::Backtrack};
 pub struct DigraphTransporter {
     digraph_left: Arc<Digraph>,
     digraph_right: Arc<Digraph>,
-    digraph_raw_left: Arc<RawDigraph>,
-    digraph_raw_right: Arc<RawDigraph>,
 }
 
 impl DigraphTransporter {
     pub fn new_stabilizer(digraph: Arc<Digraph>) -> Self {
-        let raw = Arc::new(digraph.to_raw_unordered());
         Self {
             digraph_left: digraph.clone(),
             digraph_right: digraph,
-            digraph_raw_left: raw.clone(),
-            digraph_raw_right: raw,
         }
     }
 
     pub fn new_transporter(digraph_left: Arc<Digraph>, digraph_right: Arc<Digraph>) -> Self {
-        let digraph_raw_left = Arc::new(digraph_left.to_raw_unordered());
-        let digraph_raw_right = Arc::new(digraph_right.to_raw_unordered());
         Self {
             digraph_left,
             digraph_right,
-            digraph_raw_left,
-            digraph_raw_right,
         }
     }
 
@@ -63,17 +54,22 @@ impl Refiner for DigraphTransporter {
         }
     }
 
+    fn any_compare_image(&self, p: &Permutation, prev: &Box<dyn std::any::Any>) -> std::cmp::Ordering {
+        self.digraph_left
+            .cmp_permuted(p, prev.downcast_ref::<Digraph>().unwrap())
+    }
+
     fn check(&self, p: &Permutation) -> bool {
         // For problems with many graphs (like finding two-closures), this function can takes >50% of runtime, so it
         // is stupidly optimised. We:
-        // * Store graphs as vec<vec<>>, for fastest iteration and (binary) searching
+        // * Use the sorted neighbour lists of the graphs, for fast iteration and (binary) searching
         // * Special-case when we (a) map a graph to itself and (b) map a point to itself.
         //   In that case we check after applying permutation if we need to point is mapped to itself.
         for i in 0..self.digraph_left.vertices() {
-            let neighbours = &self.digraph_raw_left[i];
+            let neighbours = self.digraph_left.neighbours(i);
 
             let i_img = p.apply(i);
-            let img_neighbours = &self.digraph_raw_right[i_img];
+            let img_neighbours = self.digraph_right.neighbours(i_img);
 
             // Special case when many points are fixed in the permutation
             if std::ptr::eq(img_neighbours, neighbours) {
@@ -126,3 +122,143 @@ impl Backtrack for DigraphTransporter {
         0
     }
 }
+
+/// An edge (source, target, label) in a digraph
+type LabelledEdge = (usize, usize, usize);
+
+/// Edge label joining the source of an edge to its edge vertex
+const SOURCE_LABEL: usize = 0;
+/// Edge label joining the target of an edge to its edge vertex
+const TARGET_LABEL: usize = 1;
+/// Edge label joining an edge vertex to its graph vertex
+const GRAPH_LABEL: usize = 2;
+
+/// Refiner for a set of digraphs on the same vertices, which may be permuted amongst themselves.
+/// Each digraph is stored as a sorted list of labelled edges. This is represented by adding
+/// one new vertex for each digraph, and one new vertex for each edge, which is joined to its source,
+/// target, and the vertex of its digraph.
+pub struct DigraphSetTransporter {
+    digraphs_left: Arc<SortedVec<SortedVec<LabelledEdge>>>,
+    digraphs_right: Arc<SortedVec<SortedVec<LabelledEdge>>>,
+}
+
+impl DigraphSetTransporter {
+    pub fn new_stabilizer(digraphs: SortedVec<SortedVec<LabelledEdge>>) -> Self {
+        let d = Arc::new(digraphs);
+        Self {
+            digraphs_left: d.clone(),
+            digraphs_right: d,
+        }
+    }
+
+    pub fn new_transporter(
+        digraphs_left: SortedVec<SortedVec<LabelledEdge>>,
+        digraphs_right: SortedVec<SortedVec<LabelledEdge>>,
+    ) -> Self {
+        Self {
+            digraphs_left: Arc::new(digraphs_left),
+            digraphs_right: Arc::new(digraphs_right),
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> SortedVec<SortedVec<LabelledEdge>> {
+        let digraphs = match side {
+            Side::Left => &self.digraphs_left,
+            Side::Right => &self.digraphs_right,
+        };
+        digraphs
+            .iter()
+            .map(|d| d.iter().map(|&(s, t, l)| (p.apply(s), p.apply(t), l)).collect())
+            .collect()
+    }
+
+    fn compare(
+        &self,
+        lhs: &SortedVec<SortedVec<LabelledEdge>>,
+        rhs: &SortedVec<SortedVec<LabelledEdge>>,
+    ) -> std::cmp::Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl Refiner for DigraphSetTransporter {
+    gen_any_image_compare!(SortedVec<SortedVec<LabelledEdge>>);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("DigraphSetStabilizer of {:?}", self.digraphs_left)
+        } else {
+            format!(
+                "DigraphSetTransporter of {:?} -> {:?}",
+                self.digraphs_left, self.digraphs_right
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        if self.digraphs_left.len() != self.digraphs_right.len() {
+            return false;
+        }
+
+        self.image(p, Side::Left) == *self.digraphs_right
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let digraphs = match side {
+            Side::Left => &self.digraphs_left,
+            Side::Right => &self.digraphs_right,
+        };
+
+        let edge_count: usize = digraphs.iter().map(|d| d.len()).sum();
+
+        // Record: number of digraphs, total number of edges
+        state.add_invariant_fact(digraphs.len())?;
+        state.add_invariant_fact(edge_count)?;
+
+        if digraphs.is_empty() {
+            return Ok(());
+        }
+
+        let base = state.partition().base_domain_size();
+        let extended = state.partition().extended_domain_size();
+        let extra_points = digraphs.len() + edge_count;
+        let total_new_size = extended + extra_points;
+
+        // Digraph vertices are coloured 0, edge vertices are coloured 1 more than their label
+        let mut colouring = vec![0usize; total_new_size];
+        let mut graph: Vec<Vec<(usize, usize)>> = vec![vec![]; total_new_size];
+
+        let mut edge_vert = extended + digraphs.len();
+        for (i, d) in digraphs.iter().enumerate() {
+            let digraph_vert = extended + i;
+            for &(s, t, l) in d {
+                debug_assert!(s < base && t < base);
+                colouring[edge_vert] = 1 + l;
+                graph[s].push((edge_vert, SOURCE_LABEL));
+                graph[t].push((edge_vert, TARGET_LABEL));
+                graph[edge_vert].push((digraph_vert, GRAPH_LABEL));
+                edge_vert += 1;
+            }
+        }
+
+        assert!(edge_vert == total_new_size);
+
+        let new_part = state.extend_partition(extra_points);
+        state.refine_partition_cell_by(new_part, |x| colouring[*x])?;
+
+        state.add_graph(&Digraph::from_labelled_vec(graph));
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        self.digraphs_left == self.digraphs_right
+    }
+}
+
+impl Backtrack for DigraphSetTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
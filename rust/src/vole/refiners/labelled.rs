@@ -0,0 +1,127 @@
+use super::Refiner;
+
+use super::{super::domain_state::DomainState, Side};
+use crate::vole::trace;
+use crate::{perm::Permutation, vole::backtracking::Backtrack};
+use std::{cmp::Ordering, collections::BTreeMap, fmt::Debug, hash::Hash, rc::Rc};
+
+/// Refiner for a labelling of the points, where point `i` is given label `labels[i]`,
+/// and points past the end of `labels` are unlabelled. Permutations must map each point
+/// to a point with the same label.
+pub struct LabelledPointsTransporter<T: Ord> {
+    labels_left: Rc<Vec<T>>,
+    labels_right: Rc<Vec<T>>,
+    /// The label of each point, replaced by 1 + its position in the sorted list of
+    /// all labels which occur on either side
+    ranks_left: Rc<Vec<usize>>,
+    ranks_right: Rc<Vec<usize>>,
+}
+
+impl<T: Ord + Clone + Debug + 'static> LabelledPointsTransporter<T> {
+    fn make_ranks(labels: &[T], rank_map: &BTreeMap<&T, usize>) -> Vec<usize> {
+        labels.iter().map(|x| rank_map[x]).collect()
+    }
+
+    pub fn new_transporter(labels_left: Vec<T>, labels_right: Vec<T>) -> Self {
+        let mut rank_map: BTreeMap<&T, usize> = labels_left.iter().chain(labels_right.iter()).map(|x| (x, 0)).collect();
+        for (i, v) in rank_map.values_mut().enumerate() {
+            *v = i + 1;
+        }
+
+        let ranks_left = Rc::new(Self::make_ranks(&labels_left, &rank_map));
+        let ranks_right = Rc::new(Self::make_ranks(&labels_right, &rank_map));
+        Self {
+            labels_left: Rc::new(labels_left),
+            labels_right: Rc::new(labels_right),
+            ranks_left,
+            ranks_right,
+        }
+    }
+
+    pub fn new_stabilizer(labels: Vec<T>) -> Self {
+        let mut rank_map: BTreeMap<&T, usize> = labels.iter().map(|x| (x, 0)).collect();
+        for (i, v) in rank_map.values_mut().enumerate() {
+            *v = i + 1;
+        }
+
+        let ranks = Rc::new(Self::make_ranks(&labels, &rank_map));
+        let l = Rc::new(labels);
+        Self {
+            labels_left: l.clone(),
+            labels_right: l,
+            ranks_left: ranks.clone(),
+            ranks_right: ranks,
+        }
+    }
+
+    /// The labelling which gives `p(i)` the label of `i`, with trailing
+    /// unlabelled points removed
+    fn image(&self, p: &Permutation, side: Side) -> Vec<Option<T>> {
+        let labels = match side {
+            Side::Left => &self.labels_left,
+            Side::Right => &self.labels_right,
+        };
+
+        let size = (0..labels.len()).map(|i| p.apply(i) + 1).max().unwrap_or(0);
+        let mut image = vec![None; size];
+        for (i, l) in labels.iter().enumerate() {
+            image[p.apply(i)] = Some(l.clone());
+        }
+        while let Some(None) = image.last() {
+            image.pop();
+        }
+        image
+    }
+
+    fn compare(&self, lhs: &[Option<T>], rhs: &[Option<T>]) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl<T: Ord + Clone + Debug + Hash + 'static> Refiner for LabelledPointsTransporter<T> {
+    gen_any_image_compare!(Vec<Option<T>>);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("LabelledPointsStabilizer of {:?}", self.labels_left)
+        } else {
+            format!(
+                "LabelledPointsTransporter of {:?} -> {:?}",
+                self.labels_left, self.labels_right
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        if self.ranks_left.len() != self.ranks_right.len() {
+            return false;
+        }
+
+        self.ranks_left
+            .iter()
+            .enumerate()
+            .all(|(i, &r)| self.ranks_right.get(p.apply(i)) == Some(&r))
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let ranks = match side {
+            Side::Left => &self.ranks_left,
+            Side::Right => &self.ranks_right,
+        };
+
+        state.base_refine_partition_by(|x| *ranks.get(*x).unwrap_or(&0))?;
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        self.ranks_left == self.ranks_right
+    }
+}
+
+impl<T: Ord> Backtrack for LabelledPointsTransporter<T> {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
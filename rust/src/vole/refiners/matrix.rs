@@ -0,0 +1,199 @@
+use super::Refiner;
+
+use super::{super::domain_state::DomainState, Side};
+use crate::{datastructures::digraph::Digraph, vole::trace};
+use crate::{perm::Permutation, vole::backtracking::Backtrack};
+use std::{cmp::Ordering, collections::BTreeMap, fmt::Debug, hash::Hash, rc::Rc};
+
+/// Split a permutation of the rows and columns of a matrix with `rows` rows and
+/// `cols` columns (where rows are the points `0..rows`, and columns are the points
+/// `rows..rows+cols`) into a permutation of the rows and a permutation of the columns.
+/// Returns `None` if `p` does not map rows to rows and columns to columns.
+pub fn split_row_column_permutation(p: &Permutation, rows: usize, cols: usize) -> Option<(Permutation, Permutation)> {
+    let row_perm: Vec<usize> = (0..rows).map(|i| p.apply(i)).collect();
+    let col_perm: Vec<usize> = (rows..rows + cols).map(|i| p.apply(i).wrapping_sub(rows)).collect();
+    if row_perm.iter().any(|&i| i >= rows) || col_perm.iter().any(|&i| i >= cols) {
+        return None;
+    }
+    Some((Permutation::from_vec(row_perm), Permutation::from_vec(col_perm)))
+}
+
+/// Refiner for mapping one matrix to another by independently permuting rows and columns.
+/// The rows of an r×c matrix are the points `0..r`, and the columns are the points `r..r+c`.
+/// The matrix is represented as a bipartite graph from rows to columns, where each edge
+/// is labelled by the matrix entry.
+pub struct MatrixTransporter<T: Ord> {
+    matrix_left: Rc<Vec<Vec<T>>>,
+    matrix_right: Rc<Vec<Vec<T>>>,
+    /// The entries of each matrix, replaced by their position in the sorted list of
+    /// all entries which occur in either matrix
+    labels_left: Rc<Vec<Vec<usize>>>,
+    labels_right: Rc<Vec<Vec<usize>>>,
+    rows: usize,
+    cols: usize,
+}
+
+impl<T: Ord + Clone + Debug + 'static> MatrixTransporter<T> {
+    fn dimensions(matrix: &[Vec<T>]) -> (usize, usize) {
+        let rows = matrix.len();
+        let cols = matrix.first().map_or(0, |r| r.len());
+        assert!(
+            matrix.iter().all(|r| r.len() == cols),
+            "All rows of a matrix must have the same length"
+        );
+        (rows, cols)
+    }
+
+    fn make_labels(matrix: &[Vec<T>], label_map: &BTreeMap<&T, usize>) -> Vec<Vec<usize>> {
+        matrix
+            .iter()
+            .map(|r| r.iter().map(|x| label_map[x]).collect())
+            .collect()
+    }
+
+    pub fn new_transporter(matrix_left: Vec<Vec<T>>, matrix_right: Vec<Vec<T>>) -> Self {
+        let (rows, cols) = Self::dimensions(&matrix_left);
+        assert!(
+            Self::dimensions(&matrix_right) == (rows, cols),
+            "Matrices must have the same dimensions"
+        );
+
+        let mut label_map: BTreeMap<&T, usize> = matrix_left
+            .iter()
+            .chain(matrix_right.iter())
+            .flatten()
+            .map(|x| (x, 0))
+            .collect();
+        for (i, v) in label_map.values_mut().enumerate() {
+            *v = i;
+        }
+
+        let labels_left = Rc::new(Self::make_labels(&matrix_left, &label_map));
+        let labels_right = Rc::new(Self::make_labels(&matrix_right, &label_map));
+        Self {
+            matrix_left: Rc::new(matrix_left),
+            matrix_right: Rc::new(matrix_right),
+            labels_left,
+            labels_right,
+            rows,
+            cols,
+        }
+    }
+
+    pub fn new_stabilizer(matrix: Vec<Vec<T>>) -> Self {
+        let (rows, cols) = Self::dimensions(&matrix);
+
+        let mut label_map: BTreeMap<&T, usize> = matrix.iter().flatten().map(|x| (x, 0)).collect();
+        for (i, v) in label_map.values_mut().enumerate() {
+            *v = i;
+        }
+
+        let labels = Rc::new(Self::make_labels(&matrix, &label_map));
+        let m = Rc::new(matrix);
+        Self {
+            matrix_left: m.clone(),
+            matrix_right: m,
+            labels_left: labels.clone(),
+            labels_right: labels,
+            rows,
+            cols,
+        }
+    }
+
+    /// Split a solution into a permutation of the rows and a permutation of the columns
+    pub fn split_permutation(&self, p: &Permutation) -> Option<(Permutation, Permutation)> {
+        split_row_column_permutation(p, self.rows, self.cols)
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> Option<Vec<Vec<T>>> {
+        let matrix = match side {
+            Side::Left => &self.matrix_left,
+            Side::Right => &self.matrix_right,
+        };
+
+        let (row_perm, col_perm) = self.split_permutation(p)?;
+        let (row_inv, col_inv) = (row_perm.inv(), col_perm.inv());
+
+        Some(
+            (0..self.rows)
+                .map(|i| {
+                    (0..self.cols)
+                        .map(|j| matrix[row_inv.apply(i)][col_inv.apply(j)].clone())
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    fn compare(&self, lhs: &Option<Vec<Vec<T>>>, rhs: &Option<Vec<Vec<T>>>) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl<T: Ord + Clone + Debug + Hash + 'static> Refiner for MatrixTransporter<T> {
+    gen_any_image_compare!(Option<Vec<Vec<T>>>);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("MatrixStabilizer of {:?}", self.matrix_left)
+        } else {
+            format!("MatrixTransporter of {:?} -> {:?}", self.matrix_left, self.matrix_right)
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        let (row_perm, col_perm) = match self.split_permutation(p) {
+            Some(perms) => perms,
+            None => return false,
+        };
+
+        (0..self.rows).all(|i| {
+            let row_left = &self.labels_left[i];
+            let row_right = &self.labels_right[row_perm.apply(i)];
+            (0..self.cols).all(|j| row_left[j] == row_right[col_perm.apply(j)])
+        })
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let labels = match side {
+            Side::Left => &self.labels_left,
+            Side::Right => &self.labels_right,
+        };
+
+        let (rows, cols) = (self.rows, self.cols);
+        debug_assert!(rows + cols <= state.partition().base_domain_size());
+
+        // Colour rows and columns apart
+        state.base_refine_partition_by(|&x| {
+            if x < rows {
+                0usize
+            } else if x < rows + cols {
+                1
+            } else {
+                2
+            }
+        })?;
+
+        let mut graph: Vec<Vec<(usize, usize)>> = vec![vec![]; rows + cols];
+        for (i, row) in labels.iter().enumerate() {
+            for (j, &label) in row.iter().enumerate() {
+                graph[i].push((rows + j, label));
+            }
+        }
+
+        state.add_graph(&Digraph::from_labelled_vec(graph));
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        self.labels_left == self.labels_right
+    }
+}
+
+impl<T: Ord> Backtrack for MatrixTransporter<T> {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
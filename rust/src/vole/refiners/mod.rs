@@ -1,5 +1,6 @@
 use std::any::Any;
 
+use crate::datastructures::hash::QHash;
 use crate::perm::Permutation;
 use crate::vole::backtracking::Backtrack;
 use crate::vole::trace;
@@ -31,6 +32,11 @@ macro_rules! gen_any_image_compare {
             let s_ref = s.downcast_ref::<$name>().unwrap();
             format!("{:?}", s_ref)
         }
+
+        fn any_hash(&self, s: &Box<dyn std::any::Any>) -> $crate::datastructures::hash::QHash {
+            let s_ref = s.downcast_ref::<$name>().unwrap();
+            $crate::datastructures::hash::do_hash(s_ref).0
+        }
     };
 }
 
@@ -54,10 +60,21 @@ pub trait Refiner: Backtrack {
     /// gen_any_image_compare! to create this function.
     fn any_compare(&self, lhs: &Box<dyn Any>, rhs: &Box<dyn Any>) -> std::cmp::Ordering;
 
+    /// Compare the image of the left side under `p` with `prev`, a value previously
+    /// returned by any_image. Refiners can override this to compare without building
+    /// the whole image, stopping at the first difference.
+    fn any_compare_image(&self, p: &Permutation, prev: &Box<dyn Any>) -> std::cmp::Ordering {
+        self.any_compare(&self.any_image(p, Side::Left), prev)
+    }
+
     /// Convert a previously return state to a string.
     /// Generated by the 'gen_any_image_compare' macro.
     fn any_to_string(&self, s: &Box<dyn Any>) -> String;
 
+    /// Hash a previously returned state, so that equal states have equal hashes.
+    /// Generated by the 'gen_any_image_compare' macro.
+    fn any_hash(&self, s: &Box<dyn Any>) -> QHash;
+
     fn refine_begin(&mut self, _: &mut DomainState, _: Side) -> trace::Result<()> {
         Ok(())
     }
@@ -77,8 +94,14 @@ pub trait Refiner: Backtrack {
     fn snapshot_rbase(&mut self, _: &mut DomainState) {}
 }
 
+pub mod code;
 pub mod digraph;
 pub mod gaprefiner;
+pub mod labelled;
+pub mod matrix;
+pub mod necklace;
+pub mod partition;
 pub mod refiner_store;
 pub mod simple;
+pub mod structure;
 pub mod symmetricgrp;
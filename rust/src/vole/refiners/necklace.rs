@@ -0,0 +1,192 @@
+use super::Refiner;
+
+use super::{super::domain_state::DomainState, Side};
+use crate::{datastructures::digraph::Digraph, vole::trace};
+use crate::{perm::Permutation, vole::backtracking::Backtrack};
+use std::{cmp::Ordering, rc::Rc};
+
+/// Edge label joining a point to the positions it occurs at in the sequence
+const MEMBER_LABEL: usize = 0;
+/// Edge label joining consecutive positions in the sequence
+const NEXT_LABEL: usize = 1;
+
+/// The position in `v` where its smallest rotation starts, found in O(n) comparisons.
+/// Rotations starting at `i` and `j` are compared, and when they first differ after `k`
+/// equal elements, none of the `k + 1` positions from the larger start can be smallest.
+fn min_rotation_start<T: Ord>(v: &[T]) -> usize {
+    let n = v.len();
+    let (mut i, mut j, mut k) = (0, 1, 0);
+    while i < n && j < n && k < n {
+        match v[(i + k) % n].cmp(&v[(j + k) % n]) {
+            Ordering::Equal => k += 1,
+            Ordering::Greater => {
+                i += k + 1;
+                if i == j {
+                    i += 1;
+                }
+                k = 0;
+            }
+            Ordering::Less => {
+                j += k + 1;
+                if i == j {
+                    j += 1;
+                }
+                k = 0;
+            }
+        }
+    }
+    i.min(j)
+}
+
+/// The smallest rotation of `v`
+pub fn min_rotation<T: Ord + Clone>(v: &[T]) -> Vec<T> {
+    if v.is_empty() {
+        return vec![];
+    }
+    let start = min_rotation_start(v);
+    [&v[start..], &v[..start]].concat()
+}
+
+/// The smallest rotation of `v`, and (if `directed` is false) of the reverse of `v`
+fn canonical_necklace(v: &[usize], directed: bool) -> Vec<usize> {
+    let rot = min_rotation(v);
+    if directed {
+        rot
+    } else {
+        let rev: Vec<usize> = v.iter().rev().copied().collect();
+        rot.min(min_rotation(&rev))
+    }
+}
+
+/// Refiner for cyclic sequences, where a sequence can be mapped to any rotation of
+/// another sequence, and if `directed` is false, any reflection. The sequence is
+/// represented by a (directed or undirected) cycle on one new vertex for each position,
+/// with each point joined to the positions it occurs at.
+pub struct NecklaceTransporter {
+    necklace_left: Rc<Vec<usize>>,
+    necklace_right: Rc<Vec<usize>>,
+    directed: bool,
+}
+
+impl NecklaceTransporter {
+    pub fn new_transporter(necklace_left: Vec<usize>, necklace_right: Vec<usize>, directed: bool) -> Self {
+        Self {
+            necklace_left: Rc::new(canonical_necklace(&necklace_left, directed)),
+            necklace_right: Rc::new(canonical_necklace(&necklace_right, directed)),
+            directed,
+        }
+    }
+
+    pub fn new_stabilizer(necklace: Vec<usize>, directed: bool) -> Self {
+        let r = Rc::new(canonical_necklace(&necklace, directed));
+        Self {
+            necklace_left: r.clone(),
+            necklace_right: r,
+            directed,
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> Vec<usize> {
+        let necklace = match side {
+            Side::Left => &self.necklace_left,
+            Side::Right => &self.necklace_right,
+        };
+
+        let image: Vec<usize> = necklace.iter().map(|&x| p.apply(x)).collect();
+        canonical_necklace(&image, self.directed)
+    }
+
+    fn compare(&self, lhs: &[usize], rhs: &[usize]) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl Refiner for NecklaceTransporter {
+    gen_any_image_compare!(Vec<usize>);
+
+    fn name(&self) -> String {
+        let kind = if self.directed { "Directed" } else { "Undirected" };
+        if self.is_group() {
+            format!("{}NecklaceStabilizer of {:?}", kind, self.necklace_left)
+        } else {
+            format!(
+                "{}NecklaceTransporter of {:?} -> {:?}",
+                kind, self.necklace_left, self.necklace_right
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        if self.necklace_left.len() != self.necklace_right.len() {
+            return false;
+        }
+
+        self.image(p, Side::Left) == *self.necklace_right
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let necklace = match side {
+            Side::Left => &self.necklace_left,
+            Side::Right => &self.necklace_right,
+        };
+
+        state.add_invariant_fact(necklace.len())?;
+
+        if necklace.is_empty() {
+            return Ok(());
+        }
+
+        let base = state.partition().base_domain_size();
+        let extended = state.partition().extended_domain_size();
+        let len = necklace.len();
+        let _ = state.extend_partition(len);
+
+        let mut graph: Vec<Vec<(usize, usize)>> = vec![vec![]; extended + len];
+        for (pos, &val) in necklace.iter().enumerate() {
+            debug_assert!(val < base);
+            let vert = extended + pos;
+            let next = extended + (pos + 1) % len;
+            graph[val].push((vert, MEMBER_LABEL));
+            graph[vert].push((next, NEXT_LABEL));
+            if !self.directed {
+                graph[next].push((vert, NEXT_LABEL));
+            }
+        }
+
+        state.add_graph(&Digraph::from_labelled_vec(graph));
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        self.necklace_left == self.necklace_right
+    }
+}
+
+impl Backtrack for NecklaceTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::min_rotation;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn min_rotation_matches_brute_force() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let len = rng.gen_range(0..12);
+            let v: Vec<usize> = (0..len).map(|_| rng.gen_range(0..3)).collect();
+            let brute = (0..v.len())
+                .map(|i| [&v[i..], &v[..i]].concat())
+                .min()
+                .unwrap_or_default();
+            assert_eq!(min_rotation(&v), brute, "{:?}", v);
+        }
+    }
+}
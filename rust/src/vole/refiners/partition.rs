@@ -0,0 +1,232 @@
+use super::Refiner;
+
+use super::{super::domain_state::DomainState, Side};
+use crate::{datastructures::sortedvec::SortedVec, vole::trace};
+use crate::{perm::Permutation, vole::backtracking::Backtrack};
+use std::{cmp::Ordering, collections::HashMap, rc::Rc};
+
+/// A partition of (some of) the points, stored as its list of cells, and
+/// a map from each point to (1 + the index of its cell), where 0 denotes
+/// the point is in no cell.
+struct PartitionData {
+    cells: Vec<SortedVec<usize>>,
+    cell_of: Vec<usize>,
+}
+
+impl PartitionData {
+    fn new(cells: Vec<SortedVec<usize>>) -> Self {
+        let size = cells.iter().flatten().max().map_or(0, |&m| m + 1);
+        let mut cell_of = vec![0; size];
+        for (i, cell) in cells.iter().enumerate() {
+            for &x in cell {
+                assert!(cell_of[x] == 0, "Partition cells must be disjoint");
+                cell_of[x] = i + 1;
+            }
+        }
+        Self { cells, cell_of }
+    }
+
+    /// 1 + the index of the cell containing `x` (or 0 if `x` is in no cell)
+    fn cell_of(&self, x: usize) -> usize {
+        *self.cell_of.get(x).unwrap_or(&0)
+    }
+
+    /// Size of the cell containing `x` (or 0 if `x` is in no cell)
+    fn cell_size_of(&self, x: usize) -> usize {
+        match self.cell_of(x) {
+            0 => 0,
+            c => self.cells[c - 1].len(),
+        }
+    }
+
+    fn image(&self, p: &Permutation) -> Vec<SortedVec<usize>> {
+        self.cells
+            .iter()
+            .map(|c| c.iter().map(|&x| p.apply(x)).collect())
+            .collect()
+    }
+}
+
+/// Refiner for the stabilizer of an ordered partition, where each cell must
+/// be mapped to itself (or in the transporter case, the cell in the same position)
+pub struct OrderedPartitionTransporter {
+    partition_left: Rc<PartitionData>,
+    partition_right: Rc<PartitionData>,
+}
+
+impl OrderedPartitionTransporter {
+    pub fn new_transporter(cells_left: Vec<SortedVec<usize>>, cells_right: Vec<SortedVec<usize>>) -> Self {
+        Self {
+            partition_left: Rc::new(PartitionData::new(cells_left)),
+            partition_right: Rc::new(PartitionData::new(cells_right)),
+        }
+    }
+
+    pub fn new_stabilizer(cells: Vec<SortedVec<usize>>) -> Self {
+        let r = Rc::new(PartitionData::new(cells));
+        Self {
+            partition_left: r.clone(),
+            partition_right: r,
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> Vec<SortedVec<usize>> {
+        match side {
+            Side::Left => self.partition_left.image(p),
+            Side::Right => self.partition_right.image(p),
+        }
+    }
+
+    fn compare(&self, lhs: &[SortedVec<usize>], rhs: &[SortedVec<usize>]) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl Refiner for OrderedPartitionTransporter {
+    gen_any_image_compare!(Vec<SortedVec<usize>>);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("OrderedPartitionStabilizer of {:?}", self.partition_left.cells)
+        } else {
+            format!(
+                "OrderedPartitionTransporter of {:?} -> {:?}",
+                self.partition_left.cells, self.partition_right.cells
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        self.partition_left.image(p) == self.partition_right.cells
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let partition = match side {
+            Side::Left => &self.partition_left,
+            Side::Right => &self.partition_right,
+        };
+
+        state.add_invariant_fact(partition.cells.len())?;
+
+        state.base_refine_partition_by(|x| partition.cell_of(*x))?;
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        Rc::ptr_eq(&self.partition_left, &self.partition_right)
+            || self.partition_left.cells == self.partition_right.cells
+    }
+}
+
+impl Backtrack for OrderedPartitionTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
+
+/// Refiner for the stabilizer of an unordered partition, where cells can be
+/// permuted amongst themselves
+pub struct UnorderedPartitionTransporter {
+    partition_left: Rc<PartitionData>,
+    partition_right: Rc<PartitionData>,
+    image_right: Rc<SortedVec<SortedVec<usize>>>,
+}
+
+impl UnorderedPartitionTransporter {
+    pub fn new_transporter(cells_left: Vec<SortedVec<usize>>, cells_right: Vec<SortedVec<usize>>) -> Self {
+        let partition_right = PartitionData::new(cells_right);
+        Self {
+            partition_left: Rc::new(PartitionData::new(cells_left)),
+            image_right: Rc::new(partition_right.cells.iter().cloned().collect()),
+            partition_right: Rc::new(partition_right),
+        }
+    }
+
+    pub fn new_stabilizer(cells: Vec<SortedVec<usize>>) -> Self {
+        let r = Rc::new(PartitionData::new(cells));
+        Self {
+            partition_left: r.clone(),
+            image_right: Rc::new(r.cells.iter().cloned().collect()),
+            partition_right: r,
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> SortedVec<SortedVec<usize>> {
+        let partition = match side {
+            Side::Left => &self.partition_left,
+            Side::Right => &self.partition_right,
+        };
+        partition.image(p).into_iter().collect()
+    }
+
+    fn compare(&self, lhs: &SortedVec<SortedVec<usize>>, rhs: &SortedVec<SortedVec<usize>>) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl Refiner for UnorderedPartitionTransporter {
+    gen_any_image_compare!(SortedVec<SortedVec<usize>>);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("UnorderedPartitionStabilizer of {:?}", self.partition_left.cells)
+        } else {
+            format!(
+                "UnorderedPartitionTransporter of {:?} -> {:?}",
+                self.partition_left.cells, self.partition_right.cells
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        self.image(p, Side::Left) == *self.image_right
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let partition = match side {
+            Side::Left => &self.partition_left,
+            Side::Right => &self.partition_right,
+        };
+
+        state.add_invariant_fact(partition.cells.len())?;
+
+        state.base_refine_partition_by(|x| partition.cell_size_of(*x))?;
+        Ok(())
+    }
+
+    fn refine_fixed_points(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let partition = match side {
+            Side::Left => &self.partition_left,
+            Side::Right => &self.partition_right,
+        };
+
+        // Each cell containing a fixed point is now distinguished, by the first fixed
+        // point it contains
+        let mut first_fixed: HashMap<usize, usize> = HashMap::new();
+        for (pos, &x) in state.partition().base_fixed_values().iter().enumerate() {
+            let cell = partition.cell_of(x);
+            if cell != 0 {
+                first_fixed.entry(cell).or_insert(pos);
+            }
+        }
+
+        let first_fixed = &first_fixed;
+        state.base_refine_partition_by(|x| *first_fixed.get(&partition.cell_of(*x)).unwrap_or(&usize::MAX))?;
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        Rc::ptr_eq(&self.partition_left, &self.partition_right)
+            || self.image(&Permutation::id(), Side::Left) == *self.image_right
+    }
+}
+
+impl Backtrack for UnorderedPartitionTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
@@ -2,23 +2,40 @@ use std::cmp::Ordering;
 
 use tracing::{info, trace_span};
 
-use crate::vole::trace::TraceEvent;
 use crate::vole::{
     backtracking::{Backtrack, Backtracking},
     trace,
 };
 use crate::vole::{
     refiners::{Refiner, Side},
-    stats::Stats,
+    stats::{ConsistencyCheckSource, RefinerInconsistency, Stats},
+};
+use crate::vole::{trace::TraceEvent, trace_dump::TraceSource};
+use crate::{
+    datastructures::hash::{do_hash, QHash},
+    perm::Permutation,
+    vole::domain_state::DomainState,
 };
-use crate::{perm::Permutation, vole::domain_state::DomainState};
 
 use std::any::Any;
 
+/// The result of comparing a canonical image with the best found so far
+pub enum CanonicalComparison {
+    /// The new image is smaller, and these are its images
+    Smaller(Vec<Box<dyn Any>>),
+    /// The new image is the same as the best found so far
+    Equal,
+    /// The new image is larger
+    Larger,
+}
+
 /// Store all refiners, and the current state of the refiners
 pub struct RefinerStore {
     /// List of refiners
     refiners: Vec<Box<dyn Refiner>>,
+    /// The index of the refiner for the group we are searching in, when finding canonical images.
+    /// If this is `None`, we search in the whole symmetric group.
+    group: Option<usize>,
     /// For each refiner, the number of fixed values in the partition the last time the refiner was called
     base_fixed_values_considered: Vec<Backtracking<usize>>,
     /// For each refiner, the number of cells of the partition last time the refiner was called
@@ -33,18 +50,69 @@ impl RefinerStore {
         let len = refiners.len();
         Self {
             refiners,
+            group: None,
             base_fixed_values_considered: std::iter::repeat_with(|| Backtracking::new(0)).take(len).collect(),
             cells_considered: std::iter::repeat_with(|| Backtracking::new(0)).take(len).collect(),
             saved_depth: 0,
         }
     }
 
+    /// Replace the refiners with `refiners`, as [RefinerStore::new_from_refiners] does, but
+    /// reusing the memory already allocated. The refiner for the group we are searching
+    /// in keeps the same index.
+    pub fn reset(&mut self, refiners: Vec<Box<dyn Refiner>>) {
+        assert!(
+            self.saved_depth == 0,
+            "Refiners can only be reset at the root of search"
+        );
+        self.refiners = refiners;
+        if let Some(group) = self.group {
+            assert!(group < self.refiners.len(), "Invalid group refiner {}", group);
+            assert!(self.refiners[group].is_group(), "Group refiner must be for a group");
+        }
+        let len = self.refiners.len();
+        self.base_fixed_values_considered.clear();
+        self.base_fixed_values_considered
+            .resize_with(len, || Backtracking::new(0));
+        self.cells_considered.clear();
+        self.cells_considered.resize_with(len, || Backtracking::new(0));
+    }
+
+    /// Set the refiner with index `group` as the refiner for the group we are searching in
+    pub fn with_group(mut self, group: usize) -> Self {
+        assert!(group < self.refiners.len(), "Invalid group refiner {}", group);
+        assert!(self.refiners[group].is_group(), "Group refiner must be for a group");
+        self.group = Some(group);
+        self
+    }
+
+    /// Check if the refiner with index `i` is for a group
+    pub fn is_group(&self, i: usize) -> bool {
+        self.refiners[i].is_group()
+    }
+
+    /// The index of the refiner for the group we are searching in, if there is one
+    pub fn group(&self) -> Option<usize> {
+        self.group
+    }
+
+    /// The refiners whose images are compared when finding canonical images, which
+    /// is every refiner except the one for the group we are searching in
+    fn canonical_refiners(&self) -> impl Iterator<Item = &Box<dyn Refiner>> {
+        self.refiners
+            .iter()
+            .enumerate()
+            .filter(move |&(i, _)| Some(i) != self.group)
+            .map(|(_, r)| r)
+    }
+
     /// Initialise the refiners
     pub fn init_refine(&mut self, state: &mut DomainState, side: Side, stats: &mut Stats) -> trace::Result<()> {
         let _span = trace_span!("init_refine:", side = debug(side)).entered();
         for (i, r) in self.refiners.iter_mut().enumerate() {
             *self.base_fixed_values_considered[i] = state.partition().base_fixed_values().len();
             *self.cells_considered[i] = state.partition().base_cells().len();
+            state.set_trace_source(TraceSource::Refiner(i));
             r.refine_begin(state, side)?;
             stats.refiner_calls += 1;
         }
@@ -61,6 +129,7 @@ impl RefinerStore {
                 let fixed_points = state.partition().base_fixed_values().len();
                 if fixed_points > *self.base_fixed_values_considered[i] {
                     *self.base_fixed_values_considered[i] = fixed_points;
+                    state.set_trace_source(TraceSource::Refiner(i));
                     refiner.refine_fixed_points(state, side)?;
                     stats.refiner_calls += 1;
                 }
@@ -72,12 +141,15 @@ impl RefinerStore {
                 let cells = state.partition().base_cells().len();
                 if cells > *self.cells_considered[i] {
                     *self.cells_considered[i] = cells;
+                    state.set_trace_source(TraceSource::Refiner(i));
                     refiner.refine_changed_cells(state, side)?;
                     stats.refiner_calls += 1;
                 }
             }
 
+            state.set_trace_source(TraceSource::Graph);
             state.refine_graphs()?;
+            state.set_trace_source(TraceSource::Search);
 
             if init_fixed_points == state.partition().base_fixed_values().len()
                 && init_cells == state.partition().base_cells().len()
@@ -89,6 +161,16 @@ impl RefinerStore {
         }
     }
 
+    /// The number of refiners
+    pub fn refiner_count(&self) -> usize {
+        self.refiners.len()
+    }
+
+    /// The names of all refiners
+    pub fn names(&self) -> Vec<String> {
+        self.refiners.iter().map(|r| r.name()).collect()
+    }
+
     /// Inform all refiners that 'state' is currently the 'rbase', in case they need to store any information
     pub fn snapshot_rbase(&mut self, state: &mut DomainState) {
         for refiner in &mut self.refiners {
@@ -96,70 +178,129 @@ impl RefinerStore {
         }
     }
 
-    /// Get the 'image' of each refiner under the permutation 'p', skipping the first refiner
+    /// Get the 'image' of each refiner under the permutation 'p', skipping the group refiner
     pub fn get_canonical_images(&self, p: &Permutation) -> Vec<Box<dyn Any>> {
-        return self
-            .refiners
-            .iter()
-            .skip(1)
-            .map(|r| r.any_image(p, Side::Left))
-            .collect();
+        self.canonical_refiners().map(|r| r.any_image(p, Side::Left)).collect()
     }
 
-    /// Check if applying 'p' produces a small image than 'prev'.
-    /// Note that we skip the first refiner, as it is for the group we are searching in.
-    pub fn get_smaller_canonical_image(
+    /// Compare the image produced by applying 'p' with 'prev'.
+    /// Note that we skip the group refiner, as it is for the group we are searching in.
+    /// Refiners are compared one at a time, stopping at the first one which is not equal,
+    /// so the full list of images is only built when it is smaller.
+    pub fn compare_with_canonical_image(
         &self,
         p: &Permutation,
         prev: &[Box<dyn Any>],
         stats: &mut Stats,
-    ) -> Option<Vec<Box<dyn Any>>> {
-        // TODO: Speed up
-        let image = self.get_canonical_images(p);
-        let len = image.len();
-        info!(
-            "Comparing Canonical. prev: {:?} image: {:?}",
-            (0..len)
-                .map(|i| self.refiners[i + 1].any_to_string(&prev[i]))
-                .collect::<Vec<_>>(),
-            (0..len)
-                .map(|i| self.refiners[i + 1].any_to_string(&image[i]))
-                .collect::<Vec<_>>()
-        );
-        for i in 0..prev.len() {
-            let ord = self.refiners[i + 1].any_compare(&image[i], &prev[i]);
-            match ord {
-                std::cmp::Ordering::Less => {
-                    info!("Improved Canonical");
-                    stats.improve_canonical += 1;
-                    return Some(image);
-                }
-                std::cmp::Ordering::Equal => {}
-                std::cmp::Ordering::Greater => {
-                    info!("Worse Canonical");
-                    stats.bad_canonical += 1;
-                    return None;
-                }
+    ) -> CanonicalComparison {
+        let mut ord = Ordering::Equal;
+        for (r, prev) in self.canonical_refiners().zip(prev) {
+            ord = r.any_compare_image(p, prev);
+            if ord != Ordering::Equal {
+                info!("Canonical images differ at {}: {:?}", r.name(), ord);
+                break;
             }
         }
-        info!("Found identical canonical image");
-        stats.equal_canonical += 1;
-        None
+        match ord {
+            Ordering::Less => {
+                info!("Improved Canonical");
+                stats.improve_canonical += 1;
+                CanonicalComparison::Smaller(self.get_canonical_images(p))
+            }
+            Ordering::Equal => {
+                info!("Found identical canonical image");
+                stats.equal_canonical += 1;
+                CanonicalComparison::Equal
+            }
+            Ordering::Greater => {
+                info!("Worse Canonical");
+                stats.bad_canonical += 1;
+                CanonicalComparison::Larger
+            }
+        }
+    }
+
+    /// Describe a list of images produced by [Self::get_canonical_images], one image per line
+    pub fn canonical_images_to_string(&self, images: &[Box<dyn Any>]) -> String {
+        self.canonical_refiners()
+            .zip(images)
+            .map(|(r, image)| r.any_to_string(image))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Hash a list of images produced by [Self::get_canonical_images]. Equal lists of
+    /// images have equal hashes.
+    pub fn hash_canonical_images(&self, images: &[Box<dyn Any>]) -> QHash {
+        let hashes: Vec<QHash> = self
+            .canonical_refiners()
+            .zip(images)
+            .map(|(r, image)| r.any_hash(image))
+            .collect();
+        do_hash(hashes).0
+    }
+
+    /// Check if `lhs`, a list of images produced by [Self::get_canonical_images], is equal to
+    /// `rhs`, which may have been produced by another list of refiners. Lists whose images
+    /// are of different types are not equal.
+    pub fn canonical_images_equal(&self, lhs: &[Box<dyn Any>], rhs: &[Box<dyn Any>]) -> bool {
+        lhs.len() == rhs.len()
+            && lhs.iter().zip(rhs).all(|(l, r)| (**l).type_id() == (**r).type_id())
+            && self.compare_canonical_images(lhs, rhs) == Ordering::Equal
+    }
+
+    /// Lexicographically compare two lists of images produced by [Self::get_canonical_images]
+    pub fn compare_canonical_images(&self, lhs: &[Box<dyn Any>], rhs: &[Box<dyn Any>]) -> Ordering {
+        for ((r, lhs), rhs) in self.canonical_refiners().zip(lhs).zip(rhs) {
+            let ord = r.any_compare(lhs, rhs);
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Check the group refiner (which defines the group we are searching in) accepts `p`
+    pub fn check_group(&self, p: &Permutation) -> bool {
+        self.group.is_none_or(|g| self.refiners[g].check(p))
     }
 
     /// Check every refiner accepts `p`
     pub fn check_all(&self, p: &Permutation) -> bool {
-        // This line checks that the 'check' function, and the canonical image code, agree
-        debug_assert!(self.refiners.iter().all(|x| x.check(p)
-            == (x.any_compare(
-                &x.any_image(p, Side::Left),
-                &x.any_image(&Permutation::id(), Side::Right)
-            ) == Ordering::Equal)));
-
         self.refiners.iter().all(|x| x.check(p))
     }
 
-    pub fn iter_mut(&mut self) -> std::slice::IterMut<Box<dyn Refiner>> {
+    /// The index of the first refiner which rejects `p`, if any
+    pub fn first_rejecting(&self, p: &Permutation) -> Option<usize> {
+        self.refiners.iter().position(|x| !x.check(p))
+    }
+
+    /// Find every refiner where the 'check' function, and the canonical image code,
+    /// disagree about if `p` maps the left side to the right side
+    pub fn find_inconsistencies(&self, p: &Permutation, source: ConsistencyCheckSource) -> Vec<RefinerInconsistency> {
+        self.refiners
+            .iter()
+            .filter_map(|r| {
+                let check = r.check(p);
+                let left_image = r.any_image(p, Side::Left);
+                let right_image = r.any_image(&Permutation::id(), Side::Right);
+                if check == (r.any_compare(&left_image, &right_image) == Ordering::Equal) {
+                    None
+                } else {
+                    Some(RefinerInconsistency {
+                        refiner: r.name(),
+                        perm: p.as_vec().to_vec(),
+                        source,
+                        check,
+                        left_image: r.any_to_string(&left_image),
+                        right_image: r.any_to_string(&right_image),
+                    })
+                }
+            })
+            .collect()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Box<dyn Refiner>> {
         self.refiners.iter_mut()
     }
 }
@@ -2,11 +2,16 @@ use super::Refiner;
 
 use super::{super::domain_state::DomainState, Side};
 use crate::{
-    datastructures::{digraph::Digraph, sortedvec::SortedVec},
+    datastructures::{
+        digraph::Digraph,
+        hash::{do_hash, QuickHashable},
+        sortedvec::SortedVec,
+    },
     vole::trace,
 };
 use crate::{perm::Permutation, vole::backtracking::Backtrack};
-use std::{cmp::Ordering, collections::HashMap, rc::Rc};
+use itertools::Itertools;
+use std::{cmp::Ordering, collections::HashMap, num::Wrapping, rc::Rc};
 
 pub struct SetTransporter {
     set_left: Rc<SortedVec<usize>>,
@@ -406,3 +411,340 @@ impl Backtrack for SetTupleTransporter {
         0
     }
 }
+
+/// A multiset is stored as a sorted list, where each value occurs as many
+/// times as its multiplicity, and as a map from value to multiplicity
+pub struct MultisetTransporter {
+    multiset_left: Rc<SortedVec<usize>>,
+    countmap_left: Rc<HashMap<usize, usize>>,
+    multiset_right: Rc<SortedVec<usize>>,
+    countmap_right: Rc<HashMap<usize, usize>>,
+}
+
+impl MultisetTransporter {
+    fn count_map(multiset: &SortedVec<usize>) -> HashMap<usize, usize> {
+        multiset
+            .iter()
+            .copied()
+            .dedup_with_count()
+            .map(|(c, x)| (x, c))
+            .collect()
+    }
+
+    pub fn new_transporter(multiset_left: SortedVec<usize>, multiset_right: SortedVec<usize>) -> Self {
+        Self {
+            countmap_left: Rc::new(Self::count_map(&multiset_left)),
+            multiset_left: Rc::new(multiset_left),
+            countmap_right: Rc::new(Self::count_map(&multiset_right)),
+            multiset_right: Rc::new(multiset_right),
+        }
+    }
+
+    pub fn new_stabilizer(multiset: SortedVec<usize>) -> Self {
+        let countmap = Rc::new(Self::count_map(&multiset));
+        let r = Rc::new(multiset);
+        Self {
+            multiset_left: r.clone(),
+            countmap_left: countmap.clone(),
+            multiset_right: r,
+            countmap_right: countmap,
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> SortedVec<usize> {
+        let multiset = match side {
+            Side::Left => &self.multiset_left,
+            Side::Right => &self.multiset_right,
+        };
+
+        multiset.iter().map(|&x| p.apply(x)).collect()
+    }
+
+    fn compare(&self, lhs: &SortedVec<usize>, rhs: &SortedVec<usize>) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl Refiner for MultisetTransporter {
+    gen_any_image_compare!(SortedVec<usize>);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("MultisetStabilizer of {:?}", self.multiset_left)
+        } else {
+            format!(
+                "MultisetTransporter of {:?} -> {:?}",
+                self.multiset_left, self.multiset_right
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        if self.multiset_left.len() != self.multiset_right.len() {
+            return false;
+        }
+
+        self.countmap_left
+            .iter()
+            .all(|(&x, c)| self.countmap_right.get(&p.apply(x)) == Some(c))
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let (multiset, countmap) = match side {
+            Side::Left => (&self.multiset_left, &self.countmap_left),
+            Side::Right => (&self.multiset_right, &self.countmap_right),
+        };
+
+        state.add_invariant_fact(multiset.len())?;
+
+        state.base_refine_partition_by(|x| countmap.get(x).unwrap_or(&0))?;
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        self.multiset_left == self.multiset_right
+    }
+}
+
+impl Backtrack for MultisetTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
+
+/// Each multiset is stored as a sorted list, where each value occurs as many
+/// times as its multiplicity
+pub struct SetMultisetTransporter {
+    set_left: Rc<SortedVec<SortedVec<usize>>>,
+    set_right: Rc<SortedVec<SortedVec<usize>>>,
+}
+
+impl SetMultisetTransporter {
+    pub fn new_transporter(set_left: SortedVec<SortedVec<usize>>, set_right: SortedVec<SortedVec<usize>>) -> Self {
+        Self {
+            set_left: Rc::new(set_left),
+            set_right: Rc::new(set_right),
+        }
+    }
+
+    pub fn new_stabilizer(set: SortedVec<SortedVec<usize>>) -> Self {
+        let r = Rc::new(set);
+        Self {
+            set_left: r.clone(),
+            set_right: r,
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> SortedVec<SortedVec<usize>> {
+        let set = match side {
+            Side::Left => &self.set_left,
+            Side::Right => &self.set_right,
+        };
+
+        set.iter().map(|x| x.iter().map(|&y| p.apply(y)).collect()).collect()
+    }
+
+    fn compare(&self, lhs: &SortedVec<SortedVec<usize>>, rhs: &SortedVec<SortedVec<usize>>) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl Refiner for SetMultisetTransporter {
+    gen_any_image_compare!(SortedVec<SortedVec<usize>>);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("SetMultisetStabilizer of {:?}", self.set_left)
+        } else {
+            format!("SetMultisetTransporter of {:?} -> {:?}", self.set_left, self.set_right)
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        if self.set_left.len() != self.set_right.len() {
+            return false;
+        }
+
+        self.image(p, Side::Left) == *self.set_right
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let set = match side {
+            Side::Left => &self.set_left,
+            Side::Right => &self.set_right,
+        };
+
+        // Record: number of multisets in the set, whether set contains the empty multiset
+        state.add_invariant_fact(set.len())?;
+        state.add_invariant_fact(set.iter().any(|x| x.is_empty()))?;
+
+        if set.is_empty() {
+            return Ok(());
+        }
+
+        let base = state.partition().base_domain_size();
+        let extended = state.partition().extended_domain_size();
+        let _ = state.extend_partition(set.len());
+
+        // Each multiset gets a new vertex, with an edge from each of its members
+        // labelled by the multiplicity of that member
+        let mut v: Vec<Vec<(usize, usize)>> = vec![vec![]; extended + set.len()];
+        for (s, i) in set.iter().enumerate() {
+            for (count, &val) in i.iter().dedup_with_count() {
+                debug_assert!(val < base);
+                v[val].push((s + extended, count));
+            }
+        }
+
+        state.add_graph(&Digraph::from_labelled_vec(v));
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        self.set_left == self.set_right
+    }
+}
+
+impl Backtrack for SetMultisetTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
+
+/// A relational structure is stored as a set of (label, tuple) pairs, where
+/// `label` identifies which relation the tuple is in
+pub struct RelationalStructureTransporter {
+    relations_left: Rc<SortedVec<(usize, Vec<usize>)>>,
+    relations_right: Rc<SortedVec<(usize, Vec<usize>)>>,
+}
+
+impl RelationalStructureTransporter {
+    pub fn new_transporter(
+        relations_left: SortedVec<(usize, Vec<usize>)>,
+        relations_right: SortedVec<(usize, Vec<usize>)>,
+    ) -> Self {
+        Self {
+            relations_left: Rc::new(relations_left),
+            relations_right: Rc::new(relations_right),
+        }
+    }
+
+    pub fn new_stabilizer(relations: SortedVec<(usize, Vec<usize>)>) -> Self {
+        let r = Rc::new(relations);
+        Self {
+            relations_left: r.clone(),
+            relations_right: r,
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> SortedVec<(usize, Vec<usize>)> {
+        let relations = match side {
+            Side::Left => &self.relations_left,
+            Side::Right => &self.relations_right,
+        };
+
+        relations
+            .iter()
+            .map(|(label, x)| (*label, x.iter().map(|&y| p.apply(y)).collect()))
+            .collect()
+    }
+
+    fn compare(&self, lhs: &SortedVec<(usize, Vec<usize>)>, rhs: &SortedVec<(usize, Vec<usize>)>) -> Ordering {
+        lhs.cmp(rhs)
+    }
+}
+
+impl Refiner for RelationalStructureTransporter {
+    gen_any_image_compare!(SortedVec<(usize, Vec<usize>)>);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("RelationalStructureStabilizer of {:?}", self.relations_left)
+        } else {
+            format!(
+                "RelationalStructureTransporter of {:?} -> {:?}",
+                self.relations_left, self.relations_right
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        if self.relations_left.len() != self.relations_right.len() {
+            return false;
+        }
+
+        self.relations_left.iter().all(|(label, tuple)| {
+            let image: Vec<usize> = tuple.iter().map(|&x| p.apply(x)).collect();
+            self.relations_right.contains(&(*label, image))
+        })
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let relations = match side {
+            Side::Left => &self.relations_left,
+            Side::Right => &self.relations_right,
+        };
+
+        // Record: number of tuples, which relations contain the empty tuple
+        state.add_invariant_fact(relations.len())?;
+        let empty_labels: Vec<usize> = relations
+            .iter()
+            .filter(|(_, x)| x.is_empty())
+            .map(|(label, _)| *label)
+            .collect();
+        state.add_invariant_fact(do_hash(empty_labels))?;
+
+        let base = state.partition().base_domain_size();
+        let extended = state.partition().extended_domain_size();
+
+        // As in SetTupleTransporter, we add one new vertex for each entry in each tuple,
+        // but colour these new vertices by both the relation label and position in the tuple
+        let extra_points = relations.iter().map(|(_, x)| x.len()).sum();
+        if extra_points == 0 {
+            return Ok(());
+        }
+        let total_new_size = extended + extra_points;
+        let mut colouring = vec![Wrapping(0); total_new_size];
+        let mut graph: Vec<Vec<usize>> = vec![vec![]; total_new_size];
+
+        let mut new_vert = extended;
+
+        for (label, tuple) in relations.iter() {
+            let label_hash = label.quick_hash();
+            for (pos, &val) in tuple.iter().enumerate() {
+                debug_assert!(val < base);
+                colouring[new_vert] = (label_hash, pos + 1).quick_hash();
+                graph[val].push(new_vert);
+                if pos > 0 {
+                    graph[new_vert].push(new_vert - 1)
+                }
+                new_vert += 1;
+            }
+        }
+
+        assert!(new_vert == total_new_size);
+
+        let new_part = state.extend_partition(extra_points);
+
+        state.refine_partition_cell_by(new_part, |x| colouring[*x])?;
+
+        state.add_graph(&Digraph::from_vec(graph));
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        self.relations_left == self.relations_right
+    }
+}
+
+impl Backtrack for RelationalStructureTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
@@ -0,0 +1,162 @@
+use super::Refiner;
+
+use super::{super::domain_state::DomainState, Side};
+use crate::{
+    datastructures::digraph::Digraph,
+    vole::{parse_input::Structure, trace},
+};
+use crate::{perm::Permutation, vole::backtracking::Backtrack};
+use std::{cmp::Ordering, rc::Rc};
+
+/// Edge label joining a member of a set, multiset or cycle to its parent
+const MEMBER_LABEL: usize = 0;
+/// Edge label joining consecutive members of a cycle
+const CYCLE_NEXT_LABEL: usize = 1;
+/// Edge label joining the first member of a tuple to its parent. Later members
+/// use `TUPLE_LABEL + position`.
+const TUPLE_LABEL: usize = 2;
+
+/// Refiner for any [Structure]. The structure is encoded as a graph, with
+/// one extra vertex for each set, tuple, multiset or cycle in the structure.
+pub struct StructureTransporter {
+    structure_left: Rc<Structure>,
+    structure_right: Rc<Structure>,
+}
+
+impl StructureTransporter {
+    pub fn new_transporter(structure_left: Structure, structure_right: Structure) -> Self {
+        Self {
+            structure_left: Rc::new(structure_left.canonicalise()),
+            structure_right: Rc::new(structure_right.canonicalise()),
+        }
+    }
+
+    pub fn new_stabilizer(structure: Structure) -> Self {
+        let r = Rc::new(structure.canonicalise());
+        Self {
+            structure_left: r.clone(),
+            structure_right: r,
+        }
+    }
+
+    fn image(&self, p: &Permutation, side: Side) -> Structure {
+        let structure = match side {
+            Side::Left => &self.structure_left,
+            Side::Right => &self.structure_right,
+        };
+
+        structure.map_points(|x| p.apply(x)).canonicalise()
+    }
+
+    fn compare(&self, lhs: &Structure, rhs: &Structure) -> Ordering {
+        lhs.cmp(rhs)
+    }
+
+    /// Add the vertices and edges representing `node` to `graph`, colouring
+    /// each new vertex by the type and size of node it represents.
+    /// Returns the vertex which represents `node`.
+    fn add_node(
+        node: &Structure,
+        next_vertex: &mut usize,
+        graph: &mut Vec<Vec<(usize, usize)>>,
+        colouring: &mut Vec<usize>,
+    ) -> usize {
+        let (kind, children) = match node {
+            Structure::Point(x) => return *x,
+            Structure::Set(v) => (1, v),
+            Structure::Tuple(v) => (2, v),
+            Structure::Multiset(v) => (3, v),
+            Structure::Cycle(v) => (4, v),
+        };
+
+        let vertex = *next_vertex;
+        *next_vertex += 1;
+        // Leave 0 free, to mark the root
+        colouring[vertex] = (children.len() * 5 + kind) * 2;
+
+        let child_vertices: Vec<usize> = children
+            .iter()
+            .map(|c| Self::add_node(c, next_vertex, graph, colouring))
+            .collect();
+
+        for (pos, &c) in child_vertices.iter().enumerate() {
+            let label = match node {
+                Structure::Tuple(_) => TUPLE_LABEL + pos,
+                _ => MEMBER_LABEL,
+            };
+            graph[c].push((vertex, label));
+        }
+
+        if let Structure::Cycle(_) = node {
+            for (pos, &c) in child_vertices.iter().enumerate() {
+                graph[c].push((child_vertices[(pos + 1) % child_vertices.len()], CYCLE_NEXT_LABEL));
+            }
+        }
+
+        vertex
+    }
+}
+
+impl Refiner for StructureTransporter {
+    gen_any_image_compare!(Structure);
+
+    fn name(&self) -> String {
+        if self.is_group() {
+            format!("StructureStabilizer of {:?}", self.structure_left)
+        } else {
+            format!(
+                "StructureTransporter of {:?} -> {:?}",
+                self.structure_left, self.structure_right
+            )
+        }
+    }
+
+    fn check(&self, p: &Permutation) -> bool {
+        self.image(p, Side::Left) == *self.structure_right
+    }
+
+    fn refine_begin(&mut self, state: &mut DomainState, side: Side) -> trace::Result<()> {
+        let structure = match side {
+            Side::Left => &self.structure_left,
+            Side::Right => &self.structure_right,
+        };
+
+        // Record: number of extra vertices we are going to add
+        let extra_points = structure.inner_nodes();
+        state.add_invariant_fact(extra_points)?;
+
+        if let Structure::Point(x) = **structure {
+            state.base_refine_partition_by(|y| *y == x)?;
+            return Ok(());
+        }
+
+        let extended = state.partition().extended_domain_size();
+        let total_new_size = extended + extra_points;
+        let mut colouring = vec![0usize; total_new_size];
+        let mut graph: Vec<Vec<(usize, usize)>> = vec![vec![]; total_new_size];
+
+        let mut new_vert = extended;
+        let root = Self::add_node(structure, &mut new_vert, &mut graph, &mut colouring);
+        assert!(new_vert == total_new_size);
+        colouring[root] += 1;
+
+        let new_part = state.extend_partition(extra_points);
+
+        state.refine_partition_cell_by(new_part, |x| colouring[*x])?;
+
+        state.add_graph(&Digraph::from_labelled_vec(graph));
+        Ok(())
+    }
+
+    fn is_group(&self) -> bool {
+        self.structure_left == self.structure_right
+    }
+}
+
+impl Backtrack for StructureTransporter {
+    fn save_state(&mut self) {}
+    fn restore_state(&mut self) {}
+    fn state_depth(&self) -> usize {
+        0
+    }
+}
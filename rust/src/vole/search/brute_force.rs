@@ -0,0 +1,310 @@
+//! A reference solver, which checks every permutation of a small domain. This is
+//! used to validate the results of search (and new refiners).
+
+use std::{any::Any, cmp::Ordering, collections::HashSet};
+
+use itertools::Itertools;
+
+use crate::perm::Permutation;
+use crate::vole::{refiners::refiner_store::RefinerStore, solutions::Solutions};
+
+/// Largest domain size [brute_force_search] will accept
+pub const MAX_BRUTE_FORCE_POINTS: usize = 9;
+
+/// The smallest canonical images found by [brute_force_search]
+#[derive(Debug)]
+pub struct BruteForceCanonical {
+    /// The images of every refiner except the group refiner, as produced by [RefinerStore::get_canonical_images]
+    pub images: Vec<Box<dyn Any>>,
+    /// Every permutation accepted by the group refiner which produces `images`
+    pub perms: Vec<Permutation>,
+}
+
+#[derive(Debug)]
+pub struct BruteForceResult {
+    /// Every permutation of the domain accepted by all refiners
+    pub solutions: HashSet<Permutation>,
+    /// The lexicographically smallest canonical images, over every permutation
+    /// accepted by the group refiner
+    pub canonical: Option<BruteForceCanonical>,
+}
+
+/// A difference between the solutions found by search, and by [brute_force_search]
+#[derive(Debug, PartialEq, Eq)]
+pub enum BruteForceMismatch {
+    /// A permutation found by brute force, but not generated by search
+    Missing(Permutation),
+    /// A permutation generated by search, but not found by brute force
+    Extra(Permutation),
+}
+
+/// Check every permutation of `0..points` against `refiners`. This is only practical for
+/// very small domains, so `points` must be at most [MAX_BRUTE_FORCE_POINTS].
+// Permutation caches its inverse in a RefCell, which does not affect hashing
+#[allow(clippy::mutable_key_type)]
+pub fn brute_force_search(refiners: &RefinerStore, points: usize) -> BruteForceResult {
+    assert!(
+        points <= MAX_BRUTE_FORCE_POINTS,
+        "Brute force search only supports up to {} points",
+        MAX_BRUTE_FORCE_POINTS
+    );
+
+    let mut solutions = HashSet::new();
+    let mut canonical: Option<BruteForceCanonical> = None;
+
+    for v in (0..points).permutations(points) {
+        let p = Permutation::from_vec(v);
+        if refiners.check_all(&p) {
+            solutions.insert(p.clone());
+        }
+
+        if refiners.check_group(&p) {
+            let images = refiners.get_canonical_images(&p);
+            match &mut canonical {
+                None => canonical = Some(BruteForceCanonical { images, perms: vec![p] }),
+                Some(c) => match refiners.compare_canonical_images(&images, &c.images) {
+                    Ordering::Less => *c = BruteForceCanonical { images, perms: vec![p] },
+                    Ordering::Equal => c.perms.push(p),
+                    Ordering::Greater => {}
+                },
+            }
+        }
+    }
+
+    BruteForceResult { solutions, canonical }
+}
+
+/// All elements of the coset generated by the solutions found by search. If `sols` contains
+/// `s_1, s_2, ...` this is `s_1 * <s_1^-1 * s_2, ...>`, so for group searches (where the identity is found first)
+/// this is the group generated by the solutions.
+#[allow(clippy::mutable_key_type)]
+pub fn generated_solutions(sols: &Solutions) -> HashSet<Permutation> {
+    let sols = sols.get();
+    let first = match sols.first() {
+        Some(p) => p,
+        None => return HashSet::new(),
+    };
+
+    let first_inv = first.inv();
+    let gens: Vec<Permutation> = sols.iter().map(|s| first_inv.multiply(s)).collect();
+
+    let mut group: HashSet<Permutation> = HashSet::new();
+    group.insert(Permutation::id());
+    let mut todo = vec![Permutation::id()];
+    while let Some(p) = todo.pop() {
+        for g in &gens {
+            let q = p.multiply(g);
+            if group.insert(q.clone()) {
+                todo.push(q);
+            }
+        }
+    }
+
+    group.iter().map(|h| first.multiply(h)).collect()
+}
+
+/// Check the solutions found by search generate exactly the solutions found by brute force
+#[allow(clippy::mutable_key_type)]
+pub fn compare_with_brute_force(sols: &Solutions, brute: &BruteForceResult) -> Result<(), BruteForceMismatch> {
+    let generated = generated_solutions(sols);
+    if let Some(p) = brute.solutions.iter().find(|p| !generated.contains(p)) {
+        return Err(BruteForceMismatch::Missing(p.clone()));
+    }
+    if let Some(p) = generated.iter().find(|p| !brute.solutions.contains(p)) {
+        return Err(BruteForceMismatch::Extra(p.clone()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{brute_force_search, compare_with_brute_force};
+    use crate::datastructures::{digraph::Digraph, sortedvec::SortedVec};
+    use crate::perm::Permutation;
+    use crate::vole::{
+        domain_state::DomainState,
+        refiners::{
+            digraph::DigraphTransporter,
+            refiner_store::RefinerStore,
+            simple::{SetTransporter, TupleTransporter},
+            symmetricgrp::InSymmetricGrp,
+            Refiner,
+        },
+        search::{simple_coset_search, simple_group_search, SearchConfig},
+        solutions::Solutions,
+        state::State,
+        stats::Stats,
+        trace,
+    };
+
+    fn search(points: usize, refiners: Vec<Box<dyn Refiner>>, coset: bool) -> Solutions {
+        search_with_storage(points, refiners, coset, trace::TraceStorage::Full).0
+    }
+
+    fn search_with_storage(
+        points: usize,
+        refiners: Vec<Box<dyn Refiner>>,
+        coset: bool,
+        storage: trace::TraceStorage,
+    ) -> (Solutions, Stats) {
+        let tracer = trace::Tracer::new_with_storage(trace::TracingType::SYMMETRY, storage);
+        let mut state = State {
+            domain: DomainState::new(points, tracer),
+            refiners: RefinerStore::new_from_refiners(refiners),
+            stats: Default::default(),
+        };
+        let mut sols = Solutions::new(points);
+        let config = SearchConfig {
+            full_graph_refine: false,
+            trace_storage: storage,
+            ..Default::default()
+        };
+        if coset {
+            simple_coset_search(&mut state, &mut sols, &config);
+        } else {
+            simple_group_search(&mut state, &mut sols, &config);
+        }
+        (sols, state.stats)
+    }
+
+    fn set(v: &[usize]) -> SortedVec<usize> {
+        v.iter().copied().collect()
+    }
+
+    fn set_refiners() -> Vec<Box<dyn Refiner>> {
+        vec![
+            Box::new(SetTransporter::new_stabilizer(set(&[0, 1, 2]))),
+            Box::new(SetTransporter::new_stabilizer(set(&[2, 3]))),
+        ]
+    }
+
+    #[test]
+    fn group_matches_brute_force() {
+        let brute = brute_force_search(&RefinerStore::new_from_refiners(set_refiners()), 6);
+        // Sym({0,1}) x Sym({4,5})
+        assert_eq!(brute.solutions.len(), 4);
+        let sols = search(6, set_refiners(), false);
+        assert_eq!(compare_with_brute_force(&sols, &brute), Ok(()));
+    }
+
+    fn coset_refiners() -> Vec<Box<dyn Refiner>> {
+        vec![
+            Box::new(SetTransporter::new_transporter(set(&[0, 1, 2]), set(&[3, 4, 5]))),
+            Box::new(TupleTransporter::new_transporter(vec![0], vec![5])),
+        ]
+    }
+
+    #[test]
+    fn coset_matches_brute_force() {
+        let brute = brute_force_search(&RefinerStore::new_from_refiners(coset_refiners()), 6);
+        assert_eq!(brute.solutions.len(), 2 * 6);
+        let sols = search(6, coset_refiners(), true);
+        assert_eq!(compare_with_brute_force(&sols, &brute), Ok(()));
+    }
+
+    #[test]
+    fn empty_matches_brute_force() {
+        let refiners = || -> Vec<Box<dyn Refiner>> {
+            vec![
+                Box::new(SetTransporter::new_transporter(set(&[0, 1]), set(&[2, 3]))),
+                Box::new(SetTransporter::new_stabilizer(set(&[0, 1, 2]))),
+            ]
+        };
+        let brute = brute_force_search(&RefinerStore::new_from_refiners(refiners()), 5);
+        assert!(brute.solutions.is_empty());
+        let sols = search(5, refiners(), true);
+        assert_eq!(compare_with_brute_force(&sols, &brute), Ok(()));
+    }
+
+    #[test]
+    fn hashed_trace_matches_brute_force() {
+        let brute = brute_force_search(&RefinerStore::new_from_refiners(set_refiners()), 6);
+        let (sols, _) = search_with_storage(6, set_refiners(), false, trace::TraceStorage::Hashed);
+        assert_eq!(compare_with_brute_force(&sols, &brute), Ok(()));
+
+        let brute = brute_force_search(&RefinerStore::new_from_refiners(coset_refiners()), 6);
+        let (sols, _) = search_with_storage(6, coset_refiners(), true, trace::TraceStorage::Hashed);
+        assert_eq!(compare_with_brute_force(&sols, &brute), Ok(()));
+    }
+
+    /// Search for `refiners` with both full and hashed traces, and check they search the same nodes
+    fn assert_hashed_visits_same_nodes<F: Fn() -> Vec<Box<dyn Refiner>>>(refiners: F, coset: bool) {
+        let (full_sols, full) = search_with_storage(6, refiners(), coset, trace::TraceStorage::Full);
+        let (hashed_sols, hashed) = search_with_storage(6, refiners(), coset, trace::TraceStorage::Hashed);
+        assert_eq!(full_sols.get(), hashed_sols.get());
+        // Differences are found at the end of the refinement at the same node, but hashed traces
+        // only find them there, so the refiners may be called more often
+        let counts = |s: &Stats| (s.search_nodes, s.trace_fail_nodes, s.bad_iso, s.good_iso);
+        assert_eq!(counts(&full), counts(&hashed));
+        assert!(full.refiner_calls <= hashed.refiner_calls);
+    }
+
+    #[test]
+    fn hashed_trace_visits_same_nodes() {
+        assert_hashed_visits_same_nodes(set_refiners, false);
+        assert_hashed_visits_same_nodes(coset_refiners, true);
+
+        // The hexagon, and a relabelled hexagon with a chord
+        let hexagon: Vec<Vec<usize>> = (0..6).map(|i| vec![(i + 1) % 6, (i + 5) % 6]).collect();
+        let chorded = vec![
+            vec![3, 5, 1],
+            vec![4, 2, 0],
+            vec![1, 5],
+            vec![0, 4],
+            vec![3, 1],
+            vec![2, 0],
+        ];
+        let digraph_refiners = || -> Vec<Box<dyn Refiner>> {
+            vec![Box::new(DigraphTransporter::new_transporter(
+                Arc::new(Digraph::from_vec(hexagon.clone())),
+                Arc::new(Digraph::from_vec(chorded.clone())),
+            ))]
+        };
+        assert_hashed_visits_same_nodes(digraph_refiners, true);
+
+        // Searches which reject nodes below the root
+        let hexagon_refiners = || -> Vec<Box<dyn Refiner>> {
+            vec![
+                Box::new(DigraphTransporter::new_stabilizer(Arc::new(Digraph::from_vec(
+                    hexagon.clone(),
+                )))),
+                Box::new(SetTransporter::new_stabilizer(set(&[0, 2]))),
+            ]
+        };
+        assert_hashed_visits_same_nodes(hexagon_refiners, false);
+        let relabelled: Vec<Vec<usize>> = (0..6).map(|i| vec![(i + 2) % 6, (i + 4) % 6]).collect();
+        let hexagon_transporter = || -> Vec<Box<dyn Refiner>> {
+            vec![
+                Box::new(DigraphTransporter::new_transporter(
+                    Arc::new(Digraph::from_vec(hexagon.clone())),
+                    Arc::new(Digraph::from_vec(relabelled.clone())),
+                )),
+                Box::new(SetTransporter::new_transporter(set(&[0, 3]), set(&[0, 1]))),
+            ]
+        };
+        assert_hashed_visits_same_nodes(hexagon_transporter, true);
+    }
+
+    #[test]
+    fn brute_force_canonical() {
+        let refiners: Vec<Box<dyn Refiner>> = vec![
+            Box::new(InSymmetricGrp::new_symmetric_group(set(&[0, 1, 2, 3]))),
+            Box::new(SetTransporter::new_stabilizer(set(&[2, 3]))),
+        ];
+        let brute = brute_force_search(&RefinerStore::new_from_refiners(refiners).with_group(0), 4);
+        let canonical = brute.canonical.unwrap();
+        // {2,3} can be mapped to {0,1} in 4 ways
+        assert_eq!(canonical.perms.len(), 4);
+        assert!(canonical.perms.contains(&Permutation::from_vec(vec![2, 3, 0, 1])));
+
+        // The group refiner does not have to come first
+        let refiners: Vec<Box<dyn Refiner>> = vec![
+            Box::new(SetTransporter::new_stabilizer(set(&[2, 3]))),
+            Box::new(InSymmetricGrp::new_symmetric_group(set(&[0, 1, 2, 3]))),
+        ];
+        let brute = brute_force_search(&RefinerStore::new_from_refiners(refiners).with_group(1), 4);
+        assert_eq!(brute.canonical.unwrap().perms, canonical.perms);
+    }
+}
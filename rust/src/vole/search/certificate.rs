@@ -0,0 +1,791 @@
+//! Certificates which allow the result of a search to be checked without trusting the search.
+//!
+//! When search finds solutions, the certificate just lists them, and the checker checks each one
+//! is accepted by every refiner. When a coset search finds no solutions, the certificate records
+//! the rbase, and a complete search tree: at every node it records the values branched on, and
+//! for each branch the range (and hash) of the trace it produced, or the trace position where it
+//! failed. Leaves record the refiner which rejected them. The checker replays this tree, checking
+//! every branch of every cell was considered.
+//!
+//! Both building and checking a certificate use the minimal refinement loop in this module, rather
+//! than [RefinerStore::do_refine] and the search code, so the checker does not depend on the search,
+//! its cell selectors, orbit pruning, sub-searches or canonical images. The checker does trust:
+//! * each refiner's refinement functions and `check`,
+//! * [DomainState], including the partition stack and refining by graphs,
+//! * the tracer, and its hash of a range of the trace.
+//!
+//! A bug in the refiners or the partition code could still be hidden by a certificate, but every
+//! leaf is checked with the refiner's `check`, which does not use the partition.
+
+use serde::{Deserialize, Serialize};
+
+use crate::datastructures::hash::QHash;
+use crate::perm::Permutation;
+use crate::vole::backtracking::Backtrack;
+use crate::vole::{
+    domain_state::DomainState,
+    parse_input::{self, Problem},
+    partition_stack::{self, PartitionStack},
+    refiners::{refiner_store::RefinerStore, Side},
+    solutions::Solutions,
+    state::State,
+    trace::{self, TraceEvent},
+    trace_dump::TraceSource,
+};
+
+/// What happened after branching on a value (or, at the root, after the initial refinement)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BranchOutcome {
+    /// Refinement violated the trace of the rbase at trace position `position`
+    TraceFail { position: usize },
+    /// Refinement produced the trace events in positions `start..end`, which have hash `trace_hash`
+    Refined {
+        start: usize,
+        end: usize,
+        trace_hash: QHash,
+        node: Box<CertificateNode>,
+    },
+}
+
+/// A node of the search tree, reached after a successful refinement
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CertificateNode {
+    /// Every point is fixed, and `perm` (the permutation from the rbase to this leaf)
+    /// is rejected by refiner number `rejected_by`
+    Leaf { perm: Vec<usize>, rejected_by: usize },
+    /// Branch on every value of a cell of the partition
+    Branch { branches: Vec<(usize, BranchOutcome)> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Certificate {
+    /// Permutations which satisfy every constraint. For group searches, these generate the group.
+    Solutions { points: usize, perms: Vec<Vec<usize>> },
+    /// There is no permutation which satisfies every constraint
+    NoSolution {
+        points: usize,
+        /// The values branched on to build the rbase
+        rbase_branches: Vec<usize>,
+        /// The length and hash of the trace of the rbase
+        rbase_trace_len: usize,
+        rbase_trace_hash: QHash,
+        root: BranchOutcome,
+    },
+}
+
+/// A problem, together with a certificate for its result
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CertifiedProblem {
+    pub problem: Problem,
+    pub certificate: Certificate,
+}
+
+/// A reason a certificate is invalid (or could not be built). `path` is the list of values
+/// branched on to reach the problem.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CertificateError {
+    /// The problem itself is invalid
+    InvalidProblem(String),
+    /// The certificate is for a different number of points
+    WrongPoints { expected: usize, found: usize },
+    /// A solution is not a permutation of the points
+    InvalidPermutation { perm: Vec<usize> },
+    /// A solution is rejected by a refiner
+    NotSolution { perm: Vec<usize>, refiner: usize },
+    /// The rbase could not be rebuilt from its branches
+    InvalidRBase { depth: usize },
+    /// The rebuilt rbase has a different trace
+    RBaseTraceMismatch,
+    /// Refinement did not produce the recorded trace
+    TraceMismatch { path: Vec<usize> },
+    /// A branch node does not branch on exactly the values of one cell
+    IncompleteBranch { path: Vec<usize> },
+    /// A node is a leaf in the certificate, but not the search (or vice versa)
+    WrongNodeType { path: Vec<usize> },
+    /// A leaf is not rejected by the recorded refiner
+    LeafNotRejected { path: Vec<usize>, perm: Vec<usize> },
+    /// While building a certificate of no solutions, a leaf was found which is a solution
+    MissedSolution { path: Vec<usize>, perm: Vec<usize> },
+}
+
+/// For each refiner, the number of fixed values and cells of the partition when it was last called
+type Considered = Vec<(usize, usize)>;
+
+fn partition_size(part: &PartitionStack) -> (usize, usize) {
+    (part.base_fixed_values().len(), part.base_cells().len())
+}
+
+/// Start refinement, by calling `refine_begin` on every refiner, and then refine with [refine]
+fn refine_begin(state: &mut State, side: Side, considered: &mut Considered) -> trace::Result<()> {
+    let State { domain, refiners, .. } = state;
+    considered.clear();
+    for (i, refiner) in refiners.iter_mut().enumerate() {
+        considered.push(partition_size(domain.partition()));
+        domain.set_trace_source(TraceSource::Refiner(i));
+        refiner.refine_begin(domain, side)?;
+    }
+    refine(state, side, considered)
+}
+
+/// Refine until nothing changes, calling each refiner when the fixed values or cells of the
+/// partition have changed since it was last called, and then refining by the graphs
+fn refine(state: &mut State, side: Side, considered: &mut Considered) -> trace::Result<()> {
+    let State { domain, refiners, .. } = state;
+    loop {
+        let before = partition_size(domain.partition());
+        for (i, refiner) in refiners.iter_mut().enumerate() {
+            let (fixed, _) = partition_size(domain.partition());
+            if fixed > considered[i].0 {
+                considered[i].0 = fixed;
+                domain.set_trace_source(TraceSource::Refiner(i));
+                refiner.refine_fixed_points(domain, side)?;
+            }
+            let (_, cells) = partition_size(domain.partition());
+            if cells > considered[i].1 {
+                considered[i].1 = cells;
+                domain.set_trace_source(TraceSource::Refiner(i));
+                refiner.refine_changed_cells(domain, side)?;
+            }
+        }
+        domain.set_trace_source(TraceSource::Graph);
+        domain.refine_graphs()?;
+        domain.set_trace_source(TraceSource::Search);
+
+        if partition_size(domain.partition()) == before {
+            return domain.add_trace_event(TraceEvent::EndRefine());
+        }
+    }
+}
+
+/// Refine the cell `cell_num` by fixing `c`, and then refine with [refine]
+fn branch_on(c: usize, cell_num: usize, side: Side) -> impl FnOnce(&mut State, &mut Considered) -> trace::Result<()> {
+    move |state: &mut State, considered: &mut Considered| {
+        state.domain.refine_partition_cell_by(cell_num, |x| *x == c)?;
+        refine(state, side, considered)
+    }
+}
+
+/// The cell to branch on: the first cell of the partition with more than one value
+fn branch_cell(part: &PartitionStack) -> Option<usize> {
+    part.base_cells().iter().copied().find(|&i| part.cell(i).len() > 1)
+}
+
+/// Refine a node of search using `refine`, adding the end of the trace if this reaches a leaf.
+/// Returns the position in the trace where refinement failed, if it did.
+fn refine_node<F>(state: &mut State, considered: &mut Considered, refine: F) -> Result<(), usize>
+where
+    F: FnOnce(&mut State, &mut Considered) -> trace::Result<()>,
+{
+    let result = refine(state, considered).and_then(|_| {
+        if state.domain.partition().base_domain_fixed() {
+            state.domain.add_trace_event(TraceEvent::EndTrace())
+        } else {
+            Ok(())
+        }
+    });
+    result.map_err(|_| state.domain.tracer().position() - 1)
+}
+
+fn record_outcome<F>(
+    state: &mut State,
+    mut considered: Considered,
+    path: &mut Vec<usize>,
+    branch: F,
+) -> Result<BranchOutcome, CertificateError>
+where
+    F: FnOnce(&mut State, &mut Considered) -> trace::Result<()>,
+{
+    let start = state.domain.tracer().position();
+    Ok(match refine_node(state, &mut considered, branch) {
+        Err(position) => BranchOutcome::TraceFail { position },
+        Ok(()) => {
+            let end = state.domain.tracer().position();
+            let trace_hash = state.domain.tracer().symmetry_trace_hash(start, end);
+            let node = Box::new(record_node(state, &considered, path)?);
+            BranchOutcome::Refined {
+                start,
+                end,
+                trace_hash,
+                node,
+            }
+        }
+    })
+}
+
+fn record_node(
+    state: &mut State,
+    considered: &Considered,
+    path: &mut Vec<usize>,
+) -> Result<CertificateNode, CertificateError> {
+    let part = state.domain.partition();
+    let cell_num = match branch_cell(part) {
+        Some(cell_num) => cell_num,
+        None => {
+            let perm = partition_stack::perm_between(state.domain.rbase_partition().as_ref().unwrap(), part);
+            return match state.refiners.first_rejecting(&perm) {
+                Some(rejected_by) => Ok(CertificateNode::Leaf {
+                    perm: perm.as_vec().to_vec(),
+                    rejected_by,
+                }),
+                None => Err(CertificateError::MissedSolution {
+                    path: path.clone(),
+                    perm: perm.as_vec().to_vec(),
+                }),
+            };
+        }
+    };
+
+    let mut cell = part.cell(cell_num).to_vec();
+    cell.sort_unstable();
+    let mut branches = Vec::with_capacity(cell.len());
+    for c in cell {
+        path.push(c);
+        state.save_state();
+        let outcome = record_outcome(state, considered.clone(), path, branch_on(c, cell_num, Side::Right));
+        state.restore_state();
+        branches.push((c, outcome?));
+        path.pop();
+    }
+    Ok(CertificateNode::Branch { branches })
+}
+
+/// Build a certificate for the result of a search for the problem described by `refiners`, which
+/// found the solutions `sols`. If no solutions were found, the search tree is explored again (with
+/// the refinement in this module, and without pruning) to record it.
+pub fn build_certificate(
+    refiners: RefinerStore,
+    points: usize,
+    sols: &Solutions,
+) -> Result<Certificate, CertificateError> {
+    if !sols.get().is_empty() {
+        return Ok(Certificate::Solutions {
+            points,
+            perms: sols.get().iter().map(|p| p.as_vec().to_vec()).collect(),
+        });
+    }
+
+    let mut state = new_state(refiners, points);
+    let rbase_branches = build_rbase(&mut state, &[], true)?;
+    let rbase_trace_len = state.domain.rbase_trace_len();
+    let rbase_trace_hash = state.domain.tracer().symmetry_trace_hash(0, rbase_trace_len);
+
+    state.save_state();
+    let root = record_outcome(
+        &mut state,
+        vec![],
+        &mut vec![],
+        |state: &mut State, considered: &mut Considered| refine_begin(state, Side::Right, considered),
+    );
+    state.restore_state();
+
+    Ok(Certificate::NoSolution {
+        points,
+        rbase_branches,
+        rbase_trace_len,
+        rbase_trace_hash,
+        root: root?,
+    })
+}
+
+fn new_state(refiners: RefinerStore, points: usize) -> State {
+    State {
+        domain: DomainState::new(points, trace::Tracer::new_with_type(trace::TracingType::SYMMETRY)),
+        refiners,
+        stats: Default::default(),
+    }
+}
+
+/// Build the rbase by branching on `rbase_branches`. If `extend` is true, then while the
+/// partition is not discrete, continue branching on the first value of [branch_cell].
+/// Returns all the values branched on.
+fn build_rbase(state: &mut State, rbase_branches: &[usize], extend: bool) -> Result<Vec<usize>, CertificateError> {
+    let points = state.domain.partition().base_domain_size();
+    let mut considered = vec![];
+    state.save_state();
+    let mut saved = 1;
+    let mut branches = vec![];
+    let mut result =
+        refine_begin(state, Side::Left, &mut considered).map_err(|_| CertificateError::InvalidRBase { depth: 0 });
+
+    while result.is_ok() {
+        let depth = branches.len();
+        let part = state.domain.partition();
+        let c = match rbase_branches.get(depth) {
+            Some(&c) if c < points && part.cell(part.cell_of(c)).len() > 1 => c,
+            Some(_) => {
+                result = Err(CertificateError::InvalidRBase { depth });
+                break;
+            }
+            None if extend => match branch_cell(part) {
+                Some(cell_num) => *part.cell(cell_num).iter().min().unwrap(),
+                None => break,
+            },
+            None => break,
+        };
+        let cell_num = part.cell_of(c);
+        branches.push(c);
+        state.domain.push_rbase_branch_val(c);
+        state.save_state();
+        saved += 1;
+        if branch_on(c, cell_num, Side::Left)(state, &mut considered).is_err() {
+            result = Err(CertificateError::InvalidRBase { depth });
+        }
+    }
+
+    if result.is_ok() {
+        if state.domain.partition().base_domain_fixed() {
+            state.domain.snapshot_rbase(&mut state.refiners);
+        } else {
+            result = Err(CertificateError::InvalidRBase { depth: branches.len() });
+        }
+    }
+
+    for _ in 0..saved {
+        state.restore_state();
+    }
+    result.map(|_| branches)
+}
+
+fn check_outcome<F>(
+    state: &mut State,
+    mut considered: Considered,
+    outcome: &BranchOutcome,
+    path: &mut Vec<usize>,
+    branch: F,
+) -> Result<(), CertificateError>
+where
+    F: FnOnce(&mut State, &mut Considered) -> trace::Result<()>,
+{
+    let actual_start = state.domain.tracer().position();
+    let refined = refine_node(state, &mut considered, branch);
+    match (outcome, refined) {
+        (BranchOutcome::TraceFail { position }, Err(actual)) if *position == actual => Ok(()),
+        (
+            BranchOutcome::Refined {
+                start,
+                end,
+                trace_hash,
+                node,
+            },
+            Ok(()),
+        ) => {
+            let actual_end = state.domain.tracer().position();
+            if *start != actual_start
+                || *end != actual_end
+                || *trace_hash != state.domain.tracer().symmetry_trace_hash(actual_start, actual_end)
+            {
+                return Err(CertificateError::TraceMismatch { path: path.clone() });
+            }
+            check_node(state, &considered, node, path)
+        }
+        _ => Err(CertificateError::TraceMismatch { path: path.clone() }),
+    }
+}
+
+fn check_node(
+    state: &mut State,
+    considered: &Considered,
+    node: &CertificateNode,
+    path: &mut Vec<usize>,
+) -> Result<(), CertificateError> {
+    let part = state.domain.partition();
+    match node {
+        CertificateNode::Leaf { perm, rejected_by } => {
+            if !part.base_domain_fixed() {
+                return Err(CertificateError::WrongNodeType { path: path.clone() });
+            }
+            let actual = partition_stack::perm_between(state.domain.rbase_partition().as_ref().unwrap(), part);
+            if actual.as_vec() != perm.as_slice() || state.refiners.first_rejecting(&actual) != Some(*rejected_by) {
+                return Err(CertificateError::LeafNotRejected {
+                    path: path.clone(),
+                    perm: perm.clone(),
+                });
+            }
+            Ok(())
+        }
+        CertificateNode::Branch { branches } => {
+            if part.base_domain_fixed() {
+                return Err(CertificateError::WrongNodeType { path: path.clone() });
+            }
+
+            // The values branched on must be exactly one cell of the partition
+            let mut values: Vec<usize> = branches.iter().map(|(c, _)| *c).collect();
+            values.sort();
+            let cell_num = match values.first() {
+                Some(&c) if c < part.base_domain_size() => part.cell_of(c),
+                _ => return Err(CertificateError::IncompleteBranch { path: path.clone() }),
+            };
+            let mut cell = part.cell(cell_num).to_vec();
+            cell.sort();
+            if cell.len() < 2 || cell != values {
+                return Err(CertificateError::IncompleteBranch { path: path.clone() });
+            }
+
+            for (c, outcome) in branches {
+                path.push(*c);
+                state.save_state();
+                let result = check_outcome(
+                    state,
+                    considered.clone(),
+                    outcome,
+                    path,
+                    branch_on(*c, cell_num, Side::Right),
+                );
+                state.restore_state();
+                result?;
+                path.pop();
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Check `certificate` is valid for the problem described by `refiners`
+pub fn check_certificate(
+    refiners: RefinerStore,
+    points: usize,
+    certificate: &Certificate,
+) -> Result<(), CertificateError> {
+    let mut state = new_state(refiners, points);
+
+    match certificate {
+        Certificate::Solutions {
+            points: cert_points,
+            perms,
+        } => {
+            if *cert_points != points {
+                return Err(CertificateError::WrongPoints {
+                    expected: points,
+                    found: *cert_points,
+                });
+            }
+            for perm in perms {
+                let mut sorted = perm.clone();
+                sorted.sort();
+                if sorted.len() > points || sorted.iter().enumerate().any(|(i, &x)| i != x) {
+                    return Err(CertificateError::InvalidPermutation { perm: perm.clone() });
+                }
+                if let Some(refiner) = state.refiners.first_rejecting(&Permutation::from_vec(perm.clone())) {
+                    return Err(CertificateError::NotSolution {
+                        perm: perm.clone(),
+                        refiner,
+                    });
+                }
+            }
+            Ok(())
+        }
+        Certificate::NoSolution {
+            points: cert_points,
+            rbase_branches,
+            rbase_trace_len,
+            rbase_trace_hash,
+            root,
+        } => {
+            if *cert_points != points {
+                return Err(CertificateError::WrongPoints {
+                    expected: points,
+                    found: *cert_points,
+                });
+            }
+
+            build_rbase(&mut state, rbase_branches, false)?;
+            if state.domain.rbase_trace_len() != *rbase_trace_len
+                || state.domain.tracer().symmetry_trace_hash(0, *rbase_trace_len) != *rbase_trace_hash
+            {
+                return Err(CertificateError::RBaseTraceMismatch);
+            }
+
+            state.save_state();
+            let result = check_outcome(
+                &mut state,
+                vec![],
+                root,
+                &mut vec![],
+                |state: &mut State, considered: &mut Considered| refine_begin(state, Side::Right, considered),
+            );
+            state.restore_state();
+            result
+        }
+    }
+}
+
+impl CertifiedProblem {
+    /// Check the certificate is valid for the problem, including its group constraint
+    pub fn check(&self) -> Result<(), CertificateError> {
+        let refiners = parse_input::build_refiner_store(&self.problem)
+            .map_err(|e| CertificateError::InvalidProblem(e.to_string()))?;
+        check_certificate(refiners, self.problem.config.points, &self.certificate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::cmp::Ordering;
+    use std::sync::Arc;
+
+    use super::{
+        build_certificate, check_certificate, BranchOutcome, Certificate, CertificateError, CertificateNode,
+        CertifiedProblem,
+    };
+    use crate::datastructures::{digraph::Digraph, hash::QHash};
+    use crate::perm::Permutation;
+    use crate::vole::{
+        backtracking::Backtrack,
+        domain_state::DomainState,
+        parse_input::{Constraint, Problem, ProblemConfig},
+        refiners::{digraph::DigraphTransporter, refiner_store::RefinerStore, Refiner, Side},
+        search::{simple_coset_search, SearchConfig},
+        solutions::Solutions,
+        state::State,
+        trace,
+    };
+
+    fn cycle(n: usize, offset: usize) -> Vec<Vec<usize>> {
+        (0..n)
+            .map(|i| vec![offset + (i + 1) % n, offset + (i + n - 1) % n])
+            .collect()
+    }
+
+    fn refiners(left: Vec<Vec<usize>>, right: Vec<Vec<usize>>) -> RefinerStore {
+        let r: Box<dyn Refiner> = Box::new(DigraphTransporter::new_transporter(
+            Arc::new(Digraph::from_vec(left)),
+            Arc::new(Digraph::from_vec(right)),
+        ));
+        RefinerStore::new_from_refiners(vec![r])
+    }
+
+    /// A refiner which rejects every permutation, but does not refine
+    struct RejectAll;
+
+    impl Refiner for RejectAll {
+        fn name(&self) -> String {
+            "RejectAll".to_string()
+        }
+
+        fn is_group(&self) -> bool {
+            false
+        }
+
+        fn check(&self, _: &Permutation) -> bool {
+            false
+        }
+
+        fn any_image(&self, _: &Permutation, _: Side) -> Box<dyn Any> {
+            Box::new(())
+        }
+
+        fn any_compare(&self, _: &Box<dyn Any>, _: &Box<dyn Any>) -> Ordering {
+            Ordering::Equal
+        }
+
+        fn any_to_string(&self, _: &Box<dyn Any>) -> String {
+            String::new()
+        }
+
+        fn any_hash(&self, _: &Box<dyn Any>) -> QHash {
+            0
+        }
+    }
+
+    impl Backtrack for RejectAll {
+        fn save_state(&mut self) {}
+        fn restore_state(&mut self) {}
+        fn state_depth(&self) -> usize {
+            0
+        }
+    }
+
+    fn hexagon() -> Vec<Vec<usize>> {
+        cycle(6, 0)
+    }
+
+    fn two_triangles() -> Vec<Vec<usize>> {
+        let mut g = cycle(3, 0);
+        g.extend(cycle(3, 3));
+        g
+    }
+
+    fn certify(left: Vec<Vec<usize>>, right: Vec<Vec<usize>>) -> Certificate {
+        let config = SearchConfig {
+            full_graph_refine: false,
+            ..Default::default()
+        };
+        let mut state = State {
+            domain: DomainState::new(6, trace::Tracer::new_with_type(trace::TracingType::SYMMETRY)),
+            refiners: refiners(left.clone(), right.clone()),
+            stats: Default::default(),
+        };
+        let mut sols = Solutions::new(6);
+        simple_coset_search(&mut state, &mut sols, &config);
+        build_certificate(refiners(left, right), 6, &sols).unwrap()
+    }
+
+    fn root(cert: &mut Certificate) -> &mut BranchOutcome {
+        match cert {
+            Certificate::NoSolution { root, .. } => root,
+            Certificate::Solutions { .. } => panic!("Expected no solutions"),
+        }
+    }
+
+    /// The first leaf of the certificate, and the path to it
+    fn first_leaf<'a>(
+        outcome: &'a mut BranchOutcome,
+        path: &mut Vec<usize>,
+    ) -> Option<(&'a mut Vec<usize>, &'a mut usize)> {
+        match outcome {
+            BranchOutcome::TraceFail { .. } => None,
+            BranchOutcome::Refined { node, .. } => match &mut **node {
+                CertificateNode::Leaf { perm, rejected_by } => Some((perm, rejected_by)),
+                CertificateNode::Branch { branches } => branches.iter_mut().find_map(|(c, outcome)| {
+                    path.push(*c);
+                    let leaf = first_leaf(outcome, path);
+                    if leaf.is_none() {
+                        path.pop();
+                    }
+                    leaf
+                }),
+            },
+        }
+    }
+
+    #[test]
+    fn no_solution_certificate() {
+        let cert = certify(hexagon(), two_triangles());
+        let root = match &cert {
+            Certificate::NoSolution { root, .. } => root,
+            Certificate::Solutions { .. } => panic!("Expected no solutions"),
+        };
+        // Both graphs are 2-regular, so search must branch
+        assert!(matches!(
+            root,
+            BranchOutcome::Refined { node, .. } if matches!(**node, CertificateNode::Branch { .. })
+        ));
+        assert_eq!(
+            check_certificate(refiners(hexagon(), two_triangles()), 6, &cert),
+            Ok(())
+        );
+
+        // A certificate must not be accepted for a different problem
+        assert!(check_certificate(refiners(hexagon(), hexagon()), 6, &cert).is_err());
+    }
+
+    #[test]
+    fn incomplete_certificate() {
+        let mut cert = certify(hexagon(), two_triangles());
+        if let Certificate::NoSolution {
+            root: BranchOutcome::Refined { node, .. },
+            ..
+        } = &mut cert
+        {
+            if let CertificateNode::Branch { branches } = &mut **node {
+                branches.pop();
+            }
+        }
+        assert_eq!(
+            check_certificate(refiners(hexagon(), two_triangles()), 6, &cert),
+            Err(CertificateError::IncompleteBranch { path: vec![] })
+        );
+    }
+
+    #[test]
+    fn solution_certificate() {
+        let cert = certify(hexagon(), hexagon());
+        assert!(matches!(cert, Certificate::Solutions { .. }));
+        assert_eq!(check_certificate(refiners(hexagon(), hexagon()), 6, &cert), Ok(()));
+        assert_eq!(
+            check_certificate(refiners(hexagon(), two_triangles()), 6, &cert),
+            Err(CertificateError::NotSolution {
+                perm: match &cert {
+                    Certificate::Solutions { perms, .. } => perms[0].clone(),
+                    _ => unreachable!(),
+                },
+                refiner: 0
+            })
+        );
+    }
+
+    #[test]
+    fn tampered_certificate() {
+        let check = |cert: &Certificate| check_certificate(refiners(hexagon(), two_triangles()), 6, cert);
+        let cert = certify(hexagon(), two_triangles());
+
+        let mut tampered = cert.clone();
+        if let BranchOutcome::Refined { trace_hash, .. } = root(&mut tampered) {
+            *trace_hash = trace_hash.wrapping_add(1);
+        }
+        assert_eq!(check(&tampered), Err(CertificateError::TraceMismatch { path: vec![] }));
+
+        let mut tampered = cert;
+        if let Certificate::NoSolution { rbase_branches, .. } = &mut tampered {
+            rbase_branches.clear();
+        }
+        assert_eq!(check(&tampered), Err(CertificateError::InvalidRBase { depth: 0 }));
+
+        let mut tampered = certify(hexagon(), hexagon());
+        if let Certificate::Solutions { perms, .. } = &mut tampered {
+            perms[0][0] = perms[0][1];
+        }
+        assert!(matches!(
+            check_certificate(refiners(hexagon(), hexagon()), 6, &tampered),
+            Err(CertificateError::InvalidPermutation { .. })
+        ));
+    }
+
+    #[test]
+    fn tampered_leaf() {
+        let reject_all = || RefinerStore::new_from_refiners(vec![Box::new(RejectAll)]);
+        let cert = build_certificate(reject_all(), 3, &Solutions::new(3)).unwrap();
+        assert_eq!(check_certificate(reject_all(), 3, &cert), Ok(()));
+
+        let mut tampered = cert.clone();
+        let mut path = vec![];
+        let (perm, _) = first_leaf(root(&mut tampered), &mut path).unwrap();
+        *perm = vec![1, 0];
+        let perm = perm.clone();
+        assert_eq!(
+            check_certificate(reject_all(), 3, &tampered),
+            Err(CertificateError::LeafNotRejected { path, perm })
+        );
+
+        let mut tampered = cert;
+        let mut path = vec![];
+        let (perm, rejected_by) = first_leaf(root(&mut tampered), &mut path).unwrap();
+        *rejected_by += 1;
+        let perm = perm.clone();
+        assert_eq!(
+            check_certificate(reject_all(), 3, &tampered),
+            Err(CertificateError::LeafNotRejected { path, perm })
+        );
+    }
+
+    #[test]
+    fn certified_problem_group() {
+        let constraint = |json: &str| -> Constraint { serde_json::from_str(json).unwrap() };
+        let certified = |group| CertifiedProblem {
+            problem: Problem {
+                config: ProblemConfig {
+                    points: 3,
+                    find_coset: true,
+                    find_canonical: false,
+                    group,
+                    root_search: false,
+                    search_config: SearchConfig::default(),
+                },
+                constraints: vec![
+                    constraint(r#"{"SetTransport": {"left_points": [1], "right_points": [2]}}"#),
+                    constraint(r#"{"SetStab": {"points": [1, 2]}}"#),
+                ],
+            },
+            certificate: Certificate::Solutions {
+                points: 3,
+                perms: vec![vec![1, 0, 2]],
+            },
+        };
+        assert_eq!(certified(Some(1)).check(), Ok(()));
+        assert!(matches!(
+            certified(Some(0)).check(),
+            Err(CertificateError::InvalidProblem(_))
+        ));
+    }
+}
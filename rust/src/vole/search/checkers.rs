@@ -1,15 +1,40 @@
 use tracing::info;
 
 use crate::gap_chat::GapChatType;
-use crate::perm::Permutation;
+use crate::perm::{random::RandomPerm, Permutation};
+use crate::vole::refiners::refiner_store::CanonicalComparison;
 use crate::vole::solutions::{Canonical, SolutionFound, Solutions};
 use crate::vole::state::State;
+use crate::vole::stats::ConsistencyCheckSource;
 use crate::vole::{partition_stack, trace};
 
-/// Check when we reach a candidate solution
+use super::{CanonicalMinimiser, SearchConfig};
+
+/// Number of random permutations each refiner is checked on, when `check_refiners` is enabled
+const RANDOM_CONSISTENCY_CHECKS: usize = 100;
+
+/// Check the refiners' `check` functions agree with their images, on the identity and
+/// random permutations of the domain. Any disagreements are recorded in the stats.
+pub fn check_refiners_random(in_state: &mut State) {
+    let pnts = in_state.domain.partition().base_domain_size();
+    let mut perms = vec![Permutation::id()];
+    if pnts > 1 {
+        let cycle = Permutation::from_vec((1..pnts).chain(std::iter::once(0)).collect());
+        let swap = Permutation::from_vec(vec![1, 0]);
+        let mut random = RandomPerm::new(vec![cycle, swap]);
+        perms.extend((0..RANDOM_CONSISTENCY_CHECKS).map(|_| random.randperm()));
+    }
+
+    for p in perms {
+        let found = in_state
+            .refiners
+            .find_inconsistencies(&p, ConsistencyCheckSource::Random);
+        in_state.stats.refiner_inconsistencies.extend(found);
+    }
+}
 
 /// Check if current DomainState produces a smaller canonical image
-fn check_canonical(in_state: &mut State, sols: &mut Solutions) {
+fn check_canonical(in_state: &mut State, sols: &mut Solutions, search_config: &SearchConfig) {
     let refiners = &mut in_state.refiners;
     let state = &mut in_state.domain;
     let stats = &mut in_state.stats;
@@ -19,10 +44,15 @@ fn check_canonical(in_state: &mut State, sols: &mut Solutions) {
 
     // Get canonical permutation
     let preimage: Vec<usize> = part.base_cells().iter().map(|&x| part.cell(x)[0]).collect();
-    // GAP needs 1 indexed
-    let preimagegap: Vec<usize> = preimage.iter().map(|&x| x + 1).collect();
-    let postimagegap: Vec<usize> = GapChatType::send_request(&("canonicalmin", &preimagegap)).unwrap();
-    let postimage: Vec<usize> = postimagegap.into_iter().map(|x| x - 1).collect();
+    let postimage: Vec<usize> = match search_config.canonical_minimiser {
+        CanonicalMinimiser::Gap => {
+            // GAP needs 1 indexed
+            let preimagegap: Vec<usize> = preimage.iter().map(|&x| x + 1).collect();
+            let postimagegap: Vec<usize> = GapChatType::send_request(&("canonicalmin", &preimagegap)).unwrap();
+            postimagegap.into_iter().map(|x| x - 1).collect()
+        }
+        CanonicalMinimiser::Symmetric => (0..pnts).collect(),
+    };
     let mut image: Vec<usize> = vec![0; pnts];
     for i in 0..pnts {
         image[preimage[i]] = postimage[i];
@@ -49,9 +79,8 @@ fn check_canonical(in_state: &mut State, sols: &mut Solutions) {
                 trace_version: state.tracer().canonical_trace_version(),
             }))
         }
-        Some(canonical) => {
-            let o = refiners.get_smaller_canonical_image(&perm, &canonical.images, stats);
-            if let Some(images) = o {
+        Some(canonical) => match refiners.compare_with_canonical_image(&perm, &canonical.images, stats) {
+            CanonicalComparison::Smaller(images) => {
                 info!("Found new canonical image: {:?}", perm);
                 sols.set_canonical(Some(Canonical {
                     perm,
@@ -59,11 +88,52 @@ fn check_canonical(in_state: &mut State, sols: &mut Solutions) {
                     trace_version: state.tracer().canonical_trace_version(),
                 }));
             }
-        }
+            CanonicalComparison::Equal => {
+                // Both permutations map the input to the same image, and `multiply` composes
+                // right to left, so this maps the input to itself
+                let auto = perm.inv().multiply(&canonical.perm);
+                add_canonical_automorphism(in_state, sols, auto);
+            }
+            CanonicalComparison::Larger => {}
+        },
     }
 }
 
-pub fn check_solution(in_state: &mut State, sols: &mut Solutions) -> SolutionFound {
+/// Add an automorphism found by two leaves with equal canonical images to `sols`, so it
+/// can be used to prune search. Orbits are only used on the first branch of search, and
+/// we are still searching below the point where the current branch leaves the first branch,
+/// so `auto` must fix the values branched on up to that point.
+///
+/// Orbits in `sols` are stored relative to its first solution, which must be the leaf
+/// at the end of the first branch. When tracing for symmetry that leaf is always a
+/// solution, and it is found before any canonical image is compared. So `sols` is only
+/// empty when tracing for canonical images alone, and then `auto` is dropped, as there
+/// is no first solution to store its orbits relative to.
+fn add_canonical_automorphism(in_state: &mut State, sols: &mut Solutions, auto: Permutation) {
+    let state = &in_state.domain;
+    let first_branch = state.rbase_branch_vals();
+    let shared = first_branch
+        .iter()
+        .zip(state.branch_path())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    if auto.is_id()
+        || sols.get().is_empty()
+        || first_branch[..shared].iter().any(|&x| auto.apply(x) != x)
+        || !in_state.refiners.check_all(&auto)
+    {
+        return;
+    }
+
+    info!("Found automorphism from canonical image: {:?}", auto);
+    in_state.stats.canonical_automorphisms += 1;
+    in_state.refiners.iter_mut().for_each(|r| r.solution_found(&auto));
+    // This is not a leaf of the first branch, so this can not be the first solution
+    let _ = sols.add_solution(&auto);
+}
+
+pub fn check_solution(in_state: &mut State, sols: &mut Solutions, search_config: &SearchConfig) -> SolutionFound {
     let refiners = &mut in_state.refiners;
     let state = &mut in_state.domain;
     let stats = &mut in_state.stats;
@@ -87,32 +157,10 @@ pub fn check_solution(in_state: &mut State, sols: &mut Solutions) -> SolutionFou
     let mut sol_found = SolutionFound::None;
     if tracing_type.contains(trace::TracingType::SYMMETRY) {
         let sol = partition_stack::perm_between(state.rbase_partition().as_ref().unwrap(), part);
-        /*
-        for r in self.refiners.iter() {
-            let x = r.check(&sol);
-            let y = r.any_image(&sol, Side::Left);
-            let z = r.any_image(&Permutation::id(), Side::Right);
-            if x != (r.any_compare(&y, &z) == Ordering::Equal) {
-                eprintln!(
-                    "\n\n!!!! {:?} {:?} {:?} {:?} {:?} {:?} !!!!\n\n",
-                    x,
-                    &sol,
-                    r.name(),
-                    r.any_to_string(&y),
-                    r.any_to_string(&z),
-                    r.any_compare(&y, &z)
-                );
-            }
-
-            assert!(
-                r.check(&sol)
-                    == (r.any_compare(
-                        &r.any_image(&sol, Side::Left),
-                        &r.any_image(&Permutation::id(), Side::Right)
-                    ) == Ordering::Equal)
-            );
+        if search_config.check_refiners {
+            let found = refiners.find_inconsistencies(&sol, ConsistencyCheckSource::Leaf);
+            stats.refiner_inconsistencies.extend(found);
         }
-        */
 
         let is_sol = refiners.check_all(&sol);
         if is_sol {
@@ -127,8 +175,114 @@ pub fn check_solution(in_state: &mut State, sols: &mut Solutions) -> SolutionFou
     }
 
     if tracing_type.contains(trace::TracingType::CANONICAL) {
-        check_canonical(in_state, sols);
+        check_canonical(in_state, sols, search_config);
     }
 
     sol_found
 }
+
+#[cfg(test)]
+mod tests {
+    use super::add_canonical_automorphism;
+    use std::sync::Arc;
+
+    use crate::{
+        datastructures::{digraph::Digraph, sortedvec::SortedVec},
+        perm::Permutation,
+        vole::{
+            canonical::digraph_refiners,
+            domain_state::DomainState,
+            refiners::{refiner_store::RefinerStore, simple::SetTransporter, symmetricgrp::InSymmetricGrp, Refiner},
+            search::{simple_group_search, CanonicalMinimiser, SearchConfig},
+            solutions::Solutions,
+            state::State,
+            trace,
+        },
+    };
+
+    #[test]
+    fn canonical_automorphism() {
+        let set: SortedVec<usize> = [0, 1, 2].iter().copied().collect();
+        let refiners: Vec<Box<dyn Refiner>> = vec![Box::new(SetTransporter::new_stabilizer(set))];
+        let mut state = State {
+            domain: DomainState::new(5, trace::Tracer::new_with_type(trace::TracingType::SYMMETRY)),
+            refiners: RefinerStore::new_from_refiners(refiners),
+            stats: Default::default(),
+        };
+        let mut sols = Solutions::new(5);
+        let config = SearchConfig {
+            full_graph_refine: false,
+            ..Default::default()
+        };
+        simple_group_search(&mut state, &mut sols, &config);
+        let found = sols.get().len();
+
+        // Not an automorphism
+        add_canonical_automorphism(&mut state, &mut sols, Permutation::from_vec(vec![3, 1, 2, 0]));
+        assert_eq!(sols.get().len(), found);
+
+        // Search has finished, so we are at the root and any automorphism can be used
+        let auto = Permutation::from_vec(vec![1, 2, 0]);
+        add_canonical_automorphism(&mut state, &mut sols, auto.clone());
+        assert_eq!(sols.get().len(), found + 1);
+        assert_eq!(sols.get().last(), Some(&auto));
+        assert_eq!(state.stats.canonical_automorphisms, 1);
+
+        // Pretend we are below the first branch, so automorphisms must fix its first value
+        let first = state.domain.rbase_branch_vals()[0];
+        state.domain.push_branch_val(first);
+        // Swap `first` with another point in the same orbit
+        let other = match first {
+            3 => 4,
+            4 => 3,
+            x => (x + 1) % 3,
+        };
+        let moves_first = Permutation::from_vec(
+            (0..5)
+                .map(|x| {
+                    if x == first {
+                        other
+                    } else if x == other {
+                        first
+                    } else {
+                        x
+                    }
+                })
+                .collect(),
+        );
+        assert!(state.refiners.check_all(&moves_first));
+        add_canonical_automorphism(&mut state, &mut sols, moves_first);
+        assert_eq!(sols.get().len(), found + 1);
+    }
+
+    #[test]
+    fn canonical_automorphisms_found() {
+        // The Petersen graph, which has 120 automorphisms
+        let mut edges = vec![vec![]; 10];
+        for i in 0..5 {
+            for (a, b) in [(i, (i + 1) % 5), (i, i + 5), (i + 5, (i + 2) % 5 + 5)] {
+                edges[a].push(b);
+                edges[b].push(a);
+            }
+        }
+        let mut refiners: Vec<Box<dyn Refiner>> =
+            vec![Box::new(InSymmetricGrp::new_symmetric_group((0..10).collect()))];
+        refiners.extend(digraph_refiners(Arc::new(Digraph::from_vec(edges)), &[]));
+        let mut state = State {
+            domain: DomainState::new(10, trace::Tracer::new()),
+            refiners: RefinerStore::new_from_refiners(refiners).with_group(0),
+            stats: Default::default(),
+        };
+        let mut sols = Solutions::new(10);
+        let config = SearchConfig {
+            canonical_minimiser: CanonicalMinimiser::Symmetric,
+            ..Default::default()
+        };
+        simple_group_search(&mut state, &mut sols, &config);
+
+        assert!(state.stats.canonical_automorphisms > 0);
+        for p in sols.get() {
+            assert!(state.refiners.check_all(p));
+        }
+    }
+}
@@ -1,3 +1,5 @@
+pub mod brute_force;
+pub mod certificate;
 mod checkers;
 
 use serde::{Deserialize, Serialize};
@@ -5,6 +7,7 @@ use serde::{Deserialize, Serialize};
 use crate::vole::subsearch::sub_full_refine;
 
 use super::solutions::SolutionFound;
+use super::trace::TraceStorage;
 use super::{backtracking::Backtrack, state::State};
 use super::{refiners::Side, selector::select_branching_cell};
 use super::{solutions::Solutions, subsearch::sub_simple_search};
@@ -16,6 +19,27 @@ use tracing::{info, trace, trace_span};
 pub struct SearchConfig {
     pub full_graph_refine: bool,
     find_single: bool,
+    /// Check every refiner's `check` agrees with its images, on random permutations and every leaf
+    #[serde(default)]
+    pub check_refiners: bool,
+    /// How to store the symmetry trace
+    #[serde(default)]
+    pub trace_storage: TraceStorage,
+    /// How to find the smallest image of each leaf, when finding canonical images
+    #[serde(default)]
+    pub canonical_minimiser: CanonicalMinimiser,
+}
+
+/// How the smallest image of a leaf under the group we are searching in is found,
+/// when finding canonical images
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CanonicalMinimiser {
+    /// Ask GAP, which knows the group
+    #[default]
+    Gap,
+    /// The group is the symmetric group on the whole domain, so the smallest image
+    /// maps the cells of the leaf, in order, to `0..n`. This does not need GAP.
+    Symmetric,
 }
 
 impl Default for SearchConfig {
@@ -23,6 +47,9 @@ impl Default for SearchConfig {
         Self {
             full_graph_refine: true,
             find_single: false,
+            check_refiners: false,
+            trace_storage: TraceStorage::Full,
+            canonical_minimiser: CanonicalMinimiser::Gap,
         }
     }
 }
@@ -49,6 +76,7 @@ fn build_rbase(state: &mut State, search_config: &SearchConfig) {
     let _span = trace_span!("C", value = c).entered();
 
     state.save_state();
+    state.domain.push_branch_val(c);
 
     let cell_count = state.domain.partition().base_cells().len();
 
@@ -99,7 +127,7 @@ fn simple_search_recurse(
     let part = state.domain.partition();
 
     if part.base_domain_fixed() {
-        return checkers::check_solution(state, sols);
+        return checkers::check_solution(state, sols, search_config);
     }
 
     let _span = trace_span!("B").entered();
@@ -120,12 +148,13 @@ fn simple_search_recurse(
             Side::Right
         };
 
-        assert!(!(doing_first_branch && !sols.orbit_needs_searching(c, depth)));
+        assert!(!doing_first_branch || sols.orbit_needs_searching(c, depth));
 
         // Skip search if we are in the first branch, not checked anything in this orbit yet, and not on the first thing.
         let skip = first_branch_in && !sols.orbit_needs_searching(c, depth);
         if !skip {
             state.save_state();
+            state.domain.push_branch_val(c);
             let cell_count = state.domain.partition().base_cells().len();
             info!("Try branching on {:?} in cell {:?}", c, cell_num);
             if state.domain.refine_partition_cell_by(cell_num, |x| *x == c).is_ok() {
@@ -171,7 +200,7 @@ fn simple_coset_search_recurse(
     let part = state.domain.partition();
 
     if part.base_domain_fixed() {
-        return checkers::check_solution(state, sols);
+        return checkers::check_solution(state, sols, search_config);
     }
 
     let _span = trace_span!("B").entered();
@@ -187,6 +216,7 @@ fn simple_coset_search_recurse(
         let skip = special_node && !sols.orbit_needs_searching(c, depth);
         if !skip {
             state.save_state();
+            state.domain.push_branch_val(c);
             let cell_count = state.domain.partition().base_cells().len();
             info!("Try branching on {:?} in cell {:?}", c, cell_num);
             if state.domain.refine_partition_cell_by(cell_num, |x| *x == c).is_ok() {
@@ -246,6 +276,10 @@ fn simple_coset_search_recurse(
 pub fn simple_coset_search(state: &mut State, sols: &mut Solutions, search_config: &SearchConfig) {
     trace!("Starting Coset Search");
 
+    if search_config.check_refiners {
+        checkers::check_refiners_random(state);
+    }
+
     // First build RBase
 
     state.save_state();
@@ -269,28 +303,36 @@ pub fn simple_coset_search(state: &mut State, sols: &mut Solutions, search_confi
     let ret = state
         .refiners
         .init_refine(&mut state.domain, Side::Right, &mut state.stats);
-    if ret.is_err() {
-        return;
+    if ret.is_ok() {
+        let _ = simple_coset_search_recurse(state, sols, 0, search_config);
     }
-    let _ = simple_coset_search_recurse(state, sols, 0, search_config);
     state.restore_state();
+    state.record_split_stats();
     trace!("Finishing Single Permutation Search");
 }
 
 /// Standard complete search, for stabilizer + canonical image
 pub fn simple_group_search(state: &mut State, sols: &mut Solutions, search_config: &SearchConfig) {
     trace!("Starting Search");
+
+    if search_config.check_refiners {
+        checkers::check_refiners_random(state);
+    }
     let ret = state
         .refiners
         .init_refine(&mut state.domain, Side::Left, &mut state.stats);
-    if ret.is_err() {
-        return;
+    if ret.is_ok() {
+        let _ = simple_search_recurse(state, sols, true, 0, search_config);
     }
-    let _ = simple_search_recurse(state, sols, true, 0, search_config);
+    state.record_split_stats();
 }
 
 /// Search only the digraph stack created during initalisation
 pub fn root_search(state: &mut State, sols: &mut Solutions, search_config: &SearchConfig) {
+    if search_config.check_refiners {
+        checkers::check_refiners_random(state);
+    }
+
     if state
         .refiners
         .init_refine(&mut state.domain, Side::Left, &mut state.stats)
@@ -301,4 +343,5 @@ pub fn root_search(state: &mut State, sols: &mut Solutions, search_config: &Sear
 
     let (ret_sols, _) = sub_simple_search(state, search_config);
     *sols = ret_sols;
+    state.record_split_stats();
 }
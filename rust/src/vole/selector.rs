@@ -21,9 +21,9 @@ enum Selector {
     SmallestMostConnected,
 }
 
-fn find_best_cell<F: Copy, T: Ord + Debug + QuickHashable>(state: &State, func: F) -> usize
+fn find_best_cell<F, T: Ord + Debug + QuickHashable>(state: &State, func: F) -> usize
 where
-    F: Fn(&State, usize) -> T,
+    F: Copy + Fn(&State, usize) -> T,
 {
     *state
         .domain
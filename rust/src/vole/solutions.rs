@@ -41,6 +41,18 @@ impl Solutions {
         }
     }
 
+    /// Reset to the state [Solutions::new] makes for `max` points, reusing the memory
+    /// already allocated
+    pub fn reset(&mut self, max: usize) {
+        self.first_sol_inv = None;
+        self.sols.clear();
+        self.orbits.reset(max);
+        self.canonical = None;
+        self.nodes = 0;
+        self.tracefails = 0;
+        self.solsfails = 0;
+    }
+
     pub fn add_solution(&mut self, p: &Permutation) -> SolutionFound {
         if self.first_sol_inv.is_none() {
             self.first_sol_inv = Some(p.inv());
@@ -88,4 +100,8 @@ impl Solutions {
     pub fn set_canonical(&mut self, c: Option<Canonical>) {
         self.canonical = c
     }
+
+    pub fn take_canonical(&mut self) -> Option<Canonical> {
+        self.canonical.take()
+    }
 }
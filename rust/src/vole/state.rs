@@ -6,6 +6,14 @@ pub struct State {
     pub stats: Stats,
 }
 
+impl State {
+    /// Record the number of cells split by each refiner, and by the graph and search, in the stats
+    pub fn record_split_stats(&mut self) {
+        self.stats
+            .record_splits(self.domain.tracer().split_counts(), self.refiners.refiner_count());
+    }
+}
+
 impl Backtrack for State {
     fn save_state(&mut self) {
         self.domain.save_state();
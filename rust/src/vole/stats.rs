@@ -1,4 +1,34 @@
 use serde::{Deserialize, Serialize};
+
+use super::{trace::SplitCounts, trace_dump::TraceSource};
+
+/// Where a permutation used to check the consistency of a refiner came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ConsistencyCheckSource {
+    /// A random permutation, generated before search
+    Random,
+    /// A leaf of the search
+    Leaf,
+}
+
+/// A permutation where a refiner's `check` does not agree with comparing
+/// the image of the left side under the permutation, with the right side
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RefinerInconsistency {
+    /// The name of the refiner
+    pub refiner: String,
+    /// The permutation which caused the disagreement
+    pub perm: Vec<usize>,
+    /// Where `perm` came from
+    pub source: ConsistencyCheckSource,
+    /// The result of `check(perm)`
+    pub check: bool,
+    /// The image of the left side under `perm`
+    pub left_image: String,
+    /// The right side
+    pub right_image: String,
+}
+
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct Stats {
     /// Total number of nodes in the search tree
@@ -15,8 +45,32 @@ pub struct Stats {
     pub improve_canonical: usize,
     /// Number of leaves where the canonical trace has not been violated, which were 'equal'
     pub equal_canonical: usize,
+    /// Number of automorphisms found from leaves with equal canonical images, and used to prune search
+    #[serde(default)]
+    pub canonical_automorphisms: usize,
     /// Total number of times refiners have been called
     pub refiner_calls: usize,
     /// Total time taken by vole (in ms)
     pub vole_time: u128,
+    /// Refiners which disagreed with themselves (only filled in when `check_refiners` is enabled)
+    #[serde(default)]
+    pub refiner_inconsistencies: Vec<RefinerInconsistency>,
+    /// Number of cells split by each refiner (in the order the refiners were given)
+    #[serde(default)]
+    pub refiner_splits: Vec<usize>,
+    /// Number of cells split by refining with the digraphs created by refiners
+    #[serde(default)]
+    pub graph_splits: usize,
+    /// Number of cells split by search, when branching
+    #[serde(default)]
+    pub branch_splits: usize,
+}
+
+impl Stats {
+    /// Record the number of cells split by each of `refiners` refiners, and by graphs and branching
+    pub fn record_splits(&mut self, counts: &SplitCounts, refiners: usize) {
+        self.refiner_splits = (0..refiners).map(|i| counts.get(TraceSource::Refiner(i))).collect();
+        self.graph_splits = counts.get(TraceSource::Graph);
+        self.branch_splits = counts.get(TraceSource::Search);
+    }
 }
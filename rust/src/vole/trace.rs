@@ -1,11 +1,13 @@
 use std::hash::Hash;
 use std::{cmp::Ordering, num::Wrapping};
 
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use crate::datastructures::hash::QHash;
+use crate::datastructures::hash::{do_hash, QHash};
 
 use super::backtracking::{Backtrack, Backtracking};
+use super::trace_dump::{EventInfo, RecordedEvent, RecordedTrace, TraceDump, TraceSource};
 
 bitflags! {
     pub struct TracingType : u8 {
@@ -16,7 +18,7 @@ bitflags! {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Hash)]
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Hash, Deserialize, Serialize)]
 pub enum TraceEvent {
     Split { cell: usize, size: usize, reason: QHash },
     FullGraph { hash: Wrapping<QHash> },
@@ -31,15 +33,115 @@ pub enum TraceEvent {
 #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Hash)]
 pub struct TraceFailure {}
 
+/// How the symmetry trace is stored
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub enum TraceStorage {
+    /// Store every event, so differences are found as soon as they happen
+    #[default]
+    Full,
+    /// Store only a rolling hash of the events at each checkpoint: the end of the refinement at
+    /// each search node, and each leaf. This needs memory proportional to the search depth, rather
+    /// than the length of the trace. A difference is not found at the event where it happens,
+    /// but at the next checkpoint, so refiners keep running until the end of the refinement
+    /// (and traces whose rolling hashes collide are treated as equal).
+    /// This does not support canonical traces.
+    Hashed,
+}
+
+/// A symmetry trace stored as a rolling hash, see [TraceStorage::Hashed]
+#[derive(Debug, Default)]
+struct HashedTrace {
+    /// The number of events in the trace
+    len: usize,
+    /// The position and rolling hash of each checkpoint event, in order of position
+    checkpoints: Vec<(usize, QHash)>,
+    /// The rolling hash of the whole trace
+    end_hash: QHash,
+}
+
+impl HashedTrace {
+    /// Events which end the refinement at a search node, or a branch of search
+    fn is_checkpoint(t: &TraceEvent) -> bool {
+        matches!(t, TraceEvent::EndRefine() | TraceEvent::EndTrace())
+    }
+
+    /// Add `t` to the rolling hash `hash`
+    fn roll(hash: QHash, t: &TraceEvent) -> QHash {
+        do_hash((hash, t)).0
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.checkpoints.clear();
+        self.end_hash = 0;
+    }
+}
+
 pub type Result<T> = std::result::Result<T, TraceFailure>;
 
+/// The number of `Split` events added to the trace by each [TraceSource]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SplitCounts {
+    refiners: Vec<usize>,
+    graph: usize,
+    search: usize,
+}
+
+impl SplitCounts {
+    fn add(&mut self, source: TraceSource) {
+        match source {
+            TraceSource::Search => self.search += 1,
+            TraceSource::Graph => self.graph += 1,
+            TraceSource::Refiner(i) => {
+                if self.refiners.len() <= i {
+                    self.refiners.resize(i + 1, 0);
+                }
+                self.refiners[i] += 1;
+            }
+        }
+    }
+
+    /// The number of splits made by `source`
+    pub fn get(&self, source: TraceSource) -> usize {
+        match source {
+            TraceSource::Search => self.search,
+            TraceSource::Graph => self.graph,
+            TraceSource::Refiner(i) => self.refiners.get(i).copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Information stored about each event, when recording a trace for debugging
+#[derive(Debug, Default)]
+struct TraceRecorder {
+    symmetry_info: Vec<EventInfo>,
+    symmetry_path: Vec<usize>,
+    canonical_info: Vec<EventInfo>,
+    canonical_path: Vec<usize>,
+}
+
 #[derive(Debug)]
 pub struct Tracer {
     pos: Backtracking<usize>,
     tracing_type: Backtracking<TracingType>,
     symmetry_trace: Vec<TraceEvent>,
+    /// The symmetry trace, when using [TraceStorage::Hashed] (in which case `symmetry_trace` is not used)
+    hashed_symmetry_trace: Option<HashedTrace>,
+    /// The rolling hash of the events on the way to the current node, and the number of
+    /// checkpoints passed, when using [TraceStorage::Hashed]
+    rolling_hash: Backtracking<QHash>,
+    checkpoints_passed: Backtracking<usize>,
     canonical_trace: Vec<TraceEvent>,
     canonical_trace_version: usize,
+    /// What is currently adding events to the trace
+    source: Backtracking<TraceSource>,
+    /// The values search has branched on to reach the current node (only the first
+    /// `branch_depth` values are valid)
+    branch_path: Vec<usize>,
+    branch_depth: Backtracking<usize>,
+    recorder: Option<TraceRecorder>,
+    /// Number of splits made by each source, including those which did not match the trace
+    split_counts: SplitCounts,
 }
 
 impl Tracer {
@@ -48,18 +150,140 @@ impl Tracer {
             pos: Backtracking::new(0),
             tracing_type: Backtracking::new(tt),
             symmetry_trace: Default::default(),
+            hashed_symmetry_trace: None,
+            rolling_hash: Backtracking::new(0),
+            checkpoints_passed: Backtracking::new(0),
             canonical_trace: Default::default(),
             canonical_trace_version: Default::default(),
+            source: Backtracking::new(TraceSource::Search),
+            branch_path: vec![],
+            branch_depth: Backtracking::new(0),
+            recorder: None,
+            split_counts: Default::default(),
+        }
+    }
+
+    /// Make a tracer which stores the symmetry trace using `storage`
+    pub fn new_with_storage(tt: TracingType, storage: TraceStorage) -> Self {
+        let mut tracer = Self::new_with_type(tt);
+        if storage == TraceStorage::Hashed {
+            assert!(
+                !tt.contains(TracingType::CANONICAL),
+                "Hashed traces cannot be used for canonical images"
+            );
+            tracer.hashed_symmetry_trace = Some(HashedTrace::default());
+        }
+        tracer
+    }
+
+    /// Clear the traces so the tracer can be used for a new search of type `tt`, keeping
+    /// the memory already allocated. The traces are stored, and recorded, as before.
+    pub fn reset(&mut self, tt: TracingType) {
+        self.pos = Backtracking::new(0);
+        self.tracing_type = Backtracking::new(tt);
+        self.symmetry_trace.clear();
+        if let Some(hashed) = &mut self.hashed_symmetry_trace {
+            assert!(
+                !tt.contains(TracingType::CANONICAL),
+                "Hashed traces cannot be used for canonical images"
+            );
+            hashed.clear();
+        }
+        self.rolling_hash = Backtracking::new(0);
+        self.checkpoints_passed = Backtracking::new(0);
+        self.canonical_trace.clear();
+        self.canonical_trace_version = 0;
+        self.source = Backtracking::new(TraceSource::Search);
+        self.branch_path.clear();
+        self.branch_depth = Backtracking::new(0);
+        if let Some(recorder) = &mut self.recorder {
+            *recorder = TraceRecorder::default();
+        }
+        self.split_counts = Default::default();
+    }
+
+    /// Start recording where each event of the trace comes from, so the trace can be dumped
+    /// with [Tracer::dump]. This should be called before any events are added.
+    pub fn start_recording(&mut self) {
+        assert!(self.symmetry_trace.is_empty() && self.canonical_trace.is_empty());
+        assert!(self.hashed_symmetry_trace.is_none(), "Hashed traces cannot be recorded");
+        self.recorder = Some(TraceRecorder::default());
+    }
+
+    /// Set what is adding events to the trace
+    pub fn set_source(&mut self, source: TraceSource) {
+        *self.source = source;
+    }
+
+    /// Record that search has branched on `val`
+    pub fn push_branch(&mut self, val: usize) {
+        self.branch_path.truncate(*self.branch_depth);
+        self.branch_path.push(val);
+        *self.branch_depth += 1;
+    }
+
+    /// The values search has branched on to reach the current node
+    pub fn branch_path(&self) -> &[usize] {
+        &self.branch_path[..*self.branch_depth]
+    }
+
+    fn event_info(&self) -> EventInfo {
+        EventInfo {
+            source: *self.source,
+            depth: *self.branch_depth,
+        }
+    }
+
+    /// Record the event at position `pos` of one of the traces
+    fn record(info: &mut Vec<EventInfo>, path: &mut Vec<usize>, pos: usize, event: EventInfo, branch_path: &[usize]) {
+        info.truncate(pos);
+        info.push(event);
+        if path[..] != *branch_path {
+            path.clear();
+            path.extend_from_slice(branch_path);
         }
     }
 
+    /// The recorded traces, using `refiners` as the names of the refiners. Returns
+    /// `None` if [Tracer::start_recording] was not called.
+    pub fn dump(&self, refiners: Vec<String>) -> Option<TraceDump> {
+        let recorder = self.recorder.as_ref()?;
+        let recorded = |events: &[TraceEvent], info: &[EventInfo], path: &[usize]| RecordedTrace {
+            path: path.to_vec(),
+            events: events
+                .iter()
+                .zip(info)
+                .map(|(&event, i)| RecordedEvent {
+                    event,
+                    source: i.source,
+                    depth: i.depth,
+                })
+                .collect(),
+        };
+        Some(TraceDump {
+            refiners,
+            symmetry: recorded(&self.symmetry_trace, &recorder.symmetry_info, &recorder.symmetry_path),
+            canonical: recorded(
+                &self.canonical_trace,
+                &recorder.canonical_info,
+                &recorder.canonical_path,
+            ),
+        })
+    }
+
     pub fn new() -> Self {
         Self::new_with_type(TracingType::BOTH)
     }
 
     /// Add new event to trace, returns an Err if search should backtrack
     pub fn add(&mut self, t: TraceEvent) -> Result<()> {
-        if self.tracing_type.contains(TracingType::SYMMETRY) {
+        if let TraceEvent::Split { .. } = t {
+            self.split_counts.add(*self.source);
+        }
+
+        if self.tracing_type.contains(TracingType::SYMMETRY) && self.hashed_symmetry_trace.is_some() {
+            self.add_hashed_symmetry(t);
+        } else if self.tracing_type.contains(TracingType::SYMMETRY) {
             if *self.pos < self.symmetry_trace.len() {
                 if self.symmetry_trace[*self.pos] != t {
                     info!(target: "tracer", "Violating Symmetry Trace: found {:?}, expected {:?}", t, self.symmetry_trace[*self.pos]);
@@ -71,6 +295,11 @@ impl Tracer {
                 info!(target: "tracer", "Adding trace event: {:?}, depth {:?}", t, *self.pos);
                 assert!(self.symmetry_trace.len() == *self.pos);
                 self.symmetry_trace.push(t);
+                let event = self.event_info();
+                if let Some(r) = &mut self.recorder {
+                    let path = &self.branch_path[..event.depth];
+                    Self::record(&mut r.symmetry_info, &mut r.symmetry_path, *self.pos, event, path);
+                }
             }
         }
 
@@ -82,6 +311,11 @@ impl Tracer {
                         self.canonical_trace.truncate(*self.pos);
                         self.canonical_trace.push(t);
                         self.canonical_trace_version += 1;
+                        let event = self.event_info();
+                        if let Some(r) = &mut self.recorder {
+                            let path = &self.branch_path[..event.depth];
+                            Self::record(&mut r.canonical_info, &mut r.canonical_path, *self.pos, event, path);
+                        }
                     }
                     Ordering::Equal => {}
                     Ordering::Greater => {
@@ -92,6 +326,11 @@ impl Tracer {
             } else {
                 assert!(self.canonical_trace.len() == *self.pos);
                 self.canonical_trace.push(t);
+                let event = self.event_info();
+                if let Some(r) = &mut self.recorder {
+                    let path = &self.branch_path[..event.depth];
+                    Self::record(&mut r.canonical_info, &mut r.canonical_path, *self.pos, event, path);
+                }
             }
         }
 
@@ -105,11 +344,75 @@ impl Tracer {
         }
     }
 
+    /// Add `t` to the symmetry trace, when using [TraceStorage::Hashed]
+    fn add_hashed_symmetry(&mut self, t: TraceEvent) {
+        let trace = self.hashed_symmetry_trace.as_mut().unwrap();
+        let pos = *self.pos;
+        let before = *self.rolling_hash;
+        let after = HashedTrace::roll(before, &t);
+        *self.rolling_hash = after;
+        let is_checkpoint = HashedTrace::is_checkpoint(&t);
+
+        let matches = if pos < trace.len {
+            // Events are only compared at checkpoints
+            !is_checkpoint || trace.checkpoints.get(*self.checkpoints_passed) == Some(&(pos, after))
+        } else {
+            assert!(trace.len == pos);
+            // The events since the last checkpoint must be the same as the rest of the trace
+            if before == trace.end_hash {
+                info!(target: "tracer", "Adding hashed trace event: {:?}, depth {:?}", t, pos);
+                trace.len += 1;
+                trace.end_hash = after;
+                if is_checkpoint {
+                    trace.checkpoints.push((pos, after));
+                }
+                true
+            } else {
+                false
+            }
+        };
+
+        if is_checkpoint {
+            *self.checkpoints_passed += 1;
+        }
+        if !matches {
+            info!(target: "tracer", "Violating hashed Symmetry Trace at {:?}", pos);
+            *self.tracing_type -= TracingType::SYMMETRY;
+        }
+    }
+
     /// The current type of the trace (SYMMETRY and CANONICAL) -- can change as search progresses
     pub fn tracing_type(&self) -> TracingType {
         *self.tracing_type
     }
 
+    /// The number of splits made by each source so far
+    pub fn split_counts(&self) -> &SplitCounts {
+        &self.split_counts
+    }
+
+    /// The current position in the trace
+    pub fn position(&self) -> usize {
+        *self.pos
+    }
+
+    /// The length of the symmetry trace
+    pub fn symmetry_trace_len(&self) -> usize {
+        match &self.hashed_symmetry_trace {
+            Some(trace) => trace.len,
+            None => self.symmetry_trace.len(),
+        }
+    }
+
+    /// A hash of the events in positions `start..end` of the symmetry trace (this needs [TraceStorage::Full])
+    pub fn symmetry_trace_hash(&self, start: usize, end: usize) -> QHash {
+        assert!(
+            self.hashed_symmetry_trace.is_none(),
+            "Hashed traces do not store individual events"
+        );
+        do_hash(&self.symmetry_trace[start..end]).0
+    }
+
     /// The version of the canonical trace -- this is incremented every time a new
     /// better canonical trace is found
     pub fn canonical_trace_version(&self) -> usize {
@@ -128,11 +431,19 @@ impl Backtrack for Tracer {
         info!(target: "tracer", "Save tracer state: {:?}", *self.pos);
         self.pos.save_state();
         self.tracing_type.save_state();
+        self.source.save_state();
+        self.branch_depth.save_state();
+        self.rolling_hash.save_state();
+        self.checkpoints_passed.save_state();
     }
 
     fn restore_state(&mut self) {
         self.pos.restore_state();
         self.tracing_type.restore_state();
+        self.source.restore_state();
+        self.branch_depth.restore_state();
+        self.rolling_hash.restore_state();
+        self.checkpoints_passed.restore_state();
         info!(target: "tracer", "Restore tracer state: {:?}", *self.pos);
     }
 
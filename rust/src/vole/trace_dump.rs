@@ -0,0 +1,222 @@
+//! Recorded traces, which can be saved to a file and compared
+//!
+//! When search misses a solution, this is usually because some refiner produced
+//! different trace events on the left and the right. Traces recorded with
+//! [Tracer::start_recording](super::trace::Tracer::start_recording) store which
+//! refiner produced each event, and where search was when it happened, so the first
+//! difference between two traces can be tracked down.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::trace::TraceEvent;
+
+/// What added an event to the trace
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub enum TraceSource {
+    /// The search itself (for example branching, or the end of a refinement)
+    Search,
+    /// The refiner with this index in the [RefinerStore](super::refiners::refiner_store::RefinerStore)
+    Refiner(usize),
+    /// Refining the partition by the digraphs added by refiners
+    Graph,
+}
+
+/// Extra information stored about a trace event while recording
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct EventInfo {
+    /// What added the event
+    pub source: TraceSource,
+    /// The number of branches search had taken when the event was added
+    pub depth: usize,
+}
+
+/// A trace event, and extra information about where it came from
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RecordedEvent {
+    pub event: TraceEvent,
+    pub source: TraceSource,
+    pub depth: usize,
+}
+
+/// A complete recorded trace
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RecordedTrace {
+    /// The values branched on by search, when the last event of the trace was added.
+    /// Event `i` was added after the first `events[i].depth` of these branches.
+    pub path: Vec<usize>,
+    pub events: Vec<RecordedEvent>,
+}
+
+/// The traces of a search, as written by `--trace-dump`
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TraceDump {
+    /// The names of the refiners used in search
+    pub refiners: Vec<String>,
+    pub symmetry: RecordedTrace,
+    pub canonical: RecordedTrace,
+}
+
+/// One side of the first difference between two traces
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct DivergentEvent {
+    /// The event, or `None` if the trace ended
+    pub event: Option<TraceEvent>,
+    /// The cell the event refined, for splits
+    pub cell: Option<usize>,
+    /// The size the cell was split at, for splits
+    pub size: Option<usize>,
+    /// The name of what added the event
+    pub source: Option<String>,
+    /// The branches taken by search before the event was added
+    pub path: Vec<usize>,
+}
+
+/// The first difference between two traces
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TraceDivergence {
+    /// The position of the first different event
+    pub position: usize,
+    pub left: DivergentEvent,
+    pub right: DivergentEvent,
+}
+
+impl TraceDump {
+    /// The name of `source`, using the names of the refiners in this dump
+    pub fn source_name(&self, source: TraceSource) -> String {
+        match source {
+            TraceSource::Search => "search".to_string(),
+            TraceSource::Graph => "graph refinement".to_string(),
+            TraceSource::Refiner(i) => match self.refiners.get(i) {
+                Some(name) => format!("refiner {}: {}", i, name),
+                None => format!("refiner {}", i),
+            },
+        }
+    }
+
+    fn divergent_event(&self, trace: &RecordedTrace, position: usize) -> DivergentEvent {
+        match trace.events.get(position) {
+            Some(e) => DivergentEvent {
+                event: Some(e.event),
+                cell: match e.event {
+                    TraceEvent::Split { cell, .. } | TraceEvent::NoSplit { cell, .. } => Some(cell),
+                    _ => None,
+                },
+                size: match e.event {
+                    TraceEvent::Split { size, .. } => Some(size),
+                    _ => None,
+                },
+                source: Some(self.source_name(e.source)),
+                path: trace.path[..e.depth.min(trace.path.len())].to_vec(),
+            },
+            None => DivergentEvent {
+                event: None,
+                cell: None,
+                size: None,
+                source: None,
+                path: trace.path.clone(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for DivergentEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.event {
+            Some(TraceEvent::Split { cell, size, reason }) => {
+                write!(f, "split of cell {} at size {} (reason {:#x})", cell, size, reason)?
+            }
+            Some(TraceEvent::NoSplit { cell, reason }) => {
+                write!(f, "no split of cell {} (reason {:#x})", cell, reason)?
+            }
+            Some(TraceEvent::Fact { reason }) => write!(f, "fact {:#x}", reason)?,
+            Some(TraceEvent::FullGraph { hash }) => write!(f, "full graph refinement (hash {:#x})", hash)?,
+            Some(TraceEvent::End()) => write!(f, "end of refining a cell")?,
+            Some(TraceEvent::EndRefine()) => write!(f, "end of refinement")?,
+            Some(TraceEvent::EndTrace()) => write!(f, "end of a branch")?,
+            None => write!(f, "end of trace")?,
+        }
+        if let Some(source) = &self.source {
+            write!(f, " from {}", source)?;
+        }
+        write!(f, ", after branching on {:?}", self.path)
+    }
+}
+
+/// Find the first position where two traces differ, or `None` if they are equal.
+/// `select` picks which trace of each dump to compare.
+pub fn diff_traces<F>(left: &TraceDump, right: &TraceDump, select: F) -> Option<TraceDivergence>
+where
+    F: Fn(&TraceDump) -> &RecordedTrace,
+{
+    let (left_trace, right_trace) = (select(left), select(right));
+    let len = left_trace.events.len().max(right_trace.events.len());
+    let position =
+        (0..len).find(|&i| left_trace.events.get(i).map(|e| e.event) != right_trace.events.get(i).map(|e| e.event))?;
+    Some(TraceDivergence {
+        position,
+        left: left.divergent_event(left_trace, position),
+        right: right.divergent_event(right_trace, position),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_traces, RecordedEvent, RecordedTrace, TraceDump, TraceSource};
+    use crate::vole::trace::TraceEvent;
+
+    fn dump(events: &[(TraceEvent, TraceSource, usize)]) -> TraceDump {
+        TraceDump {
+            refiners: vec!["SetStabilizer".to_string()],
+            symmetry: RecordedTrace {
+                path: vec![3, 5],
+                events: events
+                    .iter()
+                    .map(|&(event, source, depth)| RecordedEvent { event, source, depth })
+                    .collect(),
+            },
+            canonical: Default::default(),
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let split = |size| TraceEvent::Split {
+            cell: 0,
+            size,
+            reason: 7,
+        };
+        let left = dump(&[
+            (TraceEvent::Fact { reason: 1 }, TraceSource::Refiner(0), 0),
+            (split(2), TraceSource::Graph, 1),
+            (TraceEvent::End(), TraceSource::Graph, 1),
+        ]);
+        let mut right = left.clone();
+        assert_eq!(diff_traces(&left, &right, |d| &d.symmetry), None);
+        assert_eq!(diff_traces(&left, &right, |d| &d.canonical), None);
+
+        right.symmetry.events[1].event = split(3);
+        let div = diff_traces(&left, &right, |d| &d.symmetry).unwrap();
+        assert_eq!(div.position, 1);
+        assert_eq!(div.left.event, Some(split(2)));
+        assert_eq!(div.right.event, Some(split(3)));
+        assert_eq!((div.left.cell, div.left.size), (Some(0), Some(2)));
+        assert_eq!((div.right.cell, div.right.size), (Some(0), Some(3)));
+        assert_eq!(div.left.source.as_deref(), Some("graph refinement"));
+        assert_eq!(div.left.path, vec![3]);
+        assert_eq!(
+            div.right.to_string(),
+            "split of cell 0 at size 3 (reason 0x7) from graph refinement, after branching on [3]"
+        );
+
+        right.symmetry.events.truncate(1);
+        let div = diff_traces(&left, &right, |d| &d.symmetry).unwrap();
+        assert_eq!(div.position, 1);
+        assert_eq!(div.right.event, None);
+        assert_eq!((div.right.cell, div.right.size), (None, None));
+        assert_eq!(div.right.to_string(), "end of trace, after branching on [3, 5]");
+
+        assert_eq!(left.source_name(TraceSource::Refiner(0)), "refiner 0: SetStabilizer");
+    }
+}
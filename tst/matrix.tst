@@ -0,0 +1,88 @@
+#@local m, n, x, r, G, H, c, i, RowsColumns, RandomMatrix
+gap> START_TEST("matrix.tst");
+gap> LoadPackage("vole", false);
+true
+
+# The group which permutes the rows and columns of an r x c matrix
+gap> RowsColumns := {r, c} -> Group(Concatenation(
+>        GeneratorsOfGroup(SymmetricGroup([1 .. r])),
+>        GeneratorsOfGroup(SymmetricGroup([r + 1 .. r + c]))), ());;
+gap> RandomMatrix := {r, c} -> List([1 .. r], i -> List([1 .. c], j -> Random([0 .. 2])));;
+
+# Action
+gap> OnMatrixRowsColumns([[1, 2, 3], [4, 5, 6]], (1,2)(3,4));
+[ [ 5, 4, 6 ], [ 2, 1, 3 ] ]
+gap> OnMatrixRowsColumns([[1, 2, 3], [4, 5, 6]], ());
+[ [ 1, 2, 3 ], [ 4, 5, 6 ] ]
+gap> OnMatrixRowsColumns([], ());
+[  ]
+gap> OnMatrixRowsColumns([[1, 2], [3, 4]], (2,3));
+Error, OnMatrixRowsColumns: <p> must map rows to rows, and columns to columns
+
+# Matrix stabilisers and transporters
+gap> VoleFind.Group(VoleRefiner.MatrixStab([[1, 1], [2, 2]])) = Group([(3,4)]);
+true
+gap> VoleFind.Group(4, Constraint.Stabilise([[1, 2], [2, 1]], OnMatrixRowsColumns))
+> = Group([(1,2)(3,4)]);
+true
+gap> VoleRefiner.FromConstraint(Constraint.Stabilise([[1, 2]], OnMatrixRowsColumns));
+<Vole refiner: MatrixStab of [ [ 1, 2 ] ]>
+gap> VoleFind.Group(VoleRefiner.MatrixStab([[true, "a"], ["a", true]])) = Group([(1,2)(3,4)]);
+true
+gap> x := VoleFind.Rep(VoleRefiner.MatrixTransporter([[1, 2], [3, 4]], [[4, 3], [2, 1]]));
+(1,2)(3,4)
+gap> VoleFind.Rep(VoleRefiner.MatrixTransporter([[1, 2], [3, 4]], [[1, 2], [4, 3]]));
+fail
+gap> for i in [1 .. 50] do
+>   r := Random([1 .. 4]);
+>   c := Random([1 .. 4]);
+>   m := RandomMatrix(r, c);
+>   H := RowsColumns(r, c);
+>   G := VoleFind.Group(r + c, VoleRefiner.MatrixStab(m));
+>   if G <> Stabilizer(H, m, OnMatrixRowsColumns) then
+>     Print("Wrong stabiliser: ", m, "\n");
+>   fi;
+>   n := OnMatrixRowsColumns(m, Random(H));
+>   x := VoleFind.Rep(r + c, VoleRefiner.MatrixTransporter(m, n));
+>   if x = fail or OnMatrixRowsColumns(m, x) <> n then
+>     Print("Wrong transporter: ", m, " ", n, "\n");
+>   fi;
+> od;
+
+# Solutions are split into row and column permutations
+gap> r := VoleFind.Rep(VoleRefiner.MatrixTransporter([[1, 2, 3], [4, 5, 6]],
+>                                                    [[5, 4, 6], [2, 1, 3]]) : raw);;
+gap> Length(r.matrix_sols);
+1
+gap> r.matrix_sols[1].constraint;
+1
+gap> r.matrix_sols[1].sols;
+[ [ (1,2), (1,2) ] ]
+gap> r := VoleFind.Group(VoleRefiner.SetStab([1]),
+>                        VoleRefiner.MatrixStab([[1, 1, 2], [1, 1, 2]]) : raw);;
+gap> r.matrix_sols[1].constraint;
+2
+gap> ForAll([1 .. Length(r.sols)], i -> r.matrix_sols[1].sols[i] =
+>        [RestrictedPerm(r.sols[i], [1, 2]),
+>         PermList(OnTuples([3 .. 5], r.sols[i]) - 2)]);
+true
+
+# Canonical images
+gap> for i in [1 .. 20] do
+>   r := Random([1 .. 4]);
+>   c := Random([1 .. 4]);
+>   m := RandomMatrix(r, c);
+>   H := RowsColumns(r, c);
+>   n := OnMatrixRowsColumns(m, Random(H));
+>   x := VoleFind.Canonical(H, VoleRefiner.MatrixStab(m) : raw);
+>   if x.matrix_sols[1].constraint <> 1 or not IsBound(x.matrix_sols[1].canonical) then
+>     Print("Missing canonical permutations: ", m, "\n");
+>   fi;
+>   if OnMatrixRowsColumns(m, VoleFind.CanonicalPerm(H, VoleRefiner.MatrixStab(m)))
+>      <> OnMatrixRowsColumns(n, VoleFind.CanonicalPerm(H, VoleRefiner.MatrixStab(n))) then
+>     Print("Wrong canonical image: ", m, " ", n, "\n");
+>   fi;
+> od;
+
+#
+gap> STOP_TEST("matrix.tst");
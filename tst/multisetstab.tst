@@ -0,0 +1,62 @@
+#@local m, n, s, t, x, G, i
+gap> START_TEST("multisetstab.tst");
+gap> LoadPackage("vole", false);
+true
+
+# Actions
+gap> OnMultisets([3, 1, 3, 2], (1,2,3));
+[ 1, 1, 2, 3 ]
+gap> OnSetsMultisets([[1, 1, 2], [2, 3]], (1,2));
+[ [ 1, 2, 2 ], [ 1, 3 ] ]
+
+# Multiset stabilisers and transporters
+gap> VoleFind.Group(5, VoleRefiner.MultisetStab([1, 2, 1, 2, 3])) = Group([(1,2), (4,5)]);
+true
+gap> VoleFind.Group(5, Constraint.Stabilise([1, 1, 2, 2, 3], OnMultisets)) = Group([(1,2), (4,5)]);
+true
+gap> VoleRefiner.FromConstraint(Constraint.Stabilise([1, 1, 2], OnMultisets));
+<Vole refiner: MultisetStab of [ 1, 1, 2 ]>
+gap> x := VoleFind.Rep(VoleRefiner.MultisetTransporter([1, 1, 2], [2, 3, 3]));;
+gap> OnMultisets([1, 1, 2], x);
+[ 2, 3, 3 ]
+gap> VoleFind.Rep(4, Constraint.Transport([1, 1, 2], [2, 2, 3, 3], OnMultisets));
+fail
+gap> VoleFind.Rep(4, Constraint.Transport([1, 1, 2], [1, 2, 3], OnMultisets));
+fail
+gap> for i in [1 .. 50] do
+>   m := SortedList(List([1 .. Random([0 .. 8])], i -> Random([1 .. 6])));
+>   G := VoleFind.Group(6, Constraint.Stabilise(m, OnMultisets));
+>   if G <> Stabilizer(SymmetricGroup(6), m, OnMultisets) then
+>     Print("Wrong stabiliser: ", m, "\n");
+>   fi;
+>   n := OnMultisets(m, Random(SymmetricGroup(6)));
+>   x := VoleFind.Rep(6, Constraint.Transport(m, n, OnMultisets));
+>   if x = fail or OnMultisets(m, x) <> n then
+>     Print("Wrong transporter: ", m, " ", n, "\n");
+>   fi;
+> od;
+
+# Sets of multisets
+gap> VoleFind.Group(3, VoleRefiner.SetMultisetStab([[1, 1, 2], [2, 2, 1]])) = Group([(1,2)]);
+true
+gap> VoleFind.Group(4, Constraint.Stabilise([[1, 1, 2], [3, 3, 4]], OnSetsMultisets))
+> = Group([(1,3)(2,4)]);
+true
+gap> VoleFind.Rep(4, VoleRefiner.SetMultisetTransporter([[1, 1, 2]], [[1, 2, 2], [3]]));
+fail
+gap> for i in [1 .. 50] do
+>   s := Set([1 .. Random([0 .. 4])],
+>            j -> SortedList(List([1 .. Random([1 .. 4])], k -> Random([1 .. 5]))));
+>   G := VoleFind.Group(5, Constraint.Stabilise(s, OnSetsMultisets));
+>   if G <> Stabilizer(SymmetricGroup(5), s, OnSetsMultisets) then
+>     Print("Wrong stabiliser: ", s, "\n");
+>   fi;
+>   t := OnSetsMultisets(s, Random(SymmetricGroup(5)));
+>   x := VoleFind.Rep(5, Constraint.Transport(s, t, OnSetsMultisets));
+>   if x = fail or OnSetsMultisets(s, x) <> t then
+>     Print("Wrong transporter: ", s, " ", t, "\n");
+>   fi;
+> od;
+
+#
+gap> STOP_TEST("multisetstab.tst");
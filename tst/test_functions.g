@@ -95,6 +95,10 @@ function(p, l)
             g := Stabilizer(g, c.SetSetStab.points, OnSetsSets);
         elif IsBound(c.SetTupleStab) then
             g := Stabilizer(g, c.SetTupleStab.points, OnSetsTuples);
+        elif IsBound(c.MultisetStab) then
+            g := Stabilizer(g, SortedList(c.MultisetStab.points), OnMultisets);
+        elif IsBound(c.SetMultisetStab) then
+            g := Stabilizer(g, Set(c.SetMultisetStab.points, SortedList), OnSetsMultisets);
         elif IsBound(c.DigraphStab) then
             if MovedPoints(g) = [1 .. p] and IsNaturalSymmetricGroup(g) then
                 g := AutomorphismGroup(Digraph(c.DigraphStab.edges));
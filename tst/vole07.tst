@@ -14,8 +14,10 @@ gap> START_TEST("vole07.tst");
 gap> LoadPackage("vole", false);;
 gap> Set(RecNames(VoleRefiner));
 [ "DigraphStab", "DigraphTransporter", "FromConstraint", "InSymmetricGroup", 
-  "SetSetStab", "SetSetTransporter", "SetStab", "SetTransporter", 
-  "SetTupleStab", "SetTupleTransporter", "TupleStab", "TupleTransporter" ]
+  "MatrixStab", "MatrixTransporter", "MultisetStab", "MultisetTransporter", 
+  "SetMultisetStab", "SetMultisetTransporter", "SetSetStab", 
+  "SetSetTransporter", "SetStab", "SetTransporter", "SetTupleStab", 
+  "SetTupleTransporter", "TupleStab", "TupleTransporter" ]
 
 # doc/_Chapter_Refiners.xml:54-57
 gap> true;